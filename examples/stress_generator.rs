@@ -165,14 +165,14 @@ impl Config {
 fn generate_corrupted_line(rng: &mut Lcg, tx_id: u32) -> String {
     let corruption_type = rng.next_range(8);
     match corruption_type {
-        0 => format!("transfer,1,{},100.0", tx_id), // Invalid tx type
-        1 => format!("credit,1,{},50.0", tx_id),    // Invalid tx type
+        0 => format!("refund,1,{},100.0", tx_id), // Invalid tx type
+        1 => format!("credit,1,{},50.0", tx_id),  // Invalid tx type
         2 => format!("deposit,99999,{},100.0", tx_id), // Client ID overflow (>65535)
         3 => "deposit,1,9999999999,100.0".to_string(), // TX ID overflow (>u32::MAX)
-        4 => format!("deposit,1,{},-50.0", tx_id),  // Negative amount
-        5 => format!("deposit,1,{},", tx_id),       // Missing amount
+        4 => format!("deposit,1,{},-50.0", tx_id), // Negative amount
+        5 => format!("deposit,1,{},", tx_id),     // Missing amount
         6 => format!("deposit,abc,{},100.0", tx_id), // Non-numeric client
-        7 => "deposit,1,xyz,100.0".to_string(),     // Non-numeric tx_id
+        7 => "deposit,1,xyz,100.0".to_string(),   // Non-numeric tx_id
         _ => "invalid,line,data".to_string(),
     }
 }
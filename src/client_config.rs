@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use crate::domain::types::{Amount, ClientId};
+use crate::domain::Ledger;
+
+/// Per-client settings, read from a CSV file with the columns `client,overdraft_limit`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientConfig {
+    pub overdraft_limits: BTreeMap<ClientId, Amount>,
+}
+
+impl ClientConfig {
+    /// Sets every configured limit on `ledger`.
+    pub fn apply(&self, ledger: &mut Ledger) {
+        for (client_id, limit) in &self.overdraft_limits {
+            // Limits are checked to be non-negative when read
+            let _ = ledger.set_overdraft_limit(*client_id, *limit);
+        }
+    }
+}
+
+/// Parses a client configuration, failing on the first invalid row; when a client
+/// appears more than once, its last row wins.
+pub fn read_client_config<R: Read>(reader: R) -> Result<ClientConfig, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let headers = reader.headers().map_err(|e| e.to_string())?.clone();
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|h| h.eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("Missing required column: '{}'", name))
+    };
+    let (client_idx, limit_idx) = (column("client")?, column("overdraft_limit")?);
+
+    let mut config = ClientConfig::default();
+    for record in reader.records() {
+        let record = record.map_err(|e| e.to_string())?;
+        let line = record.position().map_or(0, |position| position.line());
+        let client = record.get(client_idx).unwrap_or("");
+        let client = client
+            .parse()
+            .map(ClientId)
+            .map_err(|_| format!("Line {}: Invalid client ID: '{}'", line, client))?;
+        let limit = record.get(limit_idx).unwrap_or("");
+        let limit = Amount::from_str_rounded(limit)
+            .ok()
+            .filter(|limit| !limit.is_negative())
+            .ok_or_else(|| format!("Line {}: Invalid overdraft limit: '{}'", line, limit))?;
+        config.overdraft_limits.insert(client, limit);
+    }
+    Ok(config)
+}
+
+/// Loads a client configuration file from disk.
+pub fn load_client_config(path: &Path) -> Result<ClientConfig, String> {
+    let file =
+        File::open(path).map_err(|e| format!("Failed to open '{}': {}", path.display(), e))?;
+    read_client_config(BufReader::new(file))
+        .map_err(|e| format!("Failed to load '{}': {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::types::{TransactionId, TransactionType};
+    use std::io::Cursor;
+
+    fn amount(s: &str) -> Amount {
+        Amount::from_str_rounded(s).expect("failed to parse amount")
+    }
+
+    #[test]
+    fn test_read_client_config() {
+        let input = "overdraft_limit, client\n100, 1\n 0.12346 ,2\n50,1\n";
+        let config = read_client_config(Cursor::new(input)).expect("config should parse");
+        assert_eq!(
+            config.overdraft_limits,
+            BTreeMap::from([(ClientId(1), amount("50")), (ClientId(2), amount("0.1235"))])
+        );
+    }
+
+    #[test]
+    fn test_read_client_config_rejects_invalid_rows() {
+        let error = read_client_config(Cursor::new("client\n1\n")).unwrap_err();
+        assert_eq!(error, "Missing required column: 'overdraft_limit'");
+
+        let error =
+            read_client_config(Cursor::new("client,overdraft_limit\n1,10\n2,-5\n")).unwrap_err();
+        assert_eq!(error, "Line 3: Invalid overdraft limit: '-5'");
+
+        let error =
+            read_client_config(Cursor::new("client,overdraft_limit\nabc,10\n")).unwrap_err();
+        assert_eq!(error, "Line 2: Invalid client ID: 'abc'");
+    }
+
+    #[test]
+    fn test_apply_sets_limits() {
+        let config = read_client_config(Cursor::new("client,overdraft_limit\n1,100\n2,0\n"))
+            .expect("config should parse");
+        let mut ledger = Ledger::new();
+        config.apply(&mut ledger);
+
+        // A zero limit is the default, so it does not open an account
+        assert!(ledger.get_account(ClientId(2)).is_none());
+        assert_eq!(
+            ledger.process(
+                TransactionType::Withdrawal,
+                ClientId(1),
+                TransactionId(1),
+                Some(amount("60"))
+            ),
+            Ok(crate::domain::ProcessOutcome::Withdrawn(amount("60")))
+        );
+        let account = ledger
+            .get_account(ClientId(1))
+            .expect("client 1 account should exist");
+        assert_eq!(account.available, amount("-60"));
+        assert_eq!(account.headroom(None), amount("40"));
+    }
+}
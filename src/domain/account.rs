@@ -1,10 +1,41 @@
-use crate::domain::types::Amount;
+use std::collections::BTreeMap;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+use serde::{Deserialize, Serialize};
+
+use crate::domain::rejection::RejectionReason;
+use crate::domain::types::{Amount, Currency, LockReason};
+
+/// Funds of an account in a single currency
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Balance {
+    pub available: Amount,
+    pub held: Amount,
+}
+
+impl Balance {
+    pub fn total(&self) -> Amount {
+        self.available + self.held
+    }
+}
+
+/// Client account; `available` and `held` are in the default currency.
+///
+/// Every method taking a `currency` works on the default balance for `None`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Account {
     pub available: Amount,
     pub held: Amount,
     pub locked: bool,
+    /// Set whenever `locked` is, absent in snapshots written before lock reasons existed.
+    #[serde(default)]
+    pub lock_reason: Option<LockReason>,
+    /// Balances in any other currency, locked together with the default one.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub currencies: BTreeMap<Currency, Balance>,
+    /// How far `available` may go below zero through withdrawals and transfers.
+    /// Only the default currency has a credit line.
+    #[serde(default, skip_serializing_if = "Amount::is_zero")]
+    pub overdraft_limit: Amount,
 }
 
 impl Default for Account {
@@ -19,6 +50,9 @@ impl Account {
             available: Amount::ZERO,
             held: Amount::ZERO,
             locked: false,
+            lock_reason: None,
+            currencies: BTreeMap::new(),
+            overdraft_limit: Amount::ZERO,
         }
     }
 
@@ -26,38 +60,128 @@ impl Account {
         self.available + self.held
     }
 
-    pub fn deposit(&mut self, amount: Amount) -> bool {
+    pub fn balance(&self, currency: Option<Currency>) -> Balance {
+        match currency {
+            None => Balance {
+                available: self.available,
+                held: self.held,
+            },
+            Some(currency) => self.currencies.get(&currency).copied().unwrap_or_default(),
+        }
+    }
+
+    /// Overdraft limit of `currency`'s balance.
+    pub fn overdraft_limit(&self, currency: Option<Currency>) -> Amount {
+        match currency {
+            None => self.overdraft_limit,
+            Some(_) => Amount::ZERO,
+        }
+    }
+
+    /// Amount that can be withdrawn from `currency` right now, overdraft included.
+    pub fn headroom(&self, currency: Option<Currency>) -> Amount {
+        if self.locked {
+            return Amount::ZERO;
+        }
+        let headroom = self.balance(currency).available + self.overdraft_limit(currency);
+        headroom.max(Amount::ZERO)
+    }
+
+    /// Available and held amounts of `currency`, creating the balance on first use.
+    fn funds_mut(&mut self, currency: Option<Currency>) -> (&mut Amount, &mut Amount) {
+        match currency {
+            None => (&mut self.available, &mut self.held),
+            Some(currency) => {
+                let balance = self.currencies.entry(currency).or_default();
+                (&mut balance.available, &mut balance.held)
+            }
+        }
+    }
+
+    pub fn deposit(
+        &mut self,
+        currency: Option<Currency>,
+        amount: Amount,
+    ) -> Result<(), RejectionReason> {
         if self.locked {
-            return false;
+            return Err(RejectionReason::AccountLocked);
         }
-        self.available += amount;
-        true
+        *self.funds_mut(currency).0 += amount;
+        Ok(())
     }
 
-    pub fn withdraw(&mut self, amount: Amount) -> bool {
+    pub fn withdraw(
+        &mut self,
+        currency: Option<Currency>,
+        amount: Amount,
+    ) -> Result<(), RejectionReason> {
         if self.locked {
-            return false;
+            return Err(RejectionReason::AccountLocked);
+        }
+        let available = self.balance(currency).available;
+        let overdraft_limit = self.overdraft_limit(currency);
+        if available + overdraft_limit < amount {
+            return Err(RejectionReason::InsufficientFunds {
+                available,
+                requested: amount,
+                overdraft_limit,
+            });
         }
-        if self.available < amount {
-            return false;
+        *self.funds_mut(currency).0 -= amount;
+        Ok(())
+    }
+
+    pub fn hold(&mut self, currency: Option<Currency>, amount: Amount) {
+        let (available, held) = self.funds_mut(currency);
+        *available -= amount;
+        *held += amount;
+    }
+
+    /// Moves held funds back to available, also crediting a charged back withdrawal.
+    pub fn release(&mut self, currency: Option<Currency>, amount: Amount) {
+        let (available, held) = self.funds_mut(currency);
+        *held -= amount;
+        *available += amount;
+    }
+
+    pub fn chargeback(&mut self, currency: Option<Currency>, amount: Amount) {
+        *self.funds_mut(currency).1 -= amount;
+        self.lock(LockReason::CHARGEBACK);
+    }
+
+    /// Locks the account; an already locked account keeps its original reason.
+    pub fn lock(&mut self, reason: LockReason) {
+        if !self.locked {
+            self.locked = true;
+            self.lock_reason = Some(reason);
         }
-        self.available -= amount;
-        true
     }
 
-    pub fn hold(&mut self, amount: Amount) {
-        self.available -= amount;
-        self.held += amount;
+    pub fn unlock(&mut self) {
+        self.locked = false;
+        self.lock_reason = None;
+    }
+
+    /// Holds a disputed withdrawal as a provisional credit until it is settled.
+    pub fn hold_refund(&mut self, currency: Option<Currency>, amount: Amount) {
+        *self.funds_mut(currency).1 += amount;
+    }
+
+    /// Drops held funds without crediting them: the provisional credit of a resolved
+    /// withdrawal dispute, or the hold on a transfer charged back to its sender.
+    pub fn drop_hold(&mut self, currency: Option<Currency>, amount: Amount) {
+        *self.funds_mut(currency).1 -= amount;
     }
 
-    pub fn release(&mut self, amount: Amount) {
-        self.held -= amount;
-        self.available += amount;
+    /// Credits funds returned by a charged back transfer; allowed on locked accounts.
+    pub fn credit(&mut self, currency: Option<Currency>, amount: Amount) {
+        *self.funds_mut(currency).0 += amount;
     }
 
-    pub fn chargeback(&mut self, amount: Amount) {
-        self.held -= amount;
-        self.locked = true;
+    /// Debits a fee of an accepted transaction; allowed on locked accounts and beyond
+    /// the overdraft limit.
+    pub fn charge(&mut self, currency: Option<Currency>, amount: Amount) {
+        *self.funds_mut(currency).0 -= amount;
     }
 }
 
@@ -72,7 +196,7 @@ mod tests {
     #[test]
     fn test_deposit_increases_available_and_total() {
         let mut account = Account::new();
-        assert!(account.deposit(amount("100")));
+        assert!(account.deposit(None, amount("100")).is_ok());
         assert_eq!(account.available, amount("100"));
         assert_eq!(account.total(), amount("100"));
     }
@@ -80,8 +204,10 @@ mod tests {
     #[test]
     fn test_withdrawal_decreases_available_and_total() {
         let mut account = Account::new();
-        account.deposit(amount("100"));
-        assert!(account.withdraw(amount("30")));
+        account
+            .deposit(None, amount("100"))
+            .expect("deposit should succeed");
+        assert!(account.withdraw(None, amount("30")).is_ok());
         assert_eq!(account.available, amount("70"));
         assert_eq!(account.total(), amount("70"));
     }
@@ -89,24 +215,37 @@ mod tests {
     #[test]
     fn test_withdrawal_fails_insufficient_funds() {
         let mut account = Account::new();
-        account.deposit(amount("50"));
-        assert!(!account.withdraw(amount("100")));
+        account
+            .deposit(None, amount("50"))
+            .expect("deposit should succeed");
+        assert_eq!(
+            account.withdraw(None, amount("100")),
+            Err(RejectionReason::InsufficientFunds {
+                available: amount("50"),
+                requested: amount("100"),
+                overdraft_limit: Amount::ZERO,
+            })
+        );
         assert_eq!(account.available, amount("50"));
     }
 
     #[test]
     fn test_withdrawal_exact_amount() {
         let mut account = Account::new();
-        account.deposit(amount("50"));
-        assert!(account.withdraw(amount("50")));
+        account
+            .deposit(None, amount("50"))
+            .expect("deposit should succeed");
+        assert!(account.withdraw(None, amount("50")).is_ok());
         assert_eq!(account.available, amount("0"));
     }
 
     #[test]
     fn test_hold_moves_available_to_held() {
         let mut account = Account::new();
-        account.deposit(amount("100"));
-        account.hold(amount("40"));
+        account
+            .deposit(None, amount("100"))
+            .expect("deposit should succeed");
+        account.hold(None, amount("40"));
         assert_eq!(account.available, amount("60"));
         assert_eq!(account.held, amount("40"));
         assert_eq!(account.total(), amount("100"));
@@ -115,9 +254,11 @@ mod tests {
     #[test]
     fn test_release_moves_held_to_available() {
         let mut account = Account::new();
-        account.deposit(amount("100"));
-        account.hold(amount("40"));
-        account.release(amount("40"));
+        account
+            .deposit(None, amount("100"))
+            .expect("deposit should succeed");
+        account.hold(None, amount("40"));
+        account.release(None, amount("40"));
         assert_eq!(account.available, amount("100"));
         assert_eq!(account.held, amount("0"));
     }
@@ -125,25 +266,111 @@ mod tests {
     #[test]
     fn test_chargeback_reduces_held_and_total_and_locks() {
         let mut account = Account::new();
-        account.deposit(amount("100"));
-        account.hold(amount("100"));
-        account.chargeback(amount("100"));
+        account
+            .deposit(None, amount("100"))
+            .expect("deposit should succeed");
+        account.hold(None, amount("100"));
+        account.chargeback(None, amount("100"));
         assert_eq!(account.available, amount("0"));
         assert_eq!(account.held, amount("0"));
         assert_eq!(account.total(), amount("0"));
         assert!(account.locked);
+        assert_eq!(account.lock_reason, Some(LockReason::CHARGEBACK));
+    }
+
+    #[test]
+    fn test_lock_keeps_first_reason() {
+        let mut account = Account::new();
+        account.lock(LockReason::ADMINISTRATIVE);
+        account.chargeback(None, amount("0"));
+        assert_eq!(account.lock_reason, Some(LockReason::ADMINISTRATIVE));
+
+        account.unlock();
+        assert!(!account.locked);
+        assert_eq!(account.lock_reason, None);
+        assert!(account.deposit(None, amount("1")).is_ok());
+    }
+
+    #[test]
+    fn test_withdrawal_within_overdraft_limit() {
+        let mut account = Account::new();
+        account.overdraft_limit = amount("50");
+        account
+            .deposit(None, amount("10"))
+            .expect("deposit should succeed");
+        assert!(account.withdraw(None, amount("40")).is_ok());
+        assert_eq!(account.available, amount("-30"));
+        assert_eq!(account.headroom(None), amount("20"));
+        assert_eq!(
+            account.withdraw(None, amount("20.0001")),
+            Err(RejectionReason::InsufficientFunds {
+                available: amount("-30"),
+                requested: amount("20.0001"),
+                overdraft_limit: amount("50"),
+            })
+        );
+        assert!(account.withdraw(None, amount("20")).is_ok());
+        assert_eq!(account.headroom(None), amount("0"));
+
+        // Other currencies have no credit line
+        let eur = Some("EUR".parse().expect("failed to parse currency"));
+        assert!(account.withdraw(eur, amount("1")).is_err());
+        account.lock(LockReason::ADMINISTRATIVE);
+        account.overdraft_limit = amount("500");
+        assert_eq!(account.headroom(None), amount("0"));
+    }
+
+    #[test]
+    fn test_currencies_are_separate_balances() {
+        let eur = Some("EUR".parse().expect("failed to parse currency"));
+        let mut account = Account::new();
+        account
+            .deposit(None, amount("10"))
+            .expect("deposit should succeed");
+        account
+            .deposit(eur, amount("5"))
+            .expect("deposit should succeed");
+        assert_eq!(
+            account.withdraw(eur, amount("6")),
+            Err(RejectionReason::InsufficientFunds {
+                available: amount("5"),
+                requested: amount("6"),
+                overdraft_limit: Amount::ZERO,
+            })
+        );
+        account.hold(eur, amount("2"));
+        assert_eq!(account.available, amount("10"));
+        assert_eq!(account.balance(eur).available, amount("3"));
+        assert_eq!(account.balance(eur).total(), amount("5"));
+
+        account.chargeback(eur, amount("2"));
+        assert!(account.locked);
+        assert_eq!(
+            account.deposit(None, amount("1")),
+            Err(RejectionReason::AccountLocked)
+        );
+    }
+
+    #[test]
+    fn test_rejected_withdrawal_creates_no_balance() {
+        let mut account = Account::new();
+        let usd = Some("USD".parse().expect("failed to parse currency"));
+        assert!(account.withdraw(usd, amount("1")).is_err());
+        assert!(account.currencies.is_empty());
     }
 
     #[test]
     fn test_invariant_total_equals_available_plus_held() {
         let mut account = Account::new();
-        account.deposit(amount("100"));
+        account
+            .deposit(None, amount("100"))
+            .expect("deposit should succeed");
         assert_eq!(account.total(), account.available + account.held);
 
-        account.hold(amount("30"));
+        account.hold(None, amount("30"));
         assert_eq!(account.total(), account.available + account.held);
 
-        account.release(amount("10"));
+        account.release(None, amount("10"));
         assert_eq!(account.total(), account.available + account.held);
     }
 
@@ -151,31 +378,72 @@ mod tests {
     fn test_locked_account_blocks_deposit() {
         let mut account = Account::new();
         account.locked = true;
-        assert!(!account.deposit(amount("100")));
+        assert_eq!(
+            account.deposit(None, amount("100")),
+            Err(RejectionReason::AccountLocked)
+        );
         assert_eq!(account.available, amount("0"));
     }
 
     #[test]
     fn test_locked_account_blocks_withdrawal() {
         let mut account = Account::new();
-        account.deposit(amount("100"));
+        account
+            .deposit(None, amount("100"))
+            .expect("deposit should succeed");
         account.locked = true;
-        assert!(!account.withdraw(amount("50")));
+        assert_eq!(
+            account.withdraw(None, amount("50")),
+            Err(RejectionReason::AccountLocked)
+        );
         assert_eq!(account.available, amount("100"));
     }
 
     #[test]
     fn test_negative_balance_from_chargeback() {
         let mut account = Account::new();
-        account.deposit(amount("100"));
-        account.withdraw(amount("80"));
-        account.hold(amount("100"));
+        account
+            .deposit(None, amount("100"))
+            .expect("deposit should succeed");
+        account
+            .withdraw(None, amount("80"))
+            .expect("withdrawal should succeed");
+        account.hold(None, amount("100"));
         assert_eq!(account.available, amount("-80"));
         assert_eq!(account.held, amount("100"));
-        account.chargeback(amount("100"));
+        account.chargeback(None, amount("100"));
         assert_eq!(account.available, amount("-80"));
         assert_eq!(account.held, amount("0"));
         assert_eq!(account.total(), amount("-80"));
         assert!(account.locked);
     }
+
+    #[test]
+    fn test_withdrawal_dispute_holds_provisional_credit() {
+        let mut account = Account::new();
+        account
+            .deposit(None, amount("100"))
+            .expect("deposit should succeed");
+        account
+            .withdraw(None, amount("40"))
+            .expect("withdrawal should succeed");
+        account.hold_refund(None, amount("40"));
+        assert_eq!(account.available, amount("60"));
+        assert_eq!(account.held, amount("40"));
+        assert_eq!(account.total(), amount("100"));
+
+        account.drop_hold(None, amount("40"));
+        assert_eq!(account.available, amount("60"));
+        assert_eq!(account.held, amount("0"));
+    }
+
+    #[test]
+    fn test_release_refunds_withdrawal_without_locking() {
+        let mut account = Account::new();
+        account.hold_refund(None, amount("25"));
+        account.release(None, amount("25"));
+        assert_eq!(account.available, amount("25"));
+        assert_eq!(account.held, amount("0"));
+        assert!(!account.locked);
+    }
 }
@@ -0,0 +1,328 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+
+use crate::domain::account::Account;
+use crate::domain::books::BooksError;
+use crate::domain::ledger::StoredTransaction;
+use crate::domain::types::{
+    currency_name, Amount, ClientId, Currency, TransactionId, TransactionKind, TransactionState,
+};
+
+/// A broken ledger invariant found by [`Ledger::audit`](crate::domain::Ledger::audit)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    NegativeHeld {
+        client: ClientId,
+        currency: Option<Currency>,
+        held: Amount,
+    },
+    /// A client's held funds differ from the sum of its open disputes.
+    HeldMismatch {
+        client: ClientId,
+        currency: Option<Currency>,
+        held: Amount,
+        disputed: Amount,
+    },
+    /// Disputed and settled amounts that are negative or exceed the transaction.
+    DisputeAmounts {
+        tx: TransactionId,
+        amount: Amount,
+        disputed: Amount,
+        settled: Amount,
+    },
+    /// A charged back transaction under dispute again.
+    ChargedBackDisputed {
+        tx: TransactionId,
+        disputed: Amount,
+    },
+    /// A state that does not match the disputed and settled amounts.
+    StateMismatch {
+        tx: TransactionId,
+        state: TransactionState,
+        disputed: Amount,
+        settled: Amount,
+    },
+    Books(BooksError),
+}
+
+impl Violation {
+    /// Stable machine-readable name for reports.
+    pub fn category(&self) -> &'static str {
+        match self {
+            Violation::NegativeHeld { .. } => "negative_held",
+            Violation::HeldMismatch { .. } => "held_mismatch",
+            Violation::DisputeAmounts { .. } => "dispute_amounts",
+            Violation::ChargedBackDisputed { .. } => "charged_back_disputed",
+            Violation::StateMismatch { .. } => "state_mismatch",
+            Violation::Books(_) => "books",
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Violation::NegativeHeld {
+                client,
+                currency,
+                held,
+            } => write!(
+                f,
+                "client {} holds {} in {}",
+                client,
+                held,
+                currency_name(&currency)
+            ),
+            Violation::HeldMismatch {
+                client,
+                currency,
+                held,
+                disputed,
+            } => write!(
+                f,
+                "client {} holds {} in {}, but its open disputes hold {}",
+                client,
+                held,
+                currency_name(&currency),
+                disputed
+            ),
+            Violation::DisputeAmounts {
+                tx,
+                amount,
+                disputed,
+                settled,
+            } => write!(
+                f,
+                "transaction {} of {} has {} disputed and {} settled",
+                tx, amount, disputed, settled
+            ),
+            Violation::ChargedBackDisputed { tx, disputed } => write!(
+                f,
+                "transaction {} was charged back but has {} disputed",
+                tx, disputed
+            ),
+            Violation::StateMismatch {
+                tx,
+                state,
+                disputed,
+                settled,
+            } => write!(
+                f,
+                "transaction {} is {} with {} disputed and {} settled",
+                tx, state, disputed, settled
+            ),
+            Violation::Books(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+/// Checks every stored transaction on its own, then the held funds of every client
+/// against the open disputes holding them; violations of clients come first.
+pub(crate) fn check_disputes(
+    accounts: &BTreeMap<ClientId, Account>,
+    transactions: impl Iterator<Item = io::Result<(TransactionId, StoredTransaction)>>,
+) -> io::Result<Vec<Violation>> {
+    let mut disputed: BTreeMap<(ClientId, Option<Currency>), Amount> = BTreeMap::new();
+    let mut transaction_violations = Vec::new();
+    for entry in transactions {
+        let (tx, stored) = entry?;
+        if let Some(violation) = check_transaction(tx, &stored) {
+            transaction_violations.push((tx, violation));
+        }
+        if !stored.disputed.is_zero() {
+            // Funds of a disputed transfer are held at its destination
+            let holder = match stored.kind {
+                TransactionKind::Transfer => stored.destination.unwrap_or(stored.client_id),
+                TransactionKind::Deposit | TransactionKind::Withdrawal => stored.client_id,
+            };
+            *disputed.entry((holder, stored.currency)).or_default() += stored.disputed;
+        }
+    }
+
+    let mut violations = Vec::new();
+    for (&client, account) in accounts {
+        let currencies = account.currencies.keys().copied().map(Some);
+        for currency in std::iter::once(None).chain(currencies) {
+            let held = account.balance(currency).held;
+            if held.is_negative() {
+                violations.push(Violation::NegativeHeld {
+                    client,
+                    currency,
+                    held,
+                });
+            }
+            let disputed = disputed.remove(&(client, currency)).unwrap_or_default();
+            if held != disputed {
+                violations.push(Violation::HeldMismatch {
+                    client,
+                    currency,
+                    held,
+                    disputed,
+                });
+            }
+        }
+    }
+    // Disputes holding funds of clients without an account or balance
+    for ((client, currency), disputed) in disputed {
+        violations.push(Violation::HeldMismatch {
+            client,
+            currency,
+            held: Amount::ZERO,
+            disputed,
+        });
+    }
+
+    // Transactions come out of storage in no particular order
+    transaction_violations.sort_by_key(|(tx, _)| tx.0);
+    violations.extend(transaction_violations.into_iter().map(|(_, v)| v));
+    Ok(violations)
+}
+
+fn check_transaction(tx: TransactionId, stored: &StoredTransaction) -> Option<Violation> {
+    let (amount, disputed, settled) = (stored.amount, stored.disputed, stored.settled);
+    if stored.state == TransactionState::ChargedBack && !disputed.is_zero() {
+        return Some(Violation::ChargedBackDisputed { tx, disputed });
+    }
+    let charged_back = stored.charged_back;
+    if disputed.is_negative()
+        || settled.is_negative()
+        || disputed + settled > amount
+        || charged_back.is_negative()
+        || charged_back > settled
+    {
+        return Some(Violation::DisputeAmounts {
+            tx,
+            amount,
+            disputed,
+            settled,
+        });
+    }
+    let consistent = match stored.state {
+        TransactionState::None => disputed.is_zero() && settled.is_zero(),
+        TransactionState::Disputed => !disputed.is_zero(),
+        TransactionState::Resolved | TransactionState::ChargedBack => {
+            disputed.is_zero() && !settled.is_zero()
+        }
+    };
+    (!consistent).then_some(Violation::StateMismatch {
+        tx,
+        state: stored.state,
+        disputed,
+        settled,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::types::{Transaction, TransactionType};
+    use crate::domain::Ledger;
+
+    fn amount(s: &str) -> Amount {
+        Amount::from_str_rounded(s).expect("failed to parse amount")
+    }
+
+    fn ledger_with_disputes() -> Ledger {
+        let mut ledger = Ledger::new().with_withdrawal_disputes();
+        let rows = [
+            (TransactionType::Deposit, 1, 1, Some("100"), None),
+            (TransactionType::Withdrawal, 1, 2, Some("10"), None),
+            (
+                TransactionType::Transfer,
+                1,
+                3,
+                Some("20"),
+                Some(ClientId(2)),
+            ),
+            (TransactionType::Deposit, 2, 4, Some("5"), None),
+            (TransactionType::Dispute, 1, 1, Some("30"), None),
+            (TransactionType::Resolve, 1, 1, Some("10"), None),
+            (TransactionType::Dispute, 1, 2, None, None),
+            (TransactionType::Dispute, 1, 3, None, None),
+            (TransactionType::Dispute, 2, 4, None, None),
+            (TransactionType::Chargeback, 2, 4, None, None),
+        ];
+        for (tx_type, client, tx, value, destination) in rows {
+            ledger
+                .process_transaction(&Transaction {
+                    tx_type,
+                    client_id: ClientId(client),
+                    tx_id: TransactionId(tx),
+                    amount: value.map(amount),
+                    destination,
+                    currency: None,
+                    timestamp: None,
+                    reason: None,
+                })
+                .expect("transaction should be accepted");
+        }
+        ledger
+    }
+
+    #[test]
+    fn test_consistent_ledger_has_no_violations() {
+        assert_eq!(
+            ledger_with_disputes().audit().expect("failed to audit"),
+            Vec::new()
+        );
+    }
+
+    #[test]
+    fn test_audit_reports_broken_invariants() {
+        let mut snapshot = ledger_with_disputes()
+            .snapshot()
+            .expect("failed to take snapshot");
+        snapshot.accounts[1].account.held = amount("-1");
+        for entry in &mut snapshot.transactions {
+            match entry.tx.0 {
+                // Partly disputed, with more disputed than is left
+                1 => entry.transaction.disputed = amount("95"),
+                // Charged back, then disputed again
+                4 => entry.transaction.disputed = amount("5"),
+                _ => {}
+            }
+        }
+        let ledger = Ledger::from_snapshot(snapshot).expect("snapshot should restore");
+
+        let violations = ledger.audit().expect("failed to audit");
+        let categories: Vec<_> = violations.iter().map(Violation::category).collect();
+        assert_eq!(
+            categories,
+            vec![
+                "held_mismatch",
+                "negative_held",
+                "held_mismatch",
+                "dispute_amounts",
+                "charged_back_disputed",
+                "books",
+            ]
+        );
+        assert_eq!(
+            violations[2].to_string(),
+            "client 2 holds -1.0000 in the default currency, but its open disputes hold 25.0000"
+        );
+        assert_eq!(
+            violations[4].to_string(),
+            "transaction 4 was charged back but has 5.0000 disputed"
+        );
+    }
+
+    #[test]
+    fn test_state_must_match_amounts() {
+        let mut stored =
+            StoredTransaction::new(ClientId(1), TransactionKind::Deposit, amount("10"), None);
+        assert_eq!(check_transaction(TransactionId(1), &stored), None);
+
+        stored.state = TransactionState::Resolved;
+        assert_eq!(
+            check_transaction(TransactionId(1), &stored),
+            Some(Violation::StateMismatch {
+                tx: TransactionId(1),
+                state: TransactionState::Resolved,
+                disputed: Amount::ZERO,
+                settled: Amount::ZERO,
+            })
+        );
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::domain::account::Account;
+use crate::domain::types::{currency_name, Amount, ClientId, Currency};
+
+/// An account of the double-entry books kept by a [`Ledger`](crate::domain::Ledger)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BookAccount {
+    /// A client's available funds.
+    Available(ClientId),
+    /// A client's funds held by disputes.
+    Held(ClientId),
+    /// Funds paid in by deposits and paid out by withdrawals.
+    ExternalFunding,
+    /// Funds returned to payers by chargebacks, and paid for disputed withdrawals.
+    ChargebackLoss,
+}
+
+impl fmt::Display for BookAccount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BookAccount::Available(client) => write!(f, "client {} available", client),
+            BookAccount::Held(client) => write!(f, "client {} held", client),
+            BookAccount::ExternalFunding => write!(f, "external funding"),
+            BookAccount::ChargebackLoss => write!(f, "chargeback loss"),
+        }
+    }
+}
+
+/// Moves `amount` from the `debit` account to the `credit` account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Posting {
+    pub debit: BookAccount,
+    pub credit: BookAccount,
+    pub amount: Amount,
+    pub currency: Option<Currency>,
+}
+
+/// Balances of every book account per currency, as credits minus debits
+///
+/// Client accounts hold what the ledger owes its clients, so they match their
+/// [`Account`]; system accounts are negative by what they paid into client accounts.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Books {
+    /// Hashed rather than ordered, as every applied event posts to two accounts.
+    balances: HashMap<(BookAccount, Option<Currency>), Amount>,
+}
+
+impl Books {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_balances(
+        balances: impl IntoIterator<Item = ((BookAccount, Option<Currency>), Amount)>,
+    ) -> Self {
+        Books {
+            balances: balances.into_iter().collect(),
+        }
+    }
+
+    /// Books funding the current balances of `accounts` from external funding, for
+    /// ledgers restored without books of their own.
+    pub fn opening(accounts: &BTreeMap<ClientId, Account>) -> Self {
+        let mut books = Books::new();
+        for ((account, currency), amount) in client_balances(accounts) {
+            books.post(Posting {
+                debit: BookAccount::ExternalFunding,
+                credit: account,
+                amount,
+                currency,
+            });
+        }
+        books
+    }
+
+    pub fn post(&mut self, posting: Posting) {
+        *self
+            .balances
+            .entry((posting.debit, posting.currency))
+            .or_default() -= posting.amount;
+        *self
+            .balances
+            .entry((posting.credit, posting.currency))
+            .or_default() += posting.amount;
+    }
+
+    /// Adds the balances of `other`, e.g. the books of another shard.
+    pub fn merge(&mut self, other: Books) {
+        for (key, amount) in other.balances {
+            *self.balances.entry(key).or_default() += amount;
+        }
+    }
+
+    pub fn balance(&self, account: BookAccount, currency: Option<Currency>) -> Amount {
+        self.balances
+            .get(&(account, currency))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Every balance posted to, ordered by account and currency.
+    pub fn balances(&self) -> BTreeMap<(BookAccount, Option<Currency>), Amount> {
+        self.balances
+            .iter()
+            .map(|(key, amount)| (*key, *amount))
+            .collect()
+    }
+
+    /// Sum of the balances per currency, zero in balanced books.
+    pub fn totals(&self) -> BTreeMap<Option<Currency>, Amount> {
+        let mut totals = BTreeMap::new();
+        for ((_, currency), amount) in &self.balances {
+            *totals.entry(*currency).or_default() += *amount;
+        }
+        totals
+    }
+
+    /// Checks that the books sum to zero in every currency and that every client
+    /// account has the same available and held funds as in the books.
+    pub fn check(&self, accounts: &BTreeMap<ClientId, Account>) -> Result<(), BooksError> {
+        if let Some((currency, total)) = self.totals().into_iter().find(|(_, t)| !t.is_zero()) {
+            return Err(BooksError::Unbalanced { currency, total });
+        }
+
+        let actual = client_balances(accounts);
+        let client_lines = self.balances.keys().filter(|(account, _)| {
+            matches!(account, BookAccount::Available(_) | BookAccount::Held(_))
+        });
+        for &(account, currency) in client_lines.chain(actual.keys()) {
+            let books = self.balance(account, currency);
+            let funds = actual
+                .get(&(account, currency))
+                .copied()
+                .unwrap_or_default();
+            if books != funds {
+                return Err(BooksError::Mismatch {
+                    account,
+                    currency,
+                    books,
+                    actual: funds,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Available and held funds of every client per currency.
+fn client_balances(
+    accounts: &BTreeMap<ClientId, Account>,
+) -> BTreeMap<(BookAccount, Option<Currency>), Amount> {
+    let mut balances = BTreeMap::new();
+    for (client, account) in accounts {
+        let currencies = account.currencies.keys().copied().map(Some);
+        for currency in std::iter::once(None).chain(currencies) {
+            let balance = account.balance(currency);
+            balances.insert(
+                (BookAccount::Available(*client), currency),
+                balance.available,
+            );
+            balances.insert((BookAccount::Held(*client), currency), balance.held);
+        }
+    }
+    balances
+}
+
+/// Why a [`Ledger::trial_balance`](crate::domain::Ledger::trial_balance) failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BooksError {
+    /// The balances of a currency do not sum to zero.
+    Unbalanced {
+        currency: Option<Currency>,
+        total: Amount,
+    },
+    /// A client's funds differ between their account and the books.
+    Mismatch {
+        account: BookAccount,
+        currency: Option<Currency>,
+        books: Amount,
+        actual: Amount,
+    },
+}
+
+impl fmt::Display for BooksError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            BooksError::Unbalanced { currency, total } => {
+                write!(
+                    f,
+                    "books are off by {} in {}",
+                    total,
+                    currency_name(&currency)
+                )
+            }
+            BooksError::Mismatch {
+                account,
+                currency,
+                books,
+                actual,
+            } => write!(
+                f,
+                "{} is {} in {} but {} in the books",
+                account,
+                actual,
+                currency_name(&currency),
+                books
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BooksError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn amount(s: &str) -> Amount {
+        Amount::from_str_rounded(s).expect("failed to parse amount")
+    }
+
+    #[test]
+    fn test_books_match_accounts() {
+        let mut books = Books::new();
+        books.post(Posting {
+            debit: BookAccount::ExternalFunding,
+            credit: BookAccount::Available(ClientId(1)),
+            amount: amount("10"),
+            currency: None,
+        });
+        books.post(Posting {
+            debit: BookAccount::Available(ClientId(1)),
+            credit: BookAccount::Held(ClientId(1)),
+            amount: amount("4"),
+            currency: None,
+        });
+        assert_eq!(books.totals(), BTreeMap::from([(None, Amount::ZERO)]));
+
+        let mut account = Account::new();
+        account.available = amount("6");
+        account.held = amount("4");
+        let mut accounts = BTreeMap::from([(ClientId(1), account)]);
+        assert_eq!(books.check(&accounts), Ok(()));
+        assert_eq!(Books::opening(&accounts).check(&accounts), Ok(()));
+
+        accounts
+            .get_mut(&ClientId(1))
+            .expect("account should exist")
+            .held = amount("5");
+        let error = books.check(&accounts).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "client 1 held is 5.0000 in the default currency but 4.0000 in the books"
+        );
+    }
+
+    #[test]
+    fn test_unbalanced_books() {
+        let currency = "EUR".parse().ok();
+        let books = Books::from_balances(BTreeMap::from([(
+            (BookAccount::ChargebackLoss, currency),
+            amount("-2"),
+        )]));
+        assert_eq!(
+            books.check(&BTreeMap::new()),
+            Err(BooksError::Unbalanced {
+                currency,
+                total: amount("-2"),
+            })
+        );
+    }
+}
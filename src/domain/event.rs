@@ -0,0 +1,133 @@
+use serde::{Deserialize, Serialize};
+
+use crate::domain::types::{
+    Amount, ClientId, Currency, LockReason, Timestamp, TransactionId, TransactionType,
+};
+
+/// An immutable fact about a change applied to the ledger.
+///
+/// Replaying the events of a run in order rebuilds the exact same state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum LedgerEvent {
+    DepositApplied {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Amount,
+        /// `None` for the default currency, which is also how older journals read.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<Currency>,
+        /// `None` when the input has no timestamps, which is also how older journals read.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<Timestamp>,
+    },
+    WithdrawalApplied {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Amount,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<Currency>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<Timestamp>,
+    },
+    FundsHeld {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Amount,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<Timestamp>,
+    },
+    FundsReleased {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Amount,
+    },
+    ChargedBack {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Amount,
+    },
+    /// Moves funds from `client` to `destination` as one step.
+    TransferApplied {
+        client: ClientId,
+        tx: TransactionId,
+        destination: ClientId,
+        amount: Amount,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<Currency>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<Timestamp>,
+    },
+    AccountLocked {
+        client: ClientId,
+        tx: TransactionId,
+        /// Absent in journals written before administrative locks existed.
+        #[serde(default)]
+        reason: LockReason,
+    },
+    AccountUnlocked {
+        client: ClientId,
+        tx: TransactionId,
+    },
+    /// Sets how far `client` may withdraw below zero in the default currency.
+    OverdraftLimitSet {
+        client: ClientId,
+        /// `None` when set from the client configuration rather than a `limit` row.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tx: Option<TransactionId>,
+        limit: Amount,
+    },
+    /// Moves the fee for transaction `tx` from `client` to the `house` account.
+    FeeCharged {
+        client: ClientId,
+        tx: TransactionId,
+        /// Type of the transaction the fee is for.
+        fee_for: TransactionType,
+        house: ClientId,
+        amount: Amount,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<Currency>,
+    },
+    /// Moves the ledger's time forward to the timestamp of the accepted row `tx` of
+    /// `client`, when none of the row's other events carried it.
+    ClockAdvanced {
+        client: ClientId,
+        tx: TransactionId,
+        timestamp: Timestamp,
+    },
+}
+
+impl LedgerEvent {
+    pub fn client(&self) -> ClientId {
+        match *self {
+            LedgerEvent::DepositApplied { client, .. }
+            | LedgerEvent::WithdrawalApplied { client, .. }
+            | LedgerEvent::TransferApplied { client, .. }
+            | LedgerEvent::FundsHeld { client, .. }
+            | LedgerEvent::FundsReleased { client, .. }
+            | LedgerEvent::ChargedBack { client, .. }
+            | LedgerEvent::AccountLocked { client, .. }
+            | LedgerEvent::AccountUnlocked { client, .. }
+            | LedgerEvent::OverdraftLimitSet { client, .. }
+            | LedgerEvent::FeeCharged { client, .. }
+            | LedgerEvent::ClockAdvanced { client, .. } => client,
+        }
+    }
+
+    /// Transaction that caused the event, if any.
+    pub fn tx(&self) -> Option<TransactionId> {
+        match *self {
+            LedgerEvent::DepositApplied { tx, .. }
+            | LedgerEvent::WithdrawalApplied { tx, .. }
+            | LedgerEvent::TransferApplied { tx, .. }
+            | LedgerEvent::FundsHeld { tx, .. }
+            | LedgerEvent::FundsReleased { tx, .. }
+            | LedgerEvent::ChargedBack { tx, .. }
+            | LedgerEvent::AccountLocked { tx, .. }
+            | LedgerEvent::AccountUnlocked { tx, .. }
+            | LedgerEvent::FeeCharged { tx, .. }
+            | LedgerEvent::ClockAdvanced { tx, .. } => Some(tx),
+            LedgerEvent::OverdraftLimitSet { tx, .. } => tx,
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::domain::types::{Amount, ClientId, TransactionType};
+
+/// Fee charged for one transaction type: `flat` plus `percent` of the amount moved,
+/// kept within `min` and `max`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeeRule {
+    #[serde(default)]
+    pub flat: Amount,
+    /// Percentage of the amount, e.g. `1.5` for 1.5%.
+    #[serde(default)]
+    pub percent: Decimal,
+    #[serde(default)]
+    pub min: Option<Amount>,
+    #[serde(default)]
+    pub max: Option<Amount>,
+}
+
+impl FeeRule {
+    /// Fee for a transaction moving `amount`, rounded to 4 decimal places like any amount.
+    pub fn fee(&self, amount: Amount) -> Amount {
+        let fee = self.flat.0 + amount.0 * self.percent / Decimal::ONE_HUNDRED;
+        let fee = self.min.map_or(fee, |min| fee.max(min.0));
+        let fee = self.max.map_or(fee, |max| fee.min(max.0));
+        Amount::new(fee)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let amounts = [Some(self.flat), self.min, self.max];
+        if amounts.iter().flatten().any(Amount::is_negative) || self.percent < Decimal::ZERO {
+            return Err("fees cannot be negative".to_string());
+        }
+        if let (Some(min), Some(max)) = (self.min, self.max) {
+            if min > max {
+                return Err(format!("min {} is above max {}", min, max));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Fees per transaction type and the house account they are paid to, see
+/// [`Ledger::with_fees`](crate::domain::Ledger::with_fees)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeeSchedule {
+    pub house_account: ClientId,
+    #[serde(default)]
+    pub fees: BTreeMap<TransactionType, FeeRule>,
+}
+
+impl FeeSchedule {
+    pub fn new(house_account: ClientId) -> Self {
+        FeeSchedule {
+            house_account,
+            fees: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_fee(mut self, tx_type: TransactionType, rule: FeeRule) -> Self {
+        self.fees.insert(tx_type, rule);
+        self
+    }
+
+    /// Fee for a transaction of `tx_type` moving `amount`; zero without a rule.
+    pub fn fee(&self, tx_type: TransactionType, amount: Amount) -> Amount {
+        self.fees
+            .get(&tx_type)
+            .map_or(Amount::ZERO, |rule| rule.fee(amount))
+    }
+
+    /// Checks that no rule has negative values or a minimum above its maximum.
+    pub fn validate(&self) -> Result<(), String> {
+        for (tx_type, rule) in &self.fees {
+            rule.validate()
+                .map_err(|e| format!("invalid {} fee: {}", tx_type, e))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn amount(s: &str) -> Amount {
+        Amount::from_str_rounded(s).expect("failed to parse amount")
+    }
+
+    #[test]
+    fn test_fee_rule_rounds_and_caps() {
+        let rule = FeeRule {
+            flat: amount("0.25"),
+            percent: Decimal::new(15, 1),
+            min: Some(amount("1")),
+            max: Some(amount("20")),
+        };
+        assert_eq!(rule.fee(amount("10")), amount("1"));
+        assert_eq!(rule.fee(amount("100.0033")), amount("1.7500"));
+        assert_eq!(rule.fee(amount("100.0067")), amount("1.7501"));
+        assert_eq!(rule.fee(amount("5000")), amount("20"));
+        assert_eq!(FeeRule::default().fee(amount("100")), Amount::ZERO);
+    }
+
+    #[test]
+    fn test_fee_schedule_from_json() {
+        let schedule: FeeSchedule = serde_json::from_str(
+            r#"{"house_account": 0, "fees": {
+                "withdrawal": {"flat": "0.5", "percent": "1"},
+                "chargeback": {"flat": "15"}
+            }}"#,
+        )
+        .expect("schedule should parse");
+        assert_eq!(schedule.house_account, ClientId(0));
+        assert_eq!(
+            schedule.fee(TransactionType::Withdrawal, amount("10")),
+            amount("0.6")
+        );
+        assert_eq!(
+            schedule.fee(TransactionType::Chargeback, amount("1000")),
+            amount("15")
+        );
+        assert_eq!(
+            schedule.fee(TransactionType::Deposit, amount("10")),
+            Amount::ZERO
+        );
+        assert!(schedule.validate().is_ok());
+    }
+
+    #[test]
+    fn test_fee_schedule_validation() {
+        let schedule = FeeSchedule::new(ClientId(0)).with_fee(
+            TransactionType::Deposit,
+            FeeRule {
+                min: Some(amount("5")),
+                max: Some(amount("1")),
+                ..FeeRule::default()
+            },
+        );
+        assert_eq!(
+            schedule.validate(),
+            Err("invalid deposit fee: min 5.0000 is above max 1.0000".to_string())
+        );
+
+        let schedule = FeeSchedule::new(ClientId(0)).with_fee(
+            TransactionType::Withdrawal,
+            FeeRule {
+                percent: Decimal::NEGATIVE_ONE,
+                ..FeeRule::default()
+            },
+        );
+        assert!(schedule.validate().is_err());
+    }
+}
@@ -0,0 +1,59 @@
+use std::fmt;
+
+use crate::domain::account::Balance;
+use crate::domain::types::{Currency, TransactionId};
+
+/// What an operation did from the point of view of one client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Activity {
+    Deposit,
+    Withdrawal,
+    /// The client sent a transfer.
+    TransferOut,
+    /// The client received a transfer.
+    TransferIn,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Lock,
+    Unlock,
+    /// A fee paid by the client, or received by the house account.
+    Fee,
+}
+
+impl Activity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Activity::Deposit => "deposit",
+            Activity::Withdrawal => "withdrawal",
+            Activity::TransferOut => "transfer_out",
+            Activity::TransferIn => "transfer_in",
+            Activity::Dispute => "dispute",
+            Activity::Resolve => "resolve",
+            Activity::Chargeback => "chargeback",
+            Activity::Lock => "lock",
+            Activity::Unlock => "unlock",
+            Activity::Fee => "fee",
+        }
+    }
+}
+
+impl fmt::Display for Activity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An operation applied to a client's account, with the balance it left behind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub tx: TransactionId,
+    pub activity: Activity,
+    /// `None` for the default currency.
+    pub currency: Option<Currency>,
+    /// Change to the available and held funds of `currency`.
+    pub change: Balance,
+    /// Running balance of `currency` after the operation.
+    pub balance: Balance,
+    pub locked: bool,
+}
@@ -1,192 +1,1425 @@
-use std::collections::{HashMap, HashSet};
-
-use crate::domain::account::Account;
-use crate::domain::types::{Amount, ClientId, TransactionId, TransactionState, TransactionType};
-
-/// A stored deposit transaction for dispute tracking
-#[derive(Debug, Clone)]
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use crate::domain::account::{Account, Balance};
+use crate::domain::audit::{check_disputes, Violation};
+use crate::domain::books::{BookAccount, Books, BooksError, Posting};
+use crate::domain::event::LedgerEvent;
+use crate::domain::fees::FeeSchedule;
+use crate::domain::history::{Activity, HistoryEntry};
+use crate::domain::policy::LedgerPolicy;
+use crate::domain::rejection::RejectionReason;
+use crate::domain::snapshot::{
+    AccountEntry, BookEntry, LedgerSnapshot, SnapshotError, SnapshotStream, TransactionEntry,
+    SNAPSHOT_VERSION,
+};
+use crate::domain::storage::{MemoryStorage, Storage};
+use crate::domain::types::{
+    Amount, ClientId, Currency, LockReason, Timestamp, Transaction, TransactionId, TransactionKind,
+    TransactionState, TransactionType,
+};
+
+/// A stored deposit, withdrawal or transfer for dispute tracking
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct StoredTransaction {
     pub client_id: ClientId,
+    /// Absent in snapshots written before withdrawals were stored.
+    #[serde(default)]
+    pub kind: TransactionKind,
     pub amount: Amount,
     pub state: TransactionState,
+    /// Portion currently under dispute (held).
+    #[serde(default)]
+    pub disputed: Amount,
+    /// Portion whose dispute was already resolved or charged back.
+    #[serde(default)]
+    pub settled: Amount,
+    /// Part of `settled` that was charged back, which is never disputed again.
+    #[serde(default, skip_serializing_if = "Amount::is_zero")]
+    pub charged_back: Amount,
+    /// Receiving client of a transfer; `client_id` is the sender.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination: Option<ClientId>,
+    /// `None` for the default currency; disputes move funds in this currency.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
+    /// When the transaction happened, if the input had timestamps.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<Timestamp>,
+    /// When the open dispute was opened, if known; cleared once nothing is disputed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disputed_at: Option<Timestamp>,
+}
+
+impl StoredTransaction {
+    pub fn new(
+        client_id: ClientId,
+        kind: TransactionKind,
+        amount: Amount,
+        currency: Option<Currency>,
+    ) -> Self {
+        StoredTransaction {
+            client_id,
+            kind,
+            amount,
+            state: TransactionState::None,
+            disputed: Amount::ZERO,
+            settled: Amount::ZERO,
+            charged_back: Amount::ZERO,
+            destination: None,
+            currency,
+            timestamp: None,
+            disputed_at: None,
+        }
+    }
+
+    /// Amount that can still be put under a new dispute.
+    pub fn undisputed(&self) -> Amount {
+        self.amount - self.disputed - self.settled
+    }
+
+    /// Amount that can be put under a new dispute when resolved parts may be reopened.
+    pub fn redisputable(&self) -> Amount {
+        self.amount - self.disputed - self.charged_back
+    }
+
+    fn open_dispute(&mut self, amount: Amount, at: Option<Timestamp>) {
+        if self.disputed.is_zero() {
+            self.disputed_at = at;
+        }
+        // Disputing more than is undisputed reopens resolved parts
+        let reopened = amount - self.undisputed();
+        if reopened > Amount::ZERO {
+            self.settled -= reopened;
+        }
+        self.disputed += amount;
+        self.state = TransactionState::Disputed;
+    }
+
+    /// Closes part of the open dispute; the state only changes once nothing is left disputed.
+    fn settle(&mut self, amount: Amount, outcome: TransactionState) {
+        self.disputed -= amount;
+        self.settled += amount;
+        if outcome == TransactionState::ChargedBack {
+            self.charged_back += amount;
+        }
+        if self.disputed.is_zero() {
+            self.state = outcome;
+            self.disputed_at = None;
+        }
+    }
+}
+
+/// Effect of a transaction accepted by the ledger
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessOutcome {
+    Deposited(Amount),
+    Withdrawn(Amount),
+    Held(Amount),
+    Released(Amount),
+    ChargedBack(Amount),
+    Transferred(Amount),
+    Locked,
+    Unlocked,
+    /// The new overdraft limit.
+    LimitSet(Amount),
+}
+
+impl ProcessOutcome {
+    /// Amount moved, or the new limit; `None` for locks and unlocks.
+    pub fn amount(&self) -> Option<Amount> {
+        match *self {
+            ProcessOutcome::Deposited(amount)
+            | ProcessOutcome::Withdrawn(amount)
+            | ProcessOutcome::Held(amount)
+            | ProcessOutcome::Released(amount)
+            | ProcessOutcome::ChargedBack(amount)
+            | ProcessOutcome::Transferred(amount)
+            | ProcessOutcome::LimitSet(amount) => Some(amount),
+            ProcessOutcome::Locked | ProcessOutcome::Unlocked => None,
+        }
+    }
+}
+
+/// A journal event that could not be applied while replaying
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayError {
+    /// Zero-based position of the event in the replayed stream.
+    pub index: usize,
+    /// Boxed, as a lock reason makes events large.
+    pub event: Box<LedgerEvent>,
+    pub reason: RejectionReason,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "event {} (client {}", self.index, self.event.client())?;
+        if let Some(tx) = self.event.tx() {
+            write!(f, ", tx {}", tx)?;
+        }
+        write!(f, ") cannot be replayed: {}", self.reason)
+    }
 }
 
+impl std::error::Error for ReplayError {}
+
 /// Maintains client accounts and processes transactions.
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct Ledger {
-    accounts: HashMap<ClientId, Account>,
-    deposits: HashMap<TransactionId, StoredTransaction>,
-    /// Tracks processed tx IDs for idempotency.
-    processed_tx_ids: HashSet<TransactionId>,
+    /// Accounts, stored transactions and the processed tx IDs kept for idempotency.
+    storage: Box<dyn Storage>,
+    /// Which disputes are accepted; applying events never depends on it.
+    policy: LedgerPolicy,
+    /// Whether applied events are kept in `events` for the caller to drain.
+    record_events: bool,
+    events: Vec<LedgerEvent>,
+    /// Whether applied operations are kept per client in `history`.
+    record_history: bool,
+    history: BTreeMap<ClientId, Vec<HistoryEntry>>,
+    /// Seconds after a transaction during which it can be disputed.
+    dispute_window: Option<u64>,
+    /// Seconds after which an open dispute is resolved automatically.
+    auto_resolve_after: Option<u64>,
+    /// Latest timestamp seen, which is what the ledger considers now.
+    clock: Option<Timestamp>,
+    /// Disputes with a known opening time, oldest first.
+    open_disputes: BTreeSet<(Timestamp, TransactionId)>,
+    fees: Option<FeeSchedule>,
+    /// Double-entry record of every movement of funds, see [`Ledger::trial_balance`].
+    books: Books,
+    /// First storage failure, after which every transaction is rejected with it.
+    storage_failure: Option<RejectionReason>,
+}
+
+impl Default for Ledger {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Ledger {
     pub fn new() -> Self {
         Ledger {
-            accounts: HashMap::new(),
-            deposits: HashMap::new(),
-            processed_tx_ids: HashSet::new(),
+            storage: Box::new(MemoryStorage::new()),
+            policy: LedgerPolicy::default(),
+            record_events: false,
+            events: Vec::new(),
+            record_history: false,
+            history: BTreeMap::new(),
+            dispute_window: None,
+            auto_resolve_after: None,
+            clock: None,
+            open_disputes: BTreeSet::new(),
+            fees: None,
+            books: Books::new(),
+            storage_failure: None,
+        }
+    }
+
+    /// Allows disputing withdrawals: a dispute holds the withdrawn amount as a
+    /// provisional credit, a resolve drops it, and a chargeback credits it back
+    /// to the client without locking the account.
+    pub fn with_withdrawal_disputes(mut self) -> Self {
+        self.policy.dispute_withdrawals = true;
+        self
+    }
+
+    /// Applies the dispute rules of `policy`, replacing any set before.
+    pub fn with_policy(mut self, policy: LedgerPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn policy(&self) -> &LedgerPolicy {
+        &self.policy
+    }
+
+    /// Keeps the state in `storage` instead of memory, moving over anything already
+    /// there, e.g. [`DiskStorage`](crate::storage::DiskStorage); fails if either storage
+    /// does.
+    pub fn with_storage(mut self, mut storage: Box<dyn Storage>) -> io::Result<Self> {
+        storage.accounts_mut().append(self.storage.accounts_mut());
+        for entry in self.storage.transactions() {
+            let (tx_id, stored) = entry?;
+            storage.insert_transaction(tx_id, stored)?;
+        }
+        for tx_id in self.storage.processed_tx_ids() {
+            storage.mark_processed(tx_id?)?;
+        }
+        self.storage = storage;
+        Ok(self)
+    }
+
+    /// Rejects disputes arriving more than `seconds` after the disputed transaction.
+    ///
+    /// Time comes from the optional timestamps of the input: a dispute is checked against
+    /// the latest timestamp seen. Deposits, withdrawals and transfers are stored with their
+    /// own timestamp, or with the latest one seen when they have none; those made before
+    /// any timestamp was seen can always be disputed.
+    pub fn with_dispute_window(mut self, seconds: u64) -> Self {
+        self.dispute_window = Some(seconds);
+        self
+    }
+
+    /// Resolves disputes still open `seconds` after they were opened, as soon as a row
+    /// with a later timestamp is accepted; the resolves are applied and journaled like
+    /// any other.
+    pub fn with_auto_resolve(mut self, seconds: u64) -> Self {
+        self.auto_resolve_after = Some(seconds);
+        self
+    }
+
+    /// Charges the fees of `schedule` for every accepted transaction, paid to its house
+    /// account, which itself pays none.
+    ///
+    /// Withdrawals and transfers must also cover their fee; any other fee is debited
+    /// even when it takes the balance below zero or the account is locked.
+    pub fn with_fees(mut self, schedule: FeeSchedule) -> Self {
+        self.fees = Some(schedule);
+        self
+    }
+
+    /// Keeps every applied event until taken with [`Ledger::drain_events`].
+    pub fn with_event_log(mut self) -> Self {
+        self.record_events = true;
+        self
+    }
+
+    /// Keeps every applied operation per client, see [`Ledger::history`].
+    ///
+    /// The history lives in memory only and is not part of snapshots.
+    pub fn with_history(mut self) -> Self {
+        self.record_history = true;
+        self
+    }
+
+    /// Operations applied to `client_id` since the history was enabled, oldest first.
+    pub fn history(&self, client_id: ClientId) -> &[HistoryEntry] {
+        self.history.get(&client_id).map_or(&[], Vec::as_slice)
+    }
+
+    /// Checks that the double-entry books sum to zero in every currency and agree with
+    /// the available and held funds of every client, returning the books.
+    pub fn trial_balance(&self) -> Result<&Books, BooksError> {
+        self.books.check(self.storage.accounts())?;
+        Ok(&self.books)
+    }
+
+    /// Checks the invariants processing keeps: held funds match the open disputes and
+    /// are never negative, dispute amounts and states agree, charged back transactions
+    /// are not disputed again, and the books balance. Empty for a consistent ledger.
+    pub fn audit(&self) -> io::Result<Vec<Violation>> {
+        let accounts = self.storage.accounts();
+        let mut violations = check_disputes(accounts, self.storage.transactions())?;
+        if let Err(error) = self.books.check(accounts) {
+            violations.push(Violation::Books(error));
+        }
+        Ok(violations)
+    }
+
+    /// Takes the events applied since the last drain, oldest first.
+    pub fn drain_events(&mut self) -> std::vec::Drain<'_, LedgerEvent> {
+        self.events.drain(..)
+    }
+
+    /// Rebuilds a ledger by applying journaled events in order.
+    pub fn replay<I>(events: I) -> Result<Self, ReplayError>
+    where
+        I: IntoIterator<Item = LedgerEvent>,
+    {
+        let mut ledger = Ledger::new();
+        for (index, event) in events.into_iter().enumerate() {
+            ledger.apply(&event).map_err(|reason| ReplayError {
+                index,
+                event: Box::new(event),
+                reason,
+            })?;
+        }
+        Ok(ledger)
+    }
+
+    /// Captures the complete state needed to resume processing later.
+    pub fn snapshot(&self) -> io::Result<LedgerSnapshot> {
+        let accounts = self
+            .storage
+            .accounts()
+            .iter()
+            .map(|(client, account)| AccountEntry {
+                client: *client,
+                account: account.clone(),
+            })
+            .collect();
+
+        let mut transactions = self
+            .storage
+            .transactions()
+            .map(|entry| entry.map(|(tx, transaction)| TransactionEntry { tx, transaction }))
+            .collect::<io::Result<Vec<_>>>()?;
+        transactions.sort_by_key(|entry| entry.tx.0);
+
+        let mut processed_tx_ids = self
+            .storage
+            .processed_tx_ids()
+            .collect::<io::Result<Vec<_>>>()?;
+        processed_tx_ids.sort_by_key(|tx| tx.0);
+
+        let books = self.book_entries();
+
+        Ok(LedgerSnapshot {
+            version: SNAPSHOT_VERSION,
+            accounts,
+            transactions,
+            processed_tx_ids,
+            books,
+        })
+    }
+
+    /// Same state as [`Ledger::snapshot`], serialized straight from storage.
+    pub fn snapshot_stream(&self) -> SnapshotStream<'_> {
+        SnapshotStream {
+            storage: self.storage.as_ref(),
+            books: self.book_entries(),
+            error: RefCell::new(None),
+        }
+    }
+
+    fn book_entries(&self) -> Vec<BookEntry> {
+        self.books
+            .balances()
+            .into_iter()
+            .map(|((account, currency), balance)| BookEntry {
+                account,
+                currency,
+                balance,
+            })
+            .collect()
+    }
+
+    /// Combines ledgers holding disjoint clients and stored transactions, such as the
+    /// shards of a [`ShardedLedger`](crate::sharded::ShardedLedger); settings come from
+    /// the first one.
+    pub fn merge(ledgers: impl IntoIterator<Item = Ledger>) -> io::Result<Ledger> {
+        let mut ledgers = ledgers.into_iter();
+        let mut merged = ledgers.next().unwrap_or_default();
+        for mut ledger in ledgers {
+            merged
+                .storage
+                .accounts_mut()
+                .append(ledger.storage.accounts_mut());
+            for entry in ledger.storage.transactions() {
+                let (tx_id, stored) = entry?;
+                merged.storage.insert_transaction(tx_id, stored)?;
+            }
+            for tx_id in ledger.storage.processed_tx_ids() {
+                merged.storage.mark_processed(tx_id?)?;
+            }
+            merged.events.extend(ledger.events);
+            merged.history.extend(ledger.history);
+            merged.open_disputes.extend(ledger.open_disputes);
+            merged.books.merge(ledger.books);
+            merged.clock = merged.clock.max(ledger.clock);
+            merged.storage_failure = merged.storage_failure.or(ledger.storage_failure);
+        }
+        Ok(merged)
+    }
+
+    /// Marks `tx_id` as used without storing a transaction, for an id that another shard
+    /// of a [`ShardedLedger`](crate::sharded::ShardedLedger) accepted; not journaled.
+    pub(crate) fn reserve_tx_id(&mut self, tx_id: TransactionId) {
+        if let Err(error) = self.storage.mark_processed(tx_id) {
+            self.storage_failure.get_or_insert(error.into());
+        }
+    }
+
+    /// Rebuilds a ledger from a snapshot taken by [`Ledger::snapshot`].
+    pub fn from_snapshot(snapshot: LedgerSnapshot) -> Result<Self, SnapshotError> {
+        let version = snapshot.version;
+        if version == 0 || version > SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let mut ledger = Ledger::new();
+        for entry in snapshot.accounts {
+            let mut account = entry.account;
+            // Before version 3 only chargebacks could lock an account
+            if version < 3 && account.locked {
+                account.lock_reason = Some(LockReason::CHARGEBACK);
+            }
+            ledger.storage.accounts_mut().insert(entry.client, account);
+        }
+        for entry in snapshot.transactions {
+            let mut stored = entry.transaction;
+            // Version 1 only disputed whole transactions, so amounts follow from the state
+            if version < 2 {
+                match stored.state {
+                    TransactionState::None => {}
+                    TransactionState::Disputed => stored.disputed = stored.amount,
+                    TransactionState::Resolved | TransactionState::ChargedBack => {
+                        stored.settled = stored.amount
+                    }
+                }
+            }
+            // Before version 9 only the final outcome tells what was charged back
+            if version < 9 && stored.state == TransactionState::ChargedBack {
+                stored.charged_back = stored.settled;
+            }
+            // The clock is not saved, it follows from the timestamps like when replaying
+            if let Some(at) = stored.disputed_at {
+                ledger.open_disputes.insert((at, entry.tx));
+            }
+            ledger.clock = ledger.clock.max(stored.timestamp).max(stored.disputed_at);
+            ledger.storage.insert_transaction(entry.tx, stored)?;
         }
+        for tx_id in snapshot.processed_tx_ids {
+            ledger.storage.mark_processed(tx_id)?;
+        }
+        ledger.books = if version < 8 {
+            Books::opening(ledger.storage.accounts())
+        } else {
+            Books::from_balances(
+                snapshot
+                    .books
+                    .into_iter()
+                    .map(|entry| ((entry.account, entry.currency), entry.balance)),
+            )
+        };
+        Ok(ledger)
     }
 
     fn get_or_create_account(&mut self, client_id: ClientId) -> &mut Account {
-        self.accounts.entry(client_id).or_default()
+        self.storage.accounts_mut().entry(client_id).or_default()
     }
 
     pub fn get_account(&self, client_id: ClientId) -> Option<&Account> {
-        self.accounts.get(&client_id)
+        self.storage.accounts().get(&client_id)
+    }
+
+    /// Accounts in ascending client order.
+    pub fn accounts(&self) -> &BTreeMap<ClientId, Account> {
+        self.storage.accounts()
     }
 
-    pub fn accounts(&self) -> &HashMap<ClientId, Account> {
-        &self.accounts
+    /// Stored deposit, withdrawal or transfer with its dispute state.
+    pub fn get_transaction(&self, tx_id: TransactionId) -> io::Result<Option<StoredTransaction>> {
+        self.storage.transaction(tx_id)
     }
 
-    /// Returns true if the transaction was successfully processed.
+    /// Applies a transaction without a destination in the default currency;
+    /// see [`Ledger::process_transaction`].
     pub fn process(
         &mut self,
         tx_type: TransactionType,
         client_id: ClientId,
         tx_id: TransactionId,
         amount: Option<Amount>,
-    ) -> bool {
-        match tx_type {
-            TransactionType::Deposit => self.process_deposit(client_id, tx_id, amount),
-            TransactionType::Withdrawal => self.process_withdrawal(client_id, tx_id, amount),
-            TransactionType::Dispute => self.process_dispute(client_id, tx_id),
-            TransactionType::Resolve => self.process_resolve(client_id, tx_id),
-            TransactionType::Chargeback => self.process_chargeback(client_id, tx_id),
+    ) -> Result<ProcessOutcome, RejectionReason> {
+        self.process_transaction(&Transaction {
+            tx_type,
+            client_id,
+            tx_id,
+            amount,
+            destination: None,
+            currency: None,
+            timestamp: None,
+            reason: None,
+        })
+    }
+
+    /// Applies a transaction, reporting either its effect or why it was rejected.
+    ///
+    /// Once the storage failed, every transaction is rejected with that failure.
+    pub fn process_transaction(
+        &mut self,
+        transaction: &Transaction,
+    ) -> Result<ProcessOutcome, RejectionReason> {
+        if let Some(failure) = &self.storage_failure {
+            return Err(failure.clone());
         }
+        let result = self.apply_transaction(transaction);
+        if let Err(failure @ RejectionReason::Storage(_)) = &result {
+            self.storage_failure = Some(failure.clone());
+        }
+        result
     }
 
-    fn process_deposit(
+    fn apply_transaction(
         &mut self,
-        client_id: ClientId,
-        tx_id: TransactionId,
-        amount: Option<Amount>,
-    ) -> bool {
-        let amount = match amount {
-            Some(a) if !a.is_negative() => a,
-            _ => return false,
-        };
+        transaction: &Transaction,
+    ) -> Result<ProcessOutcome, RejectionReason> {
+        let Transaction {
+            tx_type,
+            client_id,
+            tx_id,
+            amount,
+            destination,
+            currency,
+            timestamp,
+            reason,
+        } = *transaction;
+        let outcome = match tx_type {
+            TransactionType::Deposit => {
+                self.process_deposit(client_id, tx_id, amount, currency, timestamp)
+            }
+            TransactionType::Withdrawal => {
+                self.process_withdrawal(client_id, tx_id, amount, currency, timestamp)
+            }
+            TransactionType::Dispute => {
+                // Checked at the row's own time, which the hold carries into the clock
+                let now = self.clock.max(timestamp);
+                self.process_dispute(client_id, tx_id, amount, currency, now)
+            }
+            TransactionType::Resolve => self.process_resolve(client_id, tx_id, amount, currency),
+            TransactionType::Chargeback => {
+                self.process_chargeback(client_id, tx_id, amount, currency)
+            }
+            TransactionType::Lock => self.process_lock(client_id, tx_id, reason),
+            TransactionType::Unlock => self.process_unlock(client_id, tx_id),
+            TransactionType::Transfer => {
+                self.process_transfer(client_id, tx_id, amount, destination, currency, timestamp)
+            }
+            TransactionType::Limit => self.process_limit(client_id, tx_id, amount),
+        }?;
+        self.charge_fee(transaction, outcome)?;
+
+        // Only accepted rows move the clock; the move is journaled when none of the
+        // row's events carried its timestamp, so a replay ends at the same time
+        if let Some(timestamp) = timestamp {
+            if self.clock < Some(timestamp) {
+                self.apply(&LedgerEvent::ClockAdvanced {
+                    client: client_id,
+                    tx: tx_id,
+                    timestamp,
+                })?;
+            }
+            self.resolve_expired_disputes()?;
+        }
+        Ok(outcome)
+    }
 
-        if self.processed_tx_ids.contains(&tx_id) {
-            return false;
+    /// Fee of a transaction by `client_id` moving `amount`, zero without a schedule.
+    fn fee(&self, tx_type: TransactionType, client_id: ClientId, amount: Amount) -> Amount {
+        match &self.fees {
+            Some(schedule) if schedule.house_account != client_id => schedule.fee(tx_type, amount),
+            _ => Amount::ZERO,
         }
+    }
 
-        let account = self.get_or_create_account(client_id);
-        if !account.deposit(amount) {
-            return false;
+    /// Rejects a withdrawal or transfer whose amount and fee together exceed the funds.
+    fn check_fee_covered(
+        &self,
+        tx_type: TransactionType,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        amount: Amount,
+        currency: Option<Currency>,
+    ) -> Result<(), RejectionReason> {
+        let fee = self.fee(tx_type, client_id, amount);
+        // Duplicates are left for `apply` to reject
+        if fee.is_zero() || self.storage.is_processed(tx_id)? {
+            return Ok(());
         }
+        let mut account = self.get_account(client_id).cloned().unwrap_or_default();
+        account.withdraw(currency, amount + fee)
+    }
 
-        self.processed_tx_ids.insert(tx_id);
-        self.deposits.insert(
-            tx_id,
-            StoredTransaction {
-                client_id,
-                amount,
-                state: TransactionState::None,
-            },
-        );
-        true
+    /// Posts the fee of an accepted transaction, in the currency of the funds it moved.
+    fn charge_fee(
+        &mut self,
+        transaction: &Transaction,
+        outcome: ProcessOutcome,
+    ) -> Result<(), RejectionReason> {
+        let Some(house) = self.fees.as_ref().map(|schedule| schedule.house_account) else {
+            return Ok(());
+        };
+        let base = outcome.amount().unwrap_or(Amount::ZERO);
+        let fee = self.fee(transaction.tx_type, transaction.client_id, base);
+        if fee.is_zero() {
+            return Ok(());
+        }
+        let currency = match outcome {
+            ProcessOutcome::Deposited(_)
+            | ProcessOutcome::Withdrawn(_)
+            | ProcessOutcome::Transferred(_) => transaction.currency,
+            ProcessOutcome::Held(_)
+            | ProcessOutcome::Released(_)
+            | ProcessOutcome::ChargedBack(_) => self.stored(transaction.tx_id)?.currency,
+            ProcessOutcome::Locked | ProcessOutcome::Unlocked | ProcessOutcome::LimitSet(_) => None,
+        };
+        self.apply(&LedgerEvent::FeeCharged {
+            client: transaction.client_id,
+            tx: transaction.tx_id,
+            fee_for: transaction.tx_type,
+            house,
+            amount: fee,
+            currency,
+        })
     }
 
-    fn process_withdrawal(
+    /// Sets the overdraft limit of `client_id` outside of any transaction, e.g. from a
+    /// client configuration file; the change is journaled without a tx id.
+    ///
+    /// Creates the account unless the limit is unchanged.
+    pub fn set_overdraft_limit(
         &mut self,
         client_id: ClientId,
-        tx_id: TransactionId,
-        amount: Option<Amount>,
-    ) -> bool {
-        let amount = match amount {
-            Some(a) if !a.is_negative() => a,
-            _ => return false,
+        limit: Amount,
+    ) -> Result<(), RejectionReason> {
+        let limit = validate_amount(Some(limit))?;
+        let current = self
+            .get_account(client_id)
+            .map_or(Amount::ZERO, |account| account.overdraft_limit);
+        if limit == current {
+            return Ok(());
+        }
+        self.apply(&LedgerEvent::OverdraftLimitSet {
+            client: client_id,
+            tx: None,
+            limit,
+        })
+    }
+
+    /// Applies a single state change; both processing and replay go through here.
+    fn apply(&mut self, event: &LedgerEvent) -> Result<(), RejectionReason> {
+        let pending_history = if self.record_history {
+            self.history_before(event)?
+        } else {
+            Vec::new()
         };
+        let posting = self.posting(event)?;
 
-        if self.processed_tx_ids.contains(&tx_id) {
-            return false;
+        match *event {
+            LedgerEvent::DepositApplied {
+                client,
+                tx,
+                amount,
+                currency,
+                timestamp,
+            } => {
+                if self.storage.is_processed(tx)? {
+                    return Err(RejectionReason::DuplicateTransaction(tx));
+                }
+                // Whether a locked account takes deposits is up to the policy
+                self.get_or_create_account(client).credit(currency, amount);
+                self.storage.mark_processed(tx)?;
+                let mut stored =
+                    StoredTransaction::new(client, TransactionKind::Deposit, amount, currency);
+                stored.timestamp = timestamp;
+                self.storage.insert_transaction(tx, stored)?;
+                self.clock = self.clock.max(timestamp);
+            }
+            LedgerEvent::WithdrawalApplied {
+                client,
+                tx,
+                amount,
+                currency,
+                timestamp,
+            } => {
+                if self.storage.is_processed(tx)? {
+                    return Err(RejectionReason::DuplicateTransaction(tx));
+                }
+                self.get_or_create_account(client)
+                    .withdraw(currency, amount)?;
+                self.storage.mark_processed(tx)?;
+                let mut stored =
+                    StoredTransaction::new(client, TransactionKind::Withdrawal, amount, currency);
+                stored.timestamp = timestamp;
+                self.storage.insert_transaction(tx, stored)?;
+                self.clock = self.clock.max(timestamp);
+            }
+            LedgerEvent::TransferApplied {
+                client,
+                tx,
+                destination,
+                amount,
+                currency,
+                timestamp,
+            } => {
+                if self.storage.is_processed(tx)? {
+                    return Err(RejectionReason::DuplicateTransaction(tx));
+                }
+                // Check both sides before touching either, so a transfer is all or nothing
+                if self.get_account(destination).is_some_and(|a| a.locked) {
+                    return Err(RejectionReason::DestinationLocked(destination));
+                }
+                self.get_or_create_account(client)
+                    .withdraw(currency, amount)?;
+                self.get_or_create_account(destination)
+                    .deposit(currency, amount)?;
+                self.storage.mark_processed(tx)?;
+                let mut stored =
+                    StoredTransaction::new(client, TransactionKind::Transfer, amount, currency);
+                stored.destination = Some(destination);
+                stored.timestamp = timestamp;
+                self.storage.insert_transaction(tx, stored)?;
+                self.clock = self.clock.max(timestamp);
+            }
+            LedgerEvent::FundsHeld {
+                client,
+                tx,
+                amount,
+                timestamp,
+            } => {
+                let mut stored = self.stored(tx)?;
+                stored.open_dispute(amount, timestamp);
+                self.storage.insert_transaction(tx, stored)?;
+                let (kind, destination, currency, disputed_at) = (
+                    stored.kind,
+                    stored.destination,
+                    stored.currency,
+                    stored.disputed_at,
+                );
+                if let Some(at) = disputed_at {
+                    self.open_disputes.insert((at, tx));
+                }
+                self.clock = self.clock.max(timestamp);
+                match kind {
+                    TransactionKind::Deposit => {
+                        self.get_or_create_account(client).hold(currency, amount)
+                    }
+                    TransactionKind::Withdrawal => self
+                        .get_or_create_account(client)
+                        .hold_refund(currency, amount),
+                    TransactionKind::Transfer => self
+                        .transfer_destination(destination, tx)?
+                        .hold(currency, amount),
+                }
+            }
+            LedgerEvent::FundsReleased { client, tx, amount } => {
+                let mut stored = self.stored(tx)?;
+                let disputed_at = stored.disputed_at;
+                stored.settle(amount, TransactionState::Resolved);
+                self.storage.insert_transaction(tx, stored)?;
+                let (kind, destination, currency, closed) = (
+                    stored.kind,
+                    stored.destination,
+                    stored.currency,
+                    stored.disputed_at.is_none(),
+                );
+                if let (Some(at), true) = (disputed_at, closed) {
+                    self.open_disputes.remove(&(at, tx));
+                }
+                match kind {
+                    TransactionKind::Deposit => {
+                        self.get_or_create_account(client).release(currency, amount)
+                    }
+                    TransactionKind::Withdrawal => self
+                        .get_or_create_account(client)
+                        .drop_hold(currency, amount),
+                    TransactionKind::Transfer => self
+                        .transfer_destination(destination, tx)?
+                        .release(currency, amount),
+                }
+            }
+            LedgerEvent::ChargedBack { client, tx, amount } => {
+                let mut stored = self.stored(tx)?;
+                let disputed_at = stored.disputed_at;
+                stored.settle(amount, TransactionState::ChargedBack);
+                self.storage.insert_transaction(tx, stored)?;
+                let (kind, destination, currency, closed) = (
+                    stored.kind,
+                    stored.destination,
+                    stored.currency,
+                    stored.disputed_at.is_none(),
+                );
+                if let (Some(at), true) = (disputed_at, closed) {
+                    self.open_disputes.remove(&(at, tx));
+                }
+                match kind {
+                    TransactionKind::Deposit => self
+                        .get_or_create_account(client)
+                        .chargeback(currency, amount),
+                    // The refund is credited without locking the account
+                    TransactionKind::Withdrawal => {
+                        self.get_or_create_account(client).release(currency, amount)
+                    }
+                    TransactionKind::Transfer => {
+                        self.transfer_destination(destination, tx)?
+                            .drop_hold(currency, amount);
+                        self.get_or_create_account(client).credit(currency, amount);
+                    }
+                }
+            }
+            LedgerEvent::AccountLocked { client, reason, .. } => {
+                self.get_or_create_account(client).lock(reason);
+            }
+            LedgerEvent::AccountUnlocked { client, .. } => {
+                self.get_or_create_account(client).unlock();
+            }
+            LedgerEvent::OverdraftLimitSet { client, limit, .. } => {
+                self.get_or_create_account(client).overdraft_limit = limit;
+            }
+            LedgerEvent::FeeCharged {
+                client,
+                house,
+                amount,
+                currency,
+                ..
+            } => {
+                self.get_or_create_account(client).charge(currency, amount);
+                self.get_or_create_account(house).credit(currency, amount);
+            }
+            LedgerEvent::ClockAdvanced { timestamp, .. } => {
+                self.clock = self.clock.max(Some(timestamp));
+            }
         }
 
-        let account = self.get_or_create_account(client_id);
-        if !account.withdraw(amount) {
-            return false;
+        if let Some(posting) = posting {
+            self.books.post(posting);
+        }
+        if self.record_events {
+            self.events.push(*event);
+        }
+        for (client_id, mut entry) in pending_history {
+            let account = self.get_or_create_account(client_id);
+            let after = account.balance(entry.currency);
+            entry.change = Balance {
+                available: after.available - entry.balance.available,
+                held: after.held - entry.balance.held,
+            };
+            entry.balance = after;
+            entry.locked = account.locked;
+            self.history.entry(client_id).or_default().push(entry);
         }
+        Ok(())
+    }
+
+    /// The move between book accounts `event` makes, `None` for events moving no funds.
+    fn posting(&self, event: &LedgerEvent) -> Result<Option<Posting>, RejectionReason> {
+        use BookAccount::{Available, ChargebackLoss, ExternalFunding, Held};
 
-        self.processed_tx_ids.insert(tx_id);
-        true
+        let (debit, credit, amount, currency) = match *event {
+            LedgerEvent::DepositApplied {
+                client,
+                amount,
+                currency,
+                ..
+            } => (ExternalFunding, Available(client), amount, currency),
+            LedgerEvent::WithdrawalApplied {
+                client,
+                amount,
+                currency,
+                ..
+            } => (Available(client), ExternalFunding, amount, currency),
+            LedgerEvent::TransferApplied {
+                client,
+                destination,
+                amount,
+                currency,
+                ..
+            } => (Available(client), Available(destination), amount, currency),
+            LedgerEvent::FundsHeld {
+                client, tx, amount, ..
+            }
+            | LedgerEvent::FundsReleased { client, tx, amount }
+            | LedgerEvent::ChargedBack { client, tx, amount } => {
+                let stored = self.stored(tx)?;
+                // Funds of a disputed transfer are held at its destination
+                let holder = match stored.kind {
+                    TransactionKind::Transfer => stored
+                        .destination
+                        .ok_or(RejectionReason::TransactionNotFound(tx))?,
+                    TransactionKind::Deposit | TransactionKind::Withdrawal => client,
+                };
+                let (debit, credit) = match (event, stored.kind) {
+                    (LedgerEvent::FundsHeld { .. }, TransactionKind::Withdrawal) => {
+                        (ChargebackLoss, Held(client))
+                    }
+                    (LedgerEvent::FundsHeld { .. }, _) => (Available(holder), Held(holder)),
+                    (LedgerEvent::FundsReleased { .. }, TransactionKind::Withdrawal) => {
+                        (Held(client), ChargebackLoss)
+                    }
+                    (LedgerEvent::FundsReleased { .. }, _) => (Held(holder), Available(holder)),
+                    (_, TransactionKind::Deposit) => (Held(client), ChargebackLoss),
+                    // Refunds a withdrawal, or returns a transfer to its sender
+                    _ => (Held(holder), Available(client)),
+                };
+                (debit, credit, amount, stored.currency)
+            }
+            LedgerEvent::FeeCharged {
+                client,
+                house,
+                amount,
+                currency,
+                ..
+            } => (Available(client), Available(house), amount, currency),
+            LedgerEvent::AccountLocked { .. }
+            | LedgerEvent::AccountUnlocked { .. }
+            | LedgerEvent::OverdraftLimitSet { .. }
+            | LedgerEvent::ClockAdvanced { .. } => return Ok(None),
+        };
+        Ok(Some(Posting {
+            debit,
+            credit,
+            amount,
+            currency,
+        }))
     }
 
-    fn process_dispute(&mut self, client_id: ClientId, tx_id: TransactionId) -> bool {
-        let stored = match self.deposits.get_mut(&tx_id) {
-            Some(s) => s,
-            None => return false,
+    /// History entries for every client `event` touches, holding their balances before it.
+    fn history_before(&self, event: &LedgerEvent) -> io::Result<Vec<(ClientId, HistoryEntry)>> {
+        let (activity, currency, destination) = match *event {
+            LedgerEvent::DepositApplied { currency, .. } => (Activity::Deposit, currency, None),
+            LedgerEvent::WithdrawalApplied { currency, .. } => {
+                (Activity::Withdrawal, currency, None)
+            }
+            LedgerEvent::TransferApplied {
+                currency,
+                destination,
+                ..
+            } => (Activity::TransferOut, currency, Some(destination)),
+            LedgerEvent::FundsHeld { tx, .. }
+            | LedgerEvent::FundsReleased { tx, .. }
+            | LedgerEvent::ChargedBack { tx, .. } => {
+                let activity = match event {
+                    LedgerEvent::FundsHeld { .. } => Activity::Dispute,
+                    LedgerEvent::FundsReleased { .. } => Activity::Resolve,
+                    _ => Activity::Chargeback,
+                };
+                // Unknown transactions are rejected by `apply`, so there is nothing to record
+                let Some(stored) = self.storage.transaction(tx)? else {
+                    return Ok(Vec::new());
+                };
+                (activity, stored.currency, stored.destination)
+            }
+            LedgerEvent::AccountLocked { .. } => (Activity::Lock, None, None),
+            LedgerEvent::AccountUnlocked { .. } => (Activity::Unlock, None, None),
+            // Limits and time do not move funds, so they are no activity of their own
+            LedgerEvent::OverdraftLimitSet { .. } | LedgerEvent::ClockAdvanced { .. } => {
+                return Ok(Vec::new())
+            }
+            LedgerEvent::FeeCharged {
+                currency, house, ..
+            } => (Activity::Fee, currency, Some(house)),
+        };
+        let Some(tx) = event.tx() else {
+            return Ok(Vec::new());
         };
 
-        if stored.client_id != client_id {
-            return false;
+        // Disputes of a transfer move the receiver's funds, so both clients see them
+        let destination = destination.map(|client_id| {
+            let activity = match activity {
+                Activity::TransferOut => Activity::TransferIn,
+                other => other,
+            };
+            (client_id, activity)
+        });
+        Ok(std::iter::once((event.client(), activity))
+            .chain(destination)
+            .map(|(client_id, activity)| {
+                let account = self.get_account(client_id);
+                let entry = HistoryEntry {
+                    tx,
+                    activity,
+                    currency,
+                    change: Balance::default(),
+                    balance: account.map(|a| a.balance(currency)).unwrap_or_default(),
+                    locked: account.is_some_and(|a| a.locked),
+                };
+                (client_id, entry)
+            })
+            .collect())
+    }
+
+    /// Resolves the disputes that have been open too long by the ledger's clock.
+    fn resolve_expired_disputes(&mut self) -> Result<(), RejectionReason> {
+        let (Some(after), Some(now)) = (self.auto_resolve_after, self.clock) else {
+            return Ok(());
+        };
+        while let Some(&(opened, tx_id)) = self.open_disputes.first() {
+            if now.seconds_since(opened) <= after {
+                break;
+            }
+            self.open_disputes.pop_first();
+            let Some(stored) = self.storage.transaction(tx_id)? else {
+                continue;
+            };
+            let event = LedgerEvent::FundsReleased {
+                client: stored.client_id,
+                tx: tx_id,
+                amount: stored.disputed,
+            };
+            // Only the storage can fail for a stored transaction, and the dispute is
+            // already unlisted
+            if let Err(failure @ RejectionReason::Storage(_)) = self.apply(&event) {
+                return Err(failure);
+            }
         }
+        Ok(())
+    }
+
+    fn transfer_destination(
+        &mut self,
+        destination: Option<ClientId>,
+        tx_id: TransactionId,
+    ) -> Result<&mut Account, RejectionReason> {
+        let destination = destination.ok_or(RejectionReason::TransactionNotFound(tx_id))?;
+        Ok(self.get_or_create_account(destination))
+    }
+
+    fn stored(&self, tx_id: TransactionId) -> Result<StoredTransaction, RejectionReason> {
+        self.storage
+            .transaction(tx_id)?
+            .ok_or(RejectionReason::TransactionNotFound(tx_id))
+    }
 
-        if stored.state != TransactionState::None {
-            return false;
+    fn process_deposit(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        amount: Option<Amount>,
+        currency: Option<Currency>,
+        timestamp: Option<Timestamp>,
+    ) -> Result<ProcessOutcome, RejectionReason> {
+        let amount = validate_amount(amount)?;
+        // Checked here rather than in `apply`, so journals replay under any policy;
+        // duplicates are left for `apply` to reject
+        if !self.policy.deposit_to_locked_accounts
+            && !self.storage.is_processed(tx_id)?
+            && self.get_account(client_id).is_some_and(|a| a.locked)
+        {
+            return Err(RejectionReason::AccountLocked);
         }
 
-        let amount = stored.amount;
-        stored.state = TransactionState::Disputed;
+        self.apply(&LedgerEvent::DepositApplied {
+            client: client_id,
+            tx: tx_id,
+            amount,
+            currency,
+            timestamp: timestamp.or(self.clock),
+        })?;
+        Ok(ProcessOutcome::Deposited(amount))
+    }
 
-        let account = self.get_or_create_account(client_id);
-        account.hold(amount);
+    fn process_withdrawal(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        amount: Option<Amount>,
+        currency: Option<Currency>,
+        timestamp: Option<Timestamp>,
+    ) -> Result<ProcessOutcome, RejectionReason> {
+        let amount = validate_amount(amount)?;
+        self.check_fee_covered(
+            TransactionType::Withdrawal,
+            client_id,
+            tx_id,
+            amount,
+            currency,
+        )?;
+
+        self.apply(&LedgerEvent::WithdrawalApplied {
+            client: client_id,
+            tx: tx_id,
+            amount,
+            currency,
+            timestamp: timestamp.or(self.clock),
+        })?;
+        Ok(ProcessOutcome::Withdrawn(amount))
+    }
 
-        true
+    /// Moves funds between two clients; the sender owns the stored transaction and its disputes.
+    fn process_transfer(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        amount: Option<Amount>,
+        destination: Option<ClientId>,
+        currency: Option<Currency>,
+        timestamp: Option<Timestamp>,
+    ) -> Result<ProcessOutcome, RejectionReason> {
+        let amount = validate_amount(amount)?;
+        let destination = destination.ok_or(RejectionReason::MissingDestination)?;
+        if destination == client_id {
+            return Err(RejectionReason::TransferToSelf);
+        }
+        self.check_fee_covered(
+            TransactionType::Transfer,
+            client_id,
+            tx_id,
+            amount,
+            currency,
+        )?;
+
+        self.apply(&LedgerEvent::TransferApplied {
+            client: client_id,
+            tx: tx_id,
+            destination,
+            amount,
+            currency,
+            timestamp: timestamp.or(self.clock),
+        })?;
+        Ok(ProcessOutcome::Transferred(amount))
     }
 
-    fn process_resolve(&mut self, client_id: ClientId, tx_id: TransactionId) -> bool {
-        let stored = match self.deposits.get_mut(&tx_id) {
-            Some(s) => s,
-            None => return false,
-        };
+    /// Looks up a stored transaction owned by `client_id` in one of the `allowed` states.
+    ///
+    /// A `currency` given on the dispute row must match the transaction's; without one the
+    /// row refers to the transaction in whatever currency it was made.
+    fn disputable(
+        &self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        allowed: &[TransactionState],
+        currency: Option<Currency>,
+    ) -> Result<StoredTransaction, RejectionReason> {
+        let stored = self.stored(tx_id)?;
 
         if stored.client_id != client_id {
-            return false;
+            return Err(RejectionReason::ClientMismatch {
+                tx_id,
+                owner: stored.client_id,
+                requested_by: client_id,
+            });
+        }
+
+        if currency.is_some() && currency != stored.currency {
+            return Err(RejectionReason::CurrencyMismatch {
+                tx_id,
+                expected: stored.currency,
+                requested: currency,
+            });
         }
 
-        if stored.state != TransactionState::Disputed {
-            return false;
+        if !allowed.contains(&stored.state) {
+            return Err(RejectionReason::InvalidState {
+                tx_id,
+                state: stored.state,
+            });
         }
 
-        let amount = stored.amount;
-        stored.state = TransactionState::Resolved;
+        Ok(stored)
+    }
 
-        let account = self.get_or_create_account(client_id);
-        account.release(amount);
+    /// A dispute without amount covers the whole transaction and needs it undisputed;
+    /// a partial one may be added to an open dispute as long as the total stays within
+    /// the transaction amount.
+    fn process_dispute(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        amount: Option<Amount>,
+        currency: Option<Currency>,
+        now: Option<Timestamp>,
+    ) -> Result<ProcessOutcome, RejectionReason> {
+        let policy = self.policy;
+        let allowed: &[TransactionState] = match (amount, policy.redispute_resolved) {
+            (Some(_), false) => &[TransactionState::None, TransactionState::Disputed],
+            (Some(_), true) => &[
+                TransactionState::None,
+                TransactionState::Disputed,
+                TransactionState::Resolved,
+            ],
+            (None, false) => &[TransactionState::None],
+            (None, true) => &[TransactionState::None, TransactionState::Resolved],
+        };
+        let stored = self.disputable(client_id, tx_id, allowed, currency)?;
+        if stored.kind == TransactionKind::Withdrawal && !policy.dispute_withdrawals {
+            return Err(RejectionReason::NotDisputable {
+                tx_id,
+                kind: stored.kind,
+            });
+        }
+        if !policy.dispute_locked_accounts && self.get_account(client_id).is_some_and(|a| a.locked)
+        {
+            return Err(RejectionReason::AccountLocked);
+        }
+        if let (Some(window), Some(made), Some(now)) = (self.dispute_window, stored.timestamp, now)
+        {
+            let age = now.seconds_since(made);
+            if age > window {
+                return Err(RejectionReason::DisputeWindowClosed { tx_id, age, window });
+            }
+        }
+        let disputable = if policy.redispute_resolved {
+            stored.redisputable()
+        } else {
+            stored.undisputed()
+        };
+        let amount = partial_amount(tx_id, amount, disputable)?;
+        if !policy.dispute_into_negative {
+            self.check_holdable(&stored, amount)?;
+        }
 
-        true
+        self.apply(&LedgerEvent::FundsHeld {
+            client: client_id,
+            tx: tx_id,
+            amount,
+            timestamp: now,
+        })?;
+        Ok(ProcessOutcome::Held(amount))
     }
 
-    fn process_chargeback(&mut self, client_id: ClientId, tx_id: TransactionId) -> bool {
-        let stored = match self.deposits.get_mut(&tx_id) {
-            Some(s) => s,
-            None => return false,
+    /// Rejects holding `amount` of a deposit or transfer beyond the available funds of
+    /// the account holding it; a disputed withdrawal holds a credit, never available funds.
+    fn check_holdable(
+        &self,
+        stored: &StoredTransaction,
+        amount: Amount,
+    ) -> Result<(), RejectionReason> {
+        let holder = match stored.kind {
+            TransactionKind::Deposit => stored.client_id,
+            TransactionKind::Transfer => stored.destination.unwrap_or(stored.client_id),
+            TransactionKind::Withdrawal => return Ok(()),
         };
+        let available = self.get_account(holder).map_or(Amount::ZERO, |account| {
+            account.balance(stored.currency).available
+        });
+        if available < amount {
+            return Err(RejectionReason::InsufficientFunds {
+                available,
+                requested: amount,
+                overdraft_limit: Amount::ZERO,
+            });
+        }
+        Ok(())
+    }
 
-        if stored.client_id != client_id {
-            return false;
+    fn process_resolve(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        amount: Option<Amount>,
+        currency: Option<Currency>,
+    ) -> Result<ProcessOutcome, RejectionReason> {
+        let stored = self.disputable(client_id, tx_id, &[TransactionState::Disputed], currency)?;
+        let amount = partial_amount(tx_id, amount, stored.disputed)?;
+
+        self.apply(&LedgerEvent::FundsReleased {
+            client: client_id,
+            tx: tx_id,
+            amount,
+        })?;
+        Ok(ProcessOutcome::Released(amount))
+    }
+
+    fn process_chargeback(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        amount: Option<Amount>,
+        currency: Option<Currency>,
+    ) -> Result<ProcessOutcome, RejectionReason> {
+        let stored = self.disputable(client_id, tx_id, &[TransactionState::Disputed], currency)?;
+        let kind = stored.kind;
+        let amount = partial_amount(tx_id, amount, stored.disputed)?;
+        // Charging back a withdrawal refunds the client, so only deposits lock
+        let locks = kind == TransactionKind::Deposit
+            && !self.get_account(client_id).is_some_and(|a| a.locked);
+
+        self.apply(&LedgerEvent::ChargedBack {
+            client: client_id,
+            tx: tx_id,
+            amount,
+        })?;
+        if locks {
+            self.apply(&LedgerEvent::AccountLocked {
+                client: client_id,
+                tx: tx_id,
+                reason: LockReason::CHARGEBACK,
+            })?;
         }
+        Ok(ProcessOutcome::ChargedBack(amount))
+    }
+
+    /// Freezes an existing account for `reason`, [`LockReason::ADMINISTRATIVE`] when the
+    /// row gives none; `tx_id` only identifies the request in events.
+    fn process_lock(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        reason: Option<LockReason>,
+    ) -> Result<ProcessOutcome, RejectionReason> {
+        let account = self
+            .get_account(client_id)
+            .ok_or(RejectionReason::AccountNotFound(client_id))?;
+        if account.locked {
+            return Err(RejectionReason::AccountLocked);
+        }
+
+        self.apply(&LedgerEvent::AccountLocked {
+            client: client_id,
+            tx: tx_id,
+            reason: reason.unwrap_or(LockReason::ADMINISTRATIVE),
+        })?;
+        Ok(ProcessOutcome::Locked)
+    }
 
-        if stored.state != TransactionState::Disputed {
-            return false;
+    /// Lifts a lock of any reason, including one set by a chargeback.
+    fn process_unlock(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+    ) -> Result<ProcessOutcome, RejectionReason> {
+        let account = self
+            .get_account(client_id)
+            .ok_or(RejectionReason::AccountNotFound(client_id))?;
+        if !account.locked {
+            return Err(RejectionReason::AccountNotLocked);
         }
 
-        let amount = stored.amount;
-        stored.state = TransactionState::ChargedBack;
+        self.apply(&LedgerEvent::AccountUnlocked {
+            client: client_id,
+            tx: tx_id,
+        })?;
+        Ok(ProcessOutcome::Unlocked)
+    }
+
+    /// Sets the overdraft limit, creating the account if needed so a credit line can be
+    /// granted before the first deposit; allowed on locked accounts.
+    fn process_limit(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        amount: Option<Amount>,
+    ) -> Result<ProcessOutcome, RejectionReason> {
+        let limit = validate_amount(amount)?;
+
+        self.apply(&LedgerEvent::OverdraftLimitSet {
+            client: client_id,
+            tx: Some(tx_id),
+            limit,
+        })?;
+        Ok(ProcessOutcome::LimitSet(limit))
+    }
+}
 
-        let account = self.get_or_create_account(client_id);
-        account.chargeback(amount);
+/// Resolves the amount of a dispute step: the whole `remaining` when omitted,
+/// otherwise the requested part, which must be positive and not exceed it.
+fn partial_amount(
+    tx_id: TransactionId,
+    requested: Option<Amount>,
+    remaining: Amount,
+) -> Result<Amount, RejectionReason> {
+    match requested {
+        None => Ok(remaining),
+        Some(a) if a.is_negative() || a.is_zero() => Err(RejectionReason::InvalidAmount(a)),
+        Some(a) if a > remaining => Err(RejectionReason::ExceedsDisputable {
+            tx_id,
+            requested: a,
+            remaining,
+        }),
+        Some(a) => Ok(a),
+    }
+}
 
-        true
+/// Deposits and withdrawals must carry a non-negative amount.
+fn validate_amount(amount: Option<Amount>) -> Result<Amount, RejectionReason> {
+    match amount {
+        Some(a) if a.is_negative() => Err(RejectionReason::NegativeAmount(a)),
+        Some(a) => Ok(a),
+        None => Err(RejectionReason::MissingAmount),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::domain::fees::FeeRule;
+    use rust_decimal::Decimal;
 
     fn amount(s: &str) -> Amount {
         Amount::from_str_rounded(s).expect("failed to parse amount")
@@ -203,12 +1436,14 @@ mod tests {
     #[test]
     fn test_deposit_creates_account() {
         let mut ledger = Ledger::new();
-        assert!(ledger.process(
-            TransactionType::Deposit,
-            client(1),
-            tx(1),
-            Some(amount("100"))
-        ));
+        assert!(ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100"))
+            )
+            .is_ok());
         let acc = ledger
             .get_account(client(1))
             .expect("client(1) account should exist after deposit");
@@ -219,13 +1454,17 @@ mod tests {
     #[test]
     fn test_dispute_deposit_holds_funds() {
         let mut ledger = Ledger::new();
-        ledger.process(
-            TransactionType::Deposit,
-            client(1),
-            tx(1),
-            Some(amount("100")),
-        );
-        assert!(ledger.process(TransactionType::Dispute, client(1), tx(1), None));
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100")),
+            )
+            .expect("transaction should be accepted");
+        assert!(ledger
+            .process(TransactionType::Dispute, client(1), tx(1), None)
+            .is_ok());
         let acc = ledger
             .get_account(client(1))
             .expect("client(1) account should exist after dispute");
@@ -237,14 +1476,20 @@ mod tests {
     #[test]
     fn test_resolve_releases_held_funds() {
         let mut ledger = Ledger::new();
-        ledger.process(
-            TransactionType::Deposit,
-            client(1),
-            tx(1),
-            Some(amount("100")),
-        );
-        ledger.process(TransactionType::Dispute, client(1), tx(1), None);
-        assert!(ledger.process(TransactionType::Resolve, client(1), tx(1), None));
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100")),
+            )
+            .expect("transaction should be accepted");
+        ledger
+            .process(TransactionType::Dispute, client(1), tx(1), None)
+            .expect("transaction should be accepted");
+        assert!(ledger
+            .process(TransactionType::Resolve, client(1), tx(1), None)
+            .is_ok());
         let acc = ledger
             .get_account(client(1))
             .expect("client(1) account should exist after resolve");
@@ -255,14 +1500,20 @@ mod tests {
     #[test]
     fn test_chargeback_locks_account() {
         let mut ledger = Ledger::new();
-        ledger.process(
-            TransactionType::Deposit,
-            client(1),
-            tx(1),
-            Some(amount("100")),
-        );
-        ledger.process(TransactionType::Dispute, client(1), tx(1), None);
-        assert!(ledger.process(TransactionType::Chargeback, client(1), tx(1), None));
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100")),
+            )
+            .expect("transaction should be accepted");
+        ledger
+            .process(TransactionType::Dispute, client(1), tx(1), None)
+            .expect("transaction should be accepted");
+        assert!(ledger
+            .process(TransactionType::Chargeback, client(1), tx(1), None)
+            .is_ok());
         let acc = ledger
             .get_account(client(1))
             .expect("client(1) account should exist after chargeback");
@@ -275,140 +1526,211 @@ mod tests {
     #[test]
     fn test_dispute_nonexistent_tx_ignored() {
         let mut ledger = Ledger::new();
-        ledger.process(
-            TransactionType::Deposit,
-            client(1),
-            tx(1),
-            Some(amount("100")),
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100")),
+            )
+            .expect("transaction should be accepted");
+        assert_eq!(
+            ledger.process(TransactionType::Dispute, client(1), tx(999), None),
+            Err(RejectionReason::TransactionNotFound(tx(999)))
         );
-        assert!(!ledger.process(TransactionType::Dispute, client(1), tx(999), None));
     }
 
     #[test]
     fn test_dispute_wrong_client_ignored() {
         let mut ledger = Ledger::new();
-        ledger.process(
-            TransactionType::Deposit,
-            client(1),
-            tx(1),
-            Some(amount("100")),
-        );
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100")),
+            )
+            .expect("transaction should be accepted");
         // Client 2 tries to dispute client 1's transaction
-        assert!(!ledger.process(TransactionType::Dispute, client(2), tx(1), None));
+        assert_eq!(
+            ledger.process(TransactionType::Dispute, client(2), tx(1), None),
+            Err(RejectionReason::ClientMismatch {
+                tx_id: tx(1),
+                owner: client(1),
+                requested_by: client(2),
+            })
+        );
     }
 
     #[test]
     fn test_dispute_withdrawal_ignored() {
         let mut ledger = Ledger::new();
-        ledger.process(
-            TransactionType::Deposit,
-            client(1),
-            tx(1),
-            Some(amount("100")),
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100")),
+            )
+            .expect("transaction should be accepted");
+        ledger
+            .process(
+                TransactionType::Withdrawal,
+                client(1),
+                tx(2),
+                Some(amount("50")),
+            )
+            .expect("transaction should be accepted");
+        // Withdrawal disputes are disabled by default, so disputing tx(2) should fail
+        assert_eq!(
+            ledger.process(TransactionType::Dispute, client(1), tx(2), None),
+            Err(RejectionReason::NotDisputable {
+                tx_id: tx(2),
+                kind: TransactionKind::Withdrawal,
+            })
         );
-        ledger.process(
-            TransactionType::Withdrawal,
-            client(1),
-            tx(2),
-            Some(amount("50")),
-        );
-        // Withdrawals aren't stored, so disputing tx(2) should fail
-        assert!(!ledger.process(TransactionType::Dispute, client(1), tx(2), None));
     }
 
     #[test]
     fn test_double_dispute_ignored() {
         let mut ledger = Ledger::new();
-        ledger.process(
-            TransactionType::Deposit,
-            client(1),
-            tx(1),
-            Some(amount("100")),
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100")),
+            )
+            .expect("transaction should be accepted");
+        assert!(ledger
+            .process(TransactionType::Dispute, client(1), tx(1), None)
+            .is_ok());
+        assert_eq!(
+            ledger.process(TransactionType::Dispute, client(1), tx(1), None),
+            Err(RejectionReason::InvalidState {
+                tx_id: tx(1),
+                state: TransactionState::Disputed,
+            })
         );
-        assert!(ledger.process(TransactionType::Dispute, client(1), tx(1), None));
-        assert!(!ledger.process(TransactionType::Dispute, client(1), tx(1), None));
     }
 
     #[test]
     fn test_locked_account_blocks_deposit_withdrawal() {
         let mut ledger = Ledger::new();
-        ledger.process(
-            TransactionType::Deposit,
-            client(1),
-            tx(1),
-            Some(amount("100")),
-        );
-        ledger.process(TransactionType::Dispute, client(1), tx(1), None);
-        ledger.process(TransactionType::Chargeback, client(1), tx(1), None);
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100")),
+            )
+            .expect("transaction should be accepted");
+        ledger
+            .process(TransactionType::Dispute, client(1), tx(1), None)
+            .expect("transaction should be accepted");
+        ledger
+            .process(TransactionType::Chargeback, client(1), tx(1), None)
+            .expect("transaction should be accepted");
 
         // Account now locked
-        assert!(!ledger.process(
-            TransactionType::Deposit,
-            client(1),
-            tx(2),
-            Some(amount("50"))
-        ));
-        assert!(!ledger.process(
-            TransactionType::Withdrawal,
-            client(1),
-            tx(3),
-            Some(amount("10"))
-        ));
+        assert_eq!(
+            ledger.process(
+                TransactionType::Deposit,
+                client(1),
+                tx(2),
+                Some(amount("50"))
+            ),
+            Err(RejectionReason::AccountLocked)
+        );
+        assert!(ledger
+            .process(
+                TransactionType::Withdrawal,
+                client(1),
+                tx(3),
+                Some(amount("10"))
+            )
+            .is_err());
     }
 
     #[test]
     fn test_locked_account_allows_dispute_resolve_chargeback() {
         let mut ledger = Ledger::new();
         // First deposit and lock via chargeback
-        ledger.process(
-            TransactionType::Deposit,
-            client(1),
-            tx(1),
-            Some(amount("100")),
-        );
-        ledger.process(TransactionType::Dispute, client(1), tx(1), None);
-        ledger.process(TransactionType::Chargeback, client(1), tx(1), None);
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100")),
+            )
+            .expect("transaction should be accepted");
+        ledger
+            .process(TransactionType::Dispute, client(1), tx(1), None)
+            .expect("transaction should be accepted");
+        ledger
+            .process(TransactionType::Chargeback, client(1), tx(1), None)
+            .expect("transaction should be accepted");
 
         // Second deposit before lock (simulating this by manually adjusting)
         // Actually, we need to deposit before the lock happens
         // Let's test with a fresh scenario
         let mut ledger2 = Ledger::new();
-        ledger2.process(
-            TransactionType::Deposit,
-            client(1),
-            tx(1),
-            Some(amount("100")),
-        );
-        ledger2.process(
-            TransactionType::Deposit,
-            client(1),
-            tx(2),
-            Some(amount("50")),
-        );
-        ledger2.process(TransactionType::Dispute, client(1), tx(1), None);
-        ledger2.process(TransactionType::Chargeback, client(1), tx(1), None);
+        ledger2
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100")),
+            )
+            .expect("transaction should be accepted");
+        ledger2
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(2),
+                Some(amount("50")),
+            )
+            .expect("transaction should be accepted");
+        ledger2
+            .process(TransactionType::Dispute, client(1), tx(1), None)
+            .expect("transaction should be accepted");
+        ledger2
+            .process(TransactionType::Chargeback, client(1), tx(1), None)
+            .expect("transaction should be accepted");
         // Account locked, but we can still dispute tx(2)
-        assert!(ledger2.process(TransactionType::Dispute, client(1), tx(2), None));
-        assert!(ledger2.process(TransactionType::Resolve, client(1), tx(2), None));
+        assert!(ledger2
+            .process(TransactionType::Dispute, client(1), tx(2), None)
+            .is_ok());
+        assert!(ledger2
+            .process(TransactionType::Resolve, client(1), tx(2), None)
+            .is_ok());
     }
 
     #[test]
     fn test_negative_balance_from_chargeback() {
         let mut ledger = Ledger::new();
-        ledger.process(
-            TransactionType::Deposit,
-            client(1),
-            tx(1),
-            Some(amount("100")),
-        );
-        ledger.process(
-            TransactionType::Withdrawal,
-            client(1),
-            tx(2),
-            Some(amount("80")),
-        );
-        ledger.process(TransactionType::Dispute, client(1), tx(1), None);
-        ledger.process(TransactionType::Chargeback, client(1), tx(1), None);
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100")),
+            )
+            .expect("transaction should be accepted");
+        ledger
+            .process(
+                TransactionType::Withdrawal,
+                client(1),
+                tx(2),
+                Some(amount("80")),
+            )
+            .expect("transaction should be accepted");
+        ledger
+            .process(TransactionType::Dispute, client(1), tx(1), None)
+            .expect("transaction should be accepted");
+        ledger
+            .process(TransactionType::Chargeback, client(1), tx(1), None)
+            .expect("transaction should be accepted");
 
         let acc = ledger
             .get_account(client(1))
@@ -421,19 +1743,24 @@ mod tests {
     #[test]
     fn test_idempotent_duplicate_tx_id() {
         let mut ledger = Ledger::new();
-        assert!(ledger.process(
-            TransactionType::Deposit,
-            client(1),
-            tx(1),
-            Some(amount("100"))
-        ));
+        assert!(ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100"))
+            )
+            .is_ok());
         // Same tx ID again should be ignored
-        assert!(!ledger.process(
-            TransactionType::Deposit,
-            client(1),
-            tx(1),
-            Some(amount("100"))
-        ));
+        assert_eq!(
+            ledger.process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100"))
+            ),
+            Err(RejectionReason::DuplicateTransaction(tx(1)))
+        );
         let acc = ledger
             .get_account(client(1))
             .expect("client(1) account should exist");
@@ -443,25 +1770,31 @@ mod tests {
     #[test]
     fn test_idempotent_duplicate_withdrawal_tx_id() {
         let mut ledger = Ledger::new();
-        ledger.process(
-            TransactionType::Deposit,
-            client(1),
-            tx(1),
-            Some(amount("100")),
-        );
-        assert!(ledger.process(
-            TransactionType::Withdrawal,
-            client(1),
-            tx(2),
-            Some(amount("30"))
-        ));
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100")),
+            )
+            .expect("transaction should be accepted");
+        assert!(ledger
+            .process(
+                TransactionType::Withdrawal,
+                client(1),
+                tx(2),
+                Some(amount("30"))
+            )
+            .is_ok());
         // Same withdrawal tx ID again should be ignored
-        assert!(!ledger.process(
-            TransactionType::Withdrawal,
-            client(1),
-            tx(2),
-            Some(amount("30"))
-        ));
+        assert!(ledger
+            .process(
+                TransactionType::Withdrawal,
+                client(1),
+                tx(2),
+                Some(amount("30"))
+            )
+            .is_err());
         let acc = ledger
             .get_account(client(1))
             .expect("client(1) account should exist");
@@ -472,19 +1805,23 @@ mod tests {
     fn test_idempotent_tx_id_shared_across_types() {
         // Same tx ID used for deposit, then attempted for withdrawal
         let mut ledger = Ledger::new();
-        assert!(ledger.process(
-            TransactionType::Deposit,
-            client(1),
-            tx(1),
-            Some(amount("100"))
-        ));
+        assert!(ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100"))
+            )
+            .is_ok());
         // Withdrawal with same tx ID should be rejected
-        assert!(!ledger.process(
-            TransactionType::Withdrawal,
-            client(1),
-            tx(1),
-            Some(amount("50"))
-        ));
+        assert!(ledger
+            .process(
+                TransactionType::Withdrawal,
+                client(1),
+                tx(1),
+                Some(amount("50"))
+            )
+            .is_err());
         let acc = ledger
             .get_account(client(1))
             .expect("client(1) account should exist");
@@ -494,80 +1831,114 @@ mod tests {
     #[test]
     fn test_resolve_non_disputed_tx_ignored() {
         let mut ledger = Ledger::new();
-        ledger.process(
-            TransactionType::Deposit,
-            client(1),
-            tx(1),
-            Some(amount("100")),
-        );
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100")),
+            )
+            .expect("transaction should be accepted");
         // Try to resolve without disputing first
-        assert!(!ledger.process(TransactionType::Resolve, client(1), tx(1), None));
+        assert_eq!(
+            ledger.process(TransactionType::Resolve, client(1), tx(1), None),
+            Err(RejectionReason::InvalidState {
+                tx_id: tx(1),
+                state: TransactionState::None,
+            })
+        );
     }
 
     #[test]
     fn test_chargeback_non_disputed_tx_ignored() {
         let mut ledger = Ledger::new();
-        ledger.process(
-            TransactionType::Deposit,
-            client(1),
-            tx(1),
-            Some(amount("100")),
-        );
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100")),
+            )
+            .expect("transaction should be accepted");
         // Try to chargeback without disputing first
-        assert!(!ledger.process(TransactionType::Chargeback, client(1), tx(1), None));
+        assert!(ledger
+            .process(TransactionType::Chargeback, client(1), tx(1), None)
+            .is_err());
     }
 
     #[test]
     fn test_re_dispute_after_resolve_ignored() {
         let mut ledger = Ledger::new();
-        ledger.process(
-            TransactionType::Deposit,
-            client(1),
-            tx(1),
-            Some(amount("100")),
-        );
-        ledger.process(TransactionType::Dispute, client(1), tx(1), None);
-        ledger.process(TransactionType::Resolve, client(1), tx(1), None);
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100")),
+            )
+            .expect("transaction should be accepted");
+        ledger
+            .process(TransactionType::Dispute, client(1), tx(1), None)
+            .expect("transaction should be accepted");
+        ledger
+            .process(TransactionType::Resolve, client(1), tx(1), None)
+            .expect("transaction should be accepted");
         // Try to dispute again
-        assert!(!ledger.process(TransactionType::Dispute, client(1), tx(1), None));
+        assert!(ledger
+            .process(TransactionType::Dispute, client(1), tx(1), None)
+            .is_err());
     }
 
     #[test]
     fn test_re_dispute_after_chargeback_ignored() {
         let mut ledger = Ledger::new();
-        ledger.process(
-            TransactionType::Deposit,
-            client(1),
-            tx(1),
-            Some(amount("100")),
-        );
-        ledger.process(TransactionType::Dispute, client(1), tx(1), None);
-        ledger.process(TransactionType::Chargeback, client(1), tx(1), None);
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100")),
+            )
+            .expect("transaction should be accepted");
+        ledger
+            .process(TransactionType::Dispute, client(1), tx(1), None)
+            .expect("transaction should be accepted");
+        ledger
+            .process(TransactionType::Chargeback, client(1), tx(1), None)
+            .expect("transaction should be accepted");
         // Try to dispute again
-        assert!(!ledger.process(TransactionType::Dispute, client(1), tx(1), None));
+        assert!(ledger
+            .process(TransactionType::Dispute, client(1), tx(1), None)
+            .is_err());
     }
 
     #[test]
     fn test_multiple_clients_isolated() {
         let mut ledger = Ledger::new();
-        ledger.process(
-            TransactionType::Deposit,
-            client(1),
-            tx(1),
-            Some(amount("100")),
-        );
-        ledger.process(
-            TransactionType::Deposit,
-            client(2),
-            tx(2),
-            Some(amount("200")),
-        );
-        ledger.process(
-            TransactionType::Withdrawal,
-            client(1),
-            tx(3),
-            Some(amount("50")),
-        );
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100")),
+            )
+            .expect("transaction should be accepted");
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(2),
+                tx(2),
+                Some(amount("200")),
+            )
+            .expect("transaction should be accepted");
+        ledger
+            .process(
+                TransactionType::Withdrawal,
+                client(1),
+                tx(3),
+                Some(amount("50")),
+            )
+            .expect("transaction should be accepted");
 
         let acc1 = ledger
             .get_account(client(1))
@@ -582,12 +1953,14 @@ mod tests {
     #[test]
     fn test_zero_amount_deposit() {
         let mut ledger = Ledger::new();
-        assert!(ledger.process(
-            TransactionType::Deposit,
-            client(1),
-            tx(1),
-            Some(amount("0"))
-        ));
+        assert!(ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("0"))
+            )
+            .is_ok());
         let acc = ledger
             .get_account(client(1))
             .expect("client(1) account should exist");
@@ -598,20 +1971,85 @@ mod tests {
     fn test_negative_amount_rejected() {
         let mut ledger = Ledger::new();
         let neg = Amount::new(rust_decimal::Decimal::new(-100, 0));
-        assert!(!ledger.process(TransactionType::Deposit, client(1), tx(1), Some(neg)));
+        assert_eq!(
+            ledger.process(TransactionType::Deposit, client(1), tx(1), Some(neg)),
+            Err(RejectionReason::NegativeAmount(neg))
+        );
     }
 
     #[test]
-    fn test_negative_withdrawal_rejected() {
+    fn test_missing_amount_rejected() {
         let mut ledger = Ledger::new();
-        ledger.process(
-            TransactionType::Deposit,
-            client(1),
-            tx(1),
-            Some(amount("100")),
+        assert_eq!(
+            ledger.process(TransactionType::Deposit, client(1), tx(1), None),
+            Err(RejectionReason::MissingAmount)
+        );
+        assert!(ledger.get_account(client(1)).is_none());
+    }
+
+    #[test]
+    fn test_insufficient_funds_reports_balance() {
+        let mut ledger = Ledger::new();
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("50")),
+            )
+            .expect("transaction should be accepted");
+        assert_eq!(
+            ledger.process(
+                TransactionType::Withdrawal,
+                client(1),
+                tx(2),
+                Some(amount("80"))
+            ),
+            Err(RejectionReason::InsufficientFunds {
+                available: amount("50"),
+                requested: amount("80"),
+                overdraft_limit: Amount::ZERO,
+            })
+        );
+    }
+
+    #[test]
+    fn test_outcome_reports_applied_amount() {
+        let mut ledger = Ledger::new();
+        assert_eq!(
+            ledger.process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100"))
+            ),
+            Ok(ProcessOutcome::Deposited(amount("100")))
         );
+        assert_eq!(
+            ledger.process(TransactionType::Dispute, client(1), tx(1), None),
+            Ok(ProcessOutcome::Held(amount("100")))
+        );
+        assert_eq!(
+            ledger.process(TransactionType::Chargeback, client(1), tx(1), None),
+            Ok(ProcessOutcome::ChargedBack(amount("100")))
+        );
+    }
+
+    #[test]
+    fn test_negative_withdrawal_rejected() {
+        let mut ledger = Ledger::new();
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100")),
+            )
+            .expect("transaction should be accepted");
         let neg = Amount::new(rust_decimal::Decimal::new(-100, 0));
-        assert!(!ledger.process(TransactionType::Withdrawal, client(1), tx(2), Some(neg)));
+        assert!(ledger
+            .process(TransactionType::Withdrawal, client(1), tx(2), Some(neg))
+            .is_err());
         let acc = ledger
             .get_account(client(1))
             .expect("client(1) account should exist");
@@ -621,56 +2059,86 @@ mod tests {
     #[test]
     fn test_re_resolve_same_tx_ignored() {
         let mut ledger = Ledger::new();
-        ledger.process(
-            TransactionType::Deposit,
-            client(1),
-            tx(1),
-            Some(amount("100")),
-        );
-        ledger.process(TransactionType::Dispute, client(1), tx(1), None);
-        assert!(ledger.process(TransactionType::Resolve, client(1), tx(1), None));
-        assert!(!ledger.process(TransactionType::Resolve, client(1), tx(1), None));
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100")),
+            )
+            .expect("transaction should be accepted");
+        ledger
+            .process(TransactionType::Dispute, client(1), tx(1), None)
+            .expect("transaction should be accepted");
+        assert!(ledger
+            .process(TransactionType::Resolve, client(1), tx(1), None)
+            .is_ok());
+        assert!(ledger
+            .process(TransactionType::Resolve, client(1), tx(1), None)
+            .is_err());
     }
 
     #[test]
     fn test_re_chargeback_same_tx_ignored() {
         let mut ledger = Ledger::new();
-        ledger.process(
-            TransactionType::Deposit,
-            client(1),
-            tx(1),
-            Some(amount("100")),
-        );
-        ledger.process(TransactionType::Dispute, client(1), tx(1), None);
-        assert!(ledger.process(TransactionType::Chargeback, client(1), tx(1), None));
-        assert!(!ledger.process(TransactionType::Chargeback, client(1), tx(1), None));
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100")),
+            )
+            .expect("transaction should be accepted");
+        ledger
+            .process(TransactionType::Dispute, client(1), tx(1), None)
+            .expect("transaction should be accepted");
+        assert!(ledger
+            .process(TransactionType::Chargeback, client(1), tx(1), None)
+            .is_ok());
+        assert!(ledger
+            .process(TransactionType::Chargeback, client(1), tx(1), None)
+            .is_err());
     }
 
     #[test]
     fn test_chargeback_then_resolve_ignored() {
         let mut ledger = Ledger::new();
-        ledger.process(
-            TransactionType::Deposit,
-            client(1),
-            tx(1),
-            Some(amount("100")),
-        );
-        ledger.process(TransactionType::Dispute, client(1), tx(1), None);
-        ledger.process(TransactionType::Chargeback, client(1), tx(1), None);
-        assert!(!ledger.process(TransactionType::Resolve, client(1), tx(1), None));
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100")),
+            )
+            .expect("transaction should be accepted");
+        ledger
+            .process(TransactionType::Dispute, client(1), tx(1), None)
+            .expect("transaction should be accepted");
+        ledger
+            .process(TransactionType::Chargeback, client(1), tx(1), None)
+            .expect("transaction should be accepted");
+        assert!(ledger
+            .process(TransactionType::Resolve, client(1), tx(1), None)
+            .is_err());
     }
 
     #[test]
     fn test_resolve_wrong_client_ignored() {
         let mut ledger = Ledger::new();
-        ledger.process(
-            TransactionType::Deposit,
-            client(1),
-            tx(1),
-            Some(amount("100")),
-        );
-        ledger.process(TransactionType::Dispute, client(1), tx(1), None);
-        assert!(!ledger.process(TransactionType::Resolve, client(2), tx(1), None));
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100")),
+            )
+            .expect("transaction should be accepted");
+        ledger
+            .process(TransactionType::Dispute, client(1), tx(1), None)
+            .expect("transaction should be accepted");
+        assert!(ledger
+            .process(TransactionType::Resolve, client(2), tx(1), None)
+            .is_err());
         let acc = ledger
             .get_account(client(1))
             .expect("client(1) account should exist");
@@ -680,14 +2148,20 @@ mod tests {
     #[test]
     fn test_chargeback_wrong_client_ignored() {
         let mut ledger = Ledger::new();
-        ledger.process(
-            TransactionType::Deposit,
-            client(1),
-            tx(1),
-            Some(amount("100")),
-        );
-        ledger.process(TransactionType::Dispute, client(1), tx(1), None);
-        assert!(!ledger.process(TransactionType::Chargeback, client(2), tx(1), None));
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100")),
+            )
+            .expect("transaction should be accepted");
+        ledger
+            .process(TransactionType::Dispute, client(1), tx(1), None)
+            .expect("transaction should be accepted");
+        assert!(ledger
+            .process(TransactionType::Chargeback, client(2), tx(1), None)
+            .is_err());
         let acc = ledger
             .get_account(client(1))
             .expect("client(1) account should exist");
@@ -697,39 +2171,1796 @@ mod tests {
     #[test]
     fn test_zero_amount_withdrawal() {
         let mut ledger = Ledger::new();
-        ledger.process(
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100")),
+            )
+            .expect("transaction should be accepted");
+        assert!(ledger
+            .process(
+                TransactionType::Withdrawal,
+                client(1),
+                tx(2),
+                Some(amount("0"))
+            )
+            .is_ok());
+        let acc = ledger
+            .get_account(client(1))
+            .expect("client(1) account should exist");
+        assert_eq!(acc.available, amount("100"));
+    }
+
+    #[test]
+    fn test_held_never_negative_invariant() {
+        let mut ledger = Ledger::new();
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100")),
+            )
+            .expect("transaction should be accepted");
+        ledger
+            .process(TransactionType::Dispute, client(1), tx(1), None)
+            .expect("transaction should be accepted");
+        ledger
+            .process(TransactionType::Resolve, client(1), tx(1), None)
+            .expect("transaction should be accepted");
+        let acc = ledger
+            .get_account(client(1))
+            .expect("client(1) account should exist");
+        assert!(!acc.held.is_negative());
+        assert_eq!(acc.held, amount("0"));
+    }
+
+    #[test]
+    fn test_event_log_records_applied_changes_only() {
+        let mut ledger = Ledger::new().with_event_log();
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100")),
+            )
+            .expect("transaction should be accepted");
+        assert!(ledger
+            .process(
+                TransactionType::Withdrawal,
+                client(1),
+                tx(2),
+                Some(amount("500"))
+            )
+            .is_err());
+        ledger
+            .process(TransactionType::Dispute, client(1), tx(1), None)
+            .expect("transaction should be accepted");
+        ledger
+            .process(TransactionType::Chargeback, client(1), tx(1), None)
+            .expect("transaction should be accepted");
+
+        let events: Vec<_> = ledger.drain_events().collect();
+        assert_eq!(
+            events,
+            vec![
+                LedgerEvent::DepositApplied {
+                    client: client(1),
+                    tx: tx(1),
+                    amount: amount("100"),
+                    currency: None,
+                    timestamp: None,
+                },
+                LedgerEvent::FundsHeld {
+                    client: client(1),
+                    tx: tx(1),
+                    amount: amount("100"),
+                    timestamp: None,
+                },
+                LedgerEvent::ChargedBack {
+                    client: client(1),
+                    tx: tx(1),
+                    amount: amount("100"),
+                },
+                LedgerEvent::AccountLocked {
+                    client: client(1),
+                    tx: tx(1),
+                    reason: LockReason::CHARGEBACK,
+                },
+            ]
+        );
+        assert_eq!(ledger.drain_events().count(), 0);
+    }
+
+    #[test]
+    fn test_event_log_disabled_by_default() {
+        let mut ledger = Ledger::new();
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100")),
+            )
+            .expect("transaction should be accepted");
+        assert_eq!(ledger.drain_events().count(), 0);
+    }
+
+    #[test]
+    fn test_replay_rebuilds_identical_state() {
+        let mut ledger = Ledger::new().with_event_log();
+        let _ = ledger.process(
             TransactionType::Deposit,
             client(1),
             tx(1),
             Some(amount("100")),
         );
-        assert!(ledger.process(
-            TransactionType::Withdrawal,
+        let _ = ledger.process(
+            TransactionType::Deposit,
             client(1),
             tx(2),
-            Some(amount("0"))
-        ));
-        let acc = ledger
-            .get_account(client(1))
-            .expect("client(1) account should exist");
-        assert_eq!(acc.available, amount("100"));
+            Some(amount("50")),
+        );
+        let _ = ledger.process(
+            TransactionType::Withdrawal,
+            client(1),
+            tx(3),
+            Some(amount("20")),
+        );
+        let _ = ledger.process(
+            TransactionType::Deposit,
+            client(2),
+            tx(4),
+            Some(amount("10")),
+        );
+        let _ = ledger.process(TransactionType::Dispute, client(1), tx(1), None);
+        let _ = ledger.process(TransactionType::Chargeback, client(1), tx(1), None);
+        let _ = ledger.process(TransactionType::Dispute, client(2), tx(4), None);
+        let _ = ledger.process(TransactionType::Resolve, client(2), tx(4), None);
+
+        let mut replayed =
+            Ledger::replay(ledger.drain_events()).expect("journal should replay cleanly");
+        assert_eq!(replayed.accounts(), ledger.accounts());
+
+        // Idempotency set and dispute states are restored as well
+        assert_eq!(
+            replayed.process(
+                TransactionType::Deposit,
+                client(3),
+                tx(2),
+                Some(amount("1"))
+            ),
+            Err(RejectionReason::DuplicateTransaction(tx(2)))
+        );
+        assert_eq!(
+            replayed.process(TransactionType::Dispute, client(2), tx(4), None),
+            Err(RejectionReason::InvalidState {
+                tx_id: tx(4),
+                state: TransactionState::Resolved,
+            })
+        );
     }
 
     #[test]
-    fn test_held_never_negative_invariant() {
+    fn test_replay_rejects_inconsistent_journal() {
+        let events = vec![
+            LedgerEvent::DepositApplied {
+                client: client(1),
+                tx: tx(1),
+                amount: amount("10"),
+                currency: None,
+                timestamp: None,
+            },
+            LedgerEvent::FundsHeld {
+                client: client(1),
+                tx: tx(9),
+                amount: amount("10"),
+                timestamp: None,
+            },
+        ];
+        let err = Ledger::replay(events).unwrap_err();
+        assert_eq!(err.index, 1);
+        assert_eq!(err.reason, RejectionReason::TransactionNotFound(tx(9)));
+    }
+
+    #[test]
+    fn test_snapshot_round_trip_preserves_disputes_and_idempotency() {
         let mut ledger = Ledger::new();
-        ledger.process(
+        let _ = ledger.process(
             TransactionType::Deposit,
             client(1),
             tx(1),
             Some(amount("100")),
         );
-        ledger.process(TransactionType::Dispute, client(1), tx(1), None);
-        ledger.process(TransactionType::Resolve, client(1), tx(1), None);
+        let _ = ledger.process(
+            TransactionType::Withdrawal,
+            client(1),
+            tx(2),
+            Some(amount("30")),
+        );
+        let _ = ledger.process(
+            TransactionType::Deposit,
+            client(2),
+            tx(3),
+            Some(amount("5")),
+        );
+        let _ = ledger.process(TransactionType::Dispute, client(2), tx(3), None);
+
+        let mut restored =
+            Ledger::from_snapshot(ledger.snapshot().expect("failed to take snapshot"))
+                .expect("snapshot should restore");
+        assert_eq!(restored.accounts(), ledger.accounts());
+
+        // Withdrawal ids stay reserved and open disputes can still be settled
+        assert_eq!(
+            restored.process(
+                TransactionType::Deposit,
+                client(1),
+                tx(2),
+                Some(amount("1"))
+            ),
+            Err(RejectionReason::DuplicateTransaction(tx(2)))
+        );
+        assert_eq!(
+            restored.process(TransactionType::Resolve, client(2), tx(3), None),
+            Ok(ProcessOutcome::Released(amount("5")))
+        );
+        assert!(restored
+            .process(TransactionType::Dispute, client(1), tx(1), None)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_snapshot_rejects_unknown_version() {
+        let mut snapshot = Ledger::new().snapshot().expect("failed to take snapshot");
+        snapshot.version = SNAPSHOT_VERSION + 1;
+        assert_eq!(
+            Ledger::from_snapshot(snapshot).unwrap_err(),
+            SnapshotError::UnsupportedVersion(SNAPSHOT_VERSION + 1)
+        );
+    }
+
+    fn ledger_with_withdrawal() -> Ledger {
+        let mut ledger = Ledger::new().with_withdrawal_disputes();
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("100")),
+            )
+            .expect("transaction should be accepted");
+        ledger
+            .process(
+                TransactionType::Withdrawal,
+                client(1),
+                tx(2),
+                Some(amount("40")),
+            )
+            .expect("transaction should be accepted");
+        ledger
+    }
+
+    #[test]
+    fn test_withdrawal_dispute_holds_provisional_credit() {
+        let mut ledger = ledger_with_withdrawal();
+        assert_eq!(
+            ledger.process(TransactionType::Dispute, client(1), tx(2), None),
+            Ok(ProcessOutcome::Held(amount("40")))
+        );
         let acc = ledger
             .get_account(client(1))
             .expect("client(1) account should exist");
-        assert!(!acc.held.is_negative());
+        assert_eq!(acc.available, amount("60"));
+        assert_eq!(acc.held, amount("40"));
+        assert_eq!(acc.total(), amount("100"));
+    }
+
+    #[test]
+    fn test_withdrawal_dispute_resolve_keeps_withdrawal() {
+        let mut ledger = ledger_with_withdrawal();
+        ledger
+            .process(TransactionType::Dispute, client(1), tx(2), None)
+            .expect("transaction should be accepted");
+        assert!(ledger
+            .process(TransactionType::Resolve, client(1), tx(2), None)
+            .is_ok());
+        let acc = ledger
+            .get_account(client(1))
+            .expect("client(1) account should exist");
+        assert_eq!(acc.available, amount("60"));
+        assert_eq!(acc.held, amount("0"));
+        assert_eq!(acc.total(), amount("60"));
+    }
+
+    #[test]
+    fn test_withdrawal_chargeback_credits_funds_back() {
+        let mut ledger = ledger_with_withdrawal().with_event_log();
+        ledger
+            .process(TransactionType::Dispute, client(1), tx(2), None)
+            .expect("transaction should be accepted");
+        assert_eq!(
+            ledger.process(TransactionType::Chargeback, client(1), tx(2), None),
+            Ok(ProcessOutcome::ChargedBack(amount("40")))
+        );
+        let acc = ledger
+            .get_account(client(1))
+            .expect("client(1) account should exist");
+        assert_eq!(acc.available, amount("100"));
         assert_eq!(acc.held, amount("0"));
+        assert!(!acc.locked);
+        assert!(!ledger
+            .drain_events()
+            .any(|e| matches!(e, LedgerEvent::AccountLocked { .. })));
+    }
+
+    #[test]
+    fn test_withdrawal_dispute_wrong_client_rejected() {
+        let mut ledger = ledger_with_withdrawal();
+        assert!(matches!(
+            ledger.process(TransactionType::Dispute, client(2), tx(2), None),
+            Err(RejectionReason::ClientMismatch { .. })
+        ));
+    }
+
+    fn ledger_with_deposit(value: &str) -> Ledger {
+        let mut ledger = Ledger::new();
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount(value)),
+            )
+            .expect("transaction should be accepted");
+        ledger
+    }
+
+    #[test]
+    fn test_partial_disputes_accumulate_up_to_amount() {
+        let mut ledger = ledger_with_deposit("100");
+        assert_eq!(
+            ledger.process(
+                TransactionType::Dispute,
+                client(1),
+                tx(1),
+                Some(amount("30"))
+            ),
+            Ok(ProcessOutcome::Held(amount("30")))
+        );
+        assert_eq!(
+            ledger.process(
+                TransactionType::Dispute,
+                client(1),
+                tx(1),
+                Some(amount("50"))
+            ),
+            Ok(ProcessOutcome::Held(amount("50")))
+        );
+        assert_eq!(
+            ledger.process(
+                TransactionType::Dispute,
+                client(1),
+                tx(1),
+                Some(amount("25"))
+            ),
+            Err(RejectionReason::ExceedsDisputable {
+                tx_id: tx(1),
+                requested: amount("25"),
+                remaining: amount("20"),
+            })
+        );
+        let acc = ledger
+            .get_account(client(1))
+            .expect("client(1) account should exist");
+        assert_eq!(acc.available, amount("20"));
+        assert_eq!(acc.held, amount("80"));
+    }
+
+    #[test]
+    fn test_full_dispute_rejected_while_partially_disputed() {
+        let mut ledger = ledger_with_deposit("100");
+        ledger
+            .process(
+                TransactionType::Dispute,
+                client(1),
+                tx(1),
+                Some(amount("30")),
+            )
+            .expect("transaction should be accepted");
+        assert!(matches!(
+            ledger.process(TransactionType::Dispute, client(1), tx(1), None),
+            Err(RejectionReason::InvalidState { .. })
+        ));
+    }
+
+    #[test]
+    fn test_partial_resolve_keeps_dispute_open() {
+        let mut ledger = ledger_with_deposit("100");
+        ledger
+            .process(
+                TransactionType::Dispute,
+                client(1),
+                tx(1),
+                Some(amount("60")),
+            )
+            .expect("transaction should be accepted");
+        assert_eq!(
+            ledger.process(
+                TransactionType::Resolve,
+                client(1),
+                tx(1),
+                Some(amount("20"))
+            ),
+            Ok(ProcessOutcome::Released(amount("20")))
+        );
+        assert_eq!(
+            ledger
+                .get_transaction(tx(1))
+                .expect("failed to read storage")
+                .map(|t| t.state),
+            Some(TransactionState::Disputed)
+        );
+        assert_eq!(
+            ledger.process(TransactionType::Resolve, client(1), tx(1), None),
+            Ok(ProcessOutcome::Released(amount("40")))
+        );
+        assert_eq!(
+            ledger
+                .get_transaction(tx(1))
+                .expect("failed to read storage")
+                .map(|t| t.state),
+            Some(TransactionState::Resolved)
+        );
+        let acc = ledger
+            .get_account(client(1))
+            .expect("client(1) account should exist");
+        assert_eq!(acc.available, amount("100"));
+        assert_eq!(acc.held, amount("0"));
+    }
+
+    #[test]
+    fn test_resolve_more_than_disputed_rejected() {
+        let mut ledger = ledger_with_deposit("100");
+        ledger
+            .process(
+                TransactionType::Dispute,
+                client(1),
+                tx(1),
+                Some(amount("10")),
+            )
+            .expect("transaction should be accepted");
+        assert!(matches!(
+            ledger.process(
+                TransactionType::Resolve,
+                client(1),
+                tx(1),
+                Some(amount("10.0001"))
+            ),
+            Err(RejectionReason::ExceedsDisputable { .. })
+        ));
+    }
+
+    #[test]
+    fn test_partial_chargeback_locks_and_keeps_remainder() {
+        let mut ledger = ledger_with_deposit("100");
+        ledger
+            .process(
+                TransactionType::Dispute,
+                client(1),
+                tx(1),
+                Some(amount("40")),
+            )
+            .expect("transaction should be accepted");
+        assert_eq!(
+            ledger.process(
+                TransactionType::Chargeback,
+                client(1),
+                tx(1),
+                Some(amount("15"))
+            ),
+            Ok(ProcessOutcome::ChargedBack(amount("15")))
+        );
+        let acc = ledger
+            .get_account(client(1))
+            .expect("client(1) account should exist");
+        assert_eq!(acc.available, amount("60"));
+        assert_eq!(acc.held, amount("25"));
+        assert!(acc.locked);
+    }
+
+    #[test]
+    fn test_dispute_amount_must_be_positive() {
+        let mut ledger = ledger_with_deposit("100");
+        assert_eq!(
+            ledger.process(
+                TransactionType::Dispute,
+                client(1),
+                tx(1),
+                Some(amount("-1"))
+            ),
+            Err(RejectionReason::InvalidAmount(amount("-1")))
+        );
+        let zero = ledger.process(
+            TransactionType::Dispute,
+            client(1),
+            tx(1),
+            Some(Amount::ZERO),
+        );
+        assert_eq!(zero, Err(RejectionReason::InvalidAmount(Amount::ZERO)));
+        assert_eq!(zero.unwrap_err().category(), "invalid_amount");
+
+        // Nothing was opened, so the whole deposit can still be disputed
+        assert!(ledger
+            .process(TransactionType::Dispute, client(1), tx(1), None)
+            .is_ok());
+        assert!(ledger.audit().expect("failed to audit").is_empty());
+    }
+
+    #[test]
+    fn test_partial_disputes_replay_identically() {
+        let mut ledger = ledger_with_deposit("100").with_event_log();
+        ledger
+            .process(
+                TransactionType::Dispute,
+                client(1),
+                tx(1),
+                Some(amount("30")),
+            )
+            .expect("transaction should be accepted");
+        ledger
+            .process(
+                TransactionType::Chargeback,
+                client(1),
+                tx(1),
+                Some(amount("10")),
+            )
+            .expect("transaction should be accepted");
+        let mut events = vec![LedgerEvent::DepositApplied {
+            client: client(1),
+            tx: tx(1),
+            amount: amount("100"),
+            currency: None,
+            timestamp: None,
+        }];
+        events.extend(ledger.drain_events());
+        let replayed = Ledger::replay(events).expect("replay should succeed");
+        assert_eq!(
+            replayed.snapshot().expect("failed to take snapshot"),
+            ledger.snapshot().expect("failed to take snapshot")
+        );
+    }
+
+    #[test]
+    fn test_admin_lock_blocks_deposit_but_allows_dispute() {
+        let mut ledger = ledger_with_deposit("100");
+        assert_eq!(
+            ledger.process(TransactionType::Lock, client(1), tx(2), None),
+            Ok(ProcessOutcome::Locked)
+        );
+        assert_eq!(
+            ledger.process(
+                TransactionType::Deposit,
+                client(1),
+                tx(3),
+                Some(amount("5"))
+            ),
+            Err(RejectionReason::AccountLocked)
+        );
+        assert!(ledger
+            .process(TransactionType::Dispute, client(1), tx(1), None)
+            .is_ok());
+        let acc = ledger
+            .get_account(client(1))
+            .expect("client(1) account should exist");
+        assert_eq!(acc.lock_reason, Some(LockReason::ADMINISTRATIVE));
+    }
+
+    #[test]
+    fn test_lock_keeps_the_row_reason() {
+        let mut ledger = ledger_with_deposit("100").with_event_log();
+        let reason = "fraud review".parse().ok();
+        assert_eq!(
+            ledger.process_transaction(&Transaction {
+                tx_type: TransactionType::Lock,
+                client_id: client(1),
+                tx_id: tx(2),
+                amount: None,
+                destination: None,
+                currency: None,
+                timestamp: None,
+                reason,
+            }),
+            Ok(ProcessOutcome::Locked)
+        );
+        let acc = ledger
+            .get_account(client(1))
+            .expect("client(1) account should exist");
+        assert_eq!(acc.lock_reason, reason);
+        assert_eq!(
+            ledger.drain_events().next_back(),
+            Some(LedgerEvent::AccountLocked {
+                client: client(1),
+                tx: tx(2),
+                reason: reason.expect("reason should parse"),
+            })
+        );
+    }
+
+    #[test]
+    fn test_lock_rejections() {
+        let mut ledger = ledger_with_deposit("100");
+        assert_eq!(
+            ledger.process(TransactionType::Lock, client(2), tx(2), None),
+            Err(RejectionReason::AccountNotFound(client(2)))
+        );
+        assert_eq!(
+            ledger.process(TransactionType::Unlock, client(1), tx(2), None),
+            Err(RejectionReason::AccountNotLocked)
+        );
+        ledger
+            .process(TransactionType::Lock, client(1), tx(2), None)
+            .expect("transaction should be accepted");
+        assert_eq!(
+            ledger.process(TransactionType::Lock, client(1), tx(3), None),
+            Err(RejectionReason::AccountLocked)
+        );
+        assert!(!ledger.accounts().contains_key(&client(2)));
+    }
+
+    #[test]
+    fn test_limit_allows_overdraft() {
+        let mut ledger = ledger_with_deposit("100").with_event_log();
+        assert_eq!(
+            ledger.process(TransactionType::Limit, client(2), tx(2), Some(amount("50"))),
+            Ok(ProcessOutcome::LimitSet(amount("50")))
+        );
+        assert_eq!(
+            ledger.process(TransactionType::Limit, client(2), tx(3), None),
+            Err(RejectionReason::MissingAmount)
+        );
+        ledger
+            .process(
+                TransactionType::Withdrawal,
+                client(2),
+                tx(4),
+                Some(amount("30")),
+            )
+            .expect("transaction should be accepted");
+        let transfer = Transaction {
+            tx_type: TransactionType::Transfer,
+            client_id: client(2),
+            tx_id: tx(5),
+            amount: Some(amount("20.0001")),
+            destination: Some(client(1)),
+            currency: None,
+            timestamp: None,
+            reason: None,
+        };
+        assert_eq!(
+            ledger.process_transaction(&transfer),
+            Err(RejectionReason::InsufficientFunds {
+                available: amount("-30"),
+                requested: amount("20.0001"),
+                overdraft_limit: amount("50"),
+            })
+        );
+        ledger
+            .process_transaction(&Transaction {
+                amount: Some(amount("20")),
+                ..transfer
+            })
+            .expect("transaction should be accepted");
+        assert_eq!(ledger.accounts()[&client(2)].available, amount("-50"));
+        assert_eq!(ledger.accounts()[&client(2)].headroom(None), amount("0"));
+
+        // Client 1's deposit predates the event log, so only client 2 replays in full
+        let replayed = Ledger::replay(ledger.drain_events()).expect("journal should replay");
+        assert_eq!(
+            replayed.accounts()[&client(2)],
+            ledger.accounts()[&client(2)]
+        );
+    }
+
+    #[test]
+    fn test_set_overdraft_limit_is_journaled_without_tx() {
+        let mut ledger = Ledger::new().with_event_log();
+        ledger
+            .set_overdraft_limit(client(1), amount("0"))
+            .expect("limit should be accepted");
+        assert!(ledger.accounts().is_empty());
+        assert_eq!(
+            ledger.set_overdraft_limit(client(1), amount("-1")),
+            Err(RejectionReason::NegativeAmount(amount("-1")))
+        );
+        ledger
+            .set_overdraft_limit(client(1), amount("25"))
+            .expect("limit should be accepted");
+        let events: Vec<_> = ledger.drain_events().collect();
+        assert_eq!(
+            events,
+            vec![LedgerEvent::OverdraftLimitSet {
+                client: client(1),
+                tx: None,
+                limit: amount("25"),
+            }]
+        );
+
+        let restored = Ledger::from_snapshot(ledger.snapshot().expect("failed to take snapshot"))
+            .expect("snapshot should be restored");
+        assert_eq!(
+            restored.accounts()[&client(1)].overdraft_limit,
+            amount("25")
+        );
+
+        // Without the limit, the journal cannot account for the overdraft
+        let withdrawal = LedgerEvent::WithdrawalApplied {
+            client: client(1),
+            tx: tx(1),
+            amount: amount("10"),
+            currency: None,
+            timestamp: None,
+        };
+        let err = Ledger::replay([withdrawal]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "event 0 (client 1, tx 1) cannot be replayed: insufficient funds: available 0.0000, requested 10.0000"
+        );
+        assert!(Ledger::replay(events.into_iter().chain([withdrawal])).is_ok());
+    }
+
+    #[test]
+    fn test_unlock_after_chargeback_restores_account() {
+        let mut ledger = ledger_with_deposit("100").with_event_log();
+        ledger
+            .process(TransactionType::Dispute, client(1), tx(1), None)
+            .expect("transaction should be accepted");
+        ledger
+            .process(TransactionType::Chargeback, client(1), tx(1), None)
+            .expect("transaction should be accepted");
+        assert_eq!(
+            ledger.process(TransactionType::Unlock, client(1), tx(2), None),
+            Ok(ProcessOutcome::Unlocked)
+        );
+        assert!(ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(3),
+                Some(amount("7"))
+            )
+            .is_ok());
+
+        let mut events = vec![LedgerEvent::DepositApplied {
+            client: client(1),
+            tx: tx(1),
+            amount: amount("100"),
+            currency: None,
+            timestamp: None,
+        }];
+        events.extend(ledger.drain_events());
+        let replayed = Ledger::replay(events).expect("replay should succeed");
+        assert_eq!(
+            replayed.snapshot().expect("failed to take snapshot"),
+            ledger.snapshot().expect("failed to take snapshot")
+        );
+        let acc = replayed
+            .get_account(client(1))
+            .expect("client(1) account should exist");
+        assert!(!acc.locked);
+        assert_eq!(acc.available, amount("7"));
+    }
+
+    fn transfer(from: u16, to: u16, id: u32, value: &str) -> Transaction {
+        Transaction {
+            tx_type: TransactionType::Transfer,
+            client_id: client(from),
+            tx_id: tx(id),
+            amount: Some(amount(value)),
+            destination: Some(client(to)),
+            currency: None,
+            timestamp: None,
+            reason: None,
+        }
+    }
+
+    #[test]
+    fn test_transfer_moves_funds() {
+        let mut ledger = ledger_with_deposit("100");
+        assert_eq!(
+            ledger.process_transaction(&transfer(1, 2, 2, "30")),
+            Ok(ProcessOutcome::Transferred(amount("30")))
+        );
+        assert_eq!(ledger.accounts()[&client(1)].available, amount("70"));
+        assert_eq!(ledger.accounts()[&client(2)].available, amount("30"));
+        assert_eq!(
+            ledger.process_transaction(&transfer(1, 2, 2, "1")),
+            Err(RejectionReason::DuplicateTransaction(tx(2)))
+        );
+    }
+
+    #[test]
+    fn test_failed_transfer_changes_neither_side() {
+        let mut ledger = ledger_with_deposit("100");
+        assert!(matches!(
+            ledger.process_transaction(&transfer(1, 2, 2, "100.0001")),
+            Err(RejectionReason::InsufficientFunds { .. })
+        ));
+        assert!(!ledger.accounts().contains_key(&client(2)));
+
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(2),
+                tx(3),
+                Some(amount("1")),
+            )
+            .expect("transaction should be accepted");
+        ledger
+            .process(TransactionType::Lock, client(2), tx(4), None)
+            .expect("transaction should be accepted");
+        assert_eq!(
+            ledger.process_transaction(&transfer(1, 2, 5, "10")),
+            Err(RejectionReason::DestinationLocked(client(2)))
+        );
+        assert_eq!(ledger.accounts()[&client(1)].available, amount("100"));
+        assert_eq!(ledger.accounts()[&client(2)].available, amount("1"));
+        assert!(ledger
+            .get_transaction(tx(5))
+            .expect("failed to read storage")
+            .is_none());
+    }
+
+    #[test]
+    fn test_transfer_requires_distinct_destination() {
+        let mut ledger = ledger_with_deposit("100");
+        assert_eq!(
+            ledger.process_transaction(&transfer(1, 1, 2, "10")),
+            Err(RejectionReason::TransferToSelf)
+        );
+        assert_eq!(
+            ledger.process(
+                TransactionType::Transfer,
+                client(1),
+                tx(2),
+                Some(amount("10"))
+            ),
+            Err(RejectionReason::MissingDestination)
+        );
+    }
+
+    #[test]
+    fn test_transfer_dispute_holds_at_destination() {
+        let mut ledger = ledger_with_deposit("100");
+        ledger
+            .process_transaction(&transfer(1, 2, 2, "30"))
+            .expect("transaction should be accepted");
+        assert_eq!(
+            ledger.process(TransactionType::Dispute, client(2), tx(2), None),
+            Err(RejectionReason::ClientMismatch {
+                tx_id: tx(2),
+                owner: client(1),
+                requested_by: client(2),
+            })
+        );
+        ledger
+            .process(TransactionType::Dispute, client(1), tx(2), None)
+            .expect("transaction should be accepted");
+        assert_eq!(ledger.accounts()[&client(2)].available, amount("0"));
+        assert_eq!(ledger.accounts()[&client(2)].held, amount("30"));
+
+        ledger
+            .process(TransactionType::Resolve, client(1), tx(2), None)
+            .expect("transaction should be accepted");
+        assert_eq!(ledger.accounts()[&client(2)].available, amount("30"));
+        assert_eq!(ledger.accounts()[&client(2)].held, amount("0"));
+        assert_eq!(ledger.accounts()[&client(1)].available, amount("70"));
+    }
+
+    #[test]
+    fn test_transfer_chargeback_replays_identically() {
+        let mut ledger = ledger_with_deposit("100").with_event_log();
+        ledger
+            .process_transaction(&transfer(1, 2, 2, "30"))
+            .expect("transaction should be accepted");
+        ledger
+            .process(TransactionType::Dispute, client(1), tx(2), None)
+            .expect("transaction should be accepted");
+        assert_eq!(
+            ledger.process(TransactionType::Chargeback, client(1), tx(2), None),
+            Ok(ProcessOutcome::ChargedBack(amount("30")))
+        );
+        assert_eq!(ledger.accounts()[&client(1)].available, amount("100"));
+        assert_eq!(ledger.accounts()[&client(2)].total(), amount("0"));
+        assert!(!ledger.accounts()[&client(1)].locked);
+        assert!(!ledger.accounts()[&client(2)].locked);
+
+        let mut events = vec![LedgerEvent::DepositApplied {
+            client: client(1),
+            tx: tx(1),
+            amount: amount("100"),
+            currency: None,
+            timestamp: None,
+        }];
+        events.extend(ledger.drain_events());
+        let replayed = Ledger::replay(events).expect("replay should succeed");
+        assert_eq!(
+            replayed.snapshot().expect("failed to take snapshot"),
+            ledger.snapshot().expect("failed to take snapshot")
+        );
+    }
+
+    fn in_currency(
+        tx_type: TransactionType,
+        id: u32,
+        value: Option<&str>,
+        code: &str,
+    ) -> Transaction {
+        Transaction {
+            tx_type,
+            client_id: client(1),
+            tx_id: tx(id),
+            amount: value.map(amount),
+            destination: None,
+            currency: Some(code.parse().expect("failed to parse currency")),
+            timestamp: None,
+            reason: None,
+        }
+    }
+
+    #[test]
+    fn test_currencies_are_tracked_separately() {
+        let mut ledger = ledger_with_deposit("100");
+        ledger
+            .process_transaction(&in_currency(TransactionType::Deposit, 2, Some("50"), "EUR"))
+            .expect("transaction should be accepted");
+        assert!(matches!(
+            ledger.process_transaction(&in_currency(
+                TransactionType::Withdrawal,
+                3,
+                Some("60"),
+                "EUR"
+            )),
+            Err(RejectionReason::InsufficientFunds { .. })
+        ));
+
+        ledger
+            .process(TransactionType::Dispute, client(1), tx(2), None)
+            .expect("transaction should be accepted");
+        let acc = ledger
+            .get_account(client(1))
+            .expect("client(1) account should exist");
+        let eur = acc.balance("EUR".parse().ok());
+        assert_eq!(eur.available, amount("0"));
+        assert_eq!(eur.held, amount("50"));
+        assert_eq!(acc.available, amount("100"));
+        assert_eq!(acc.held, amount("0"));
+    }
+
+    #[test]
+    fn test_dispute_in_other_currency_rejected() {
+        let mut ledger = ledger_with_deposit("100");
+        assert_eq!(
+            ledger.process_transaction(&in_currency(TransactionType::Dispute, 1, None, "EUR")),
+            Err(RejectionReason::CurrencyMismatch {
+                tx_id: tx(1),
+                expected: None,
+                requested: "EUR".parse().ok(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_currency_chargeback_locks_whole_account() {
+        let mut ledger = ledger_with_deposit("100").with_event_log();
+        ledger
+            .process_transaction(&in_currency(TransactionType::Deposit, 2, Some("50"), "EUR"))
+            .expect("transaction should be accepted");
+        ledger
+            .process_transaction(&in_currency(TransactionType::Dispute, 2, None, "EUR"))
+            .expect("transaction should be accepted");
+        ledger
+            .process_transaction(&in_currency(TransactionType::Chargeback, 2, None, "EUR"))
+            .expect("transaction should be accepted");
+        assert_eq!(
+            ledger.process(
+                TransactionType::Withdrawal,
+                client(1),
+                tx(3),
+                Some(amount("1"))
+            ),
+            Err(RejectionReason::AccountLocked)
+        );
+
+        let mut events = vec![LedgerEvent::DepositApplied {
+            client: client(1),
+            tx: tx(1),
+            amount: amount("100"),
+            currency: None,
+            timestamp: None,
+        }];
+        events.extend(ledger.drain_events());
+        let replayed = Ledger::replay(events).expect("replay should succeed");
+        assert_eq!(
+            replayed.snapshot().expect("failed to take snapshot"),
+            ledger.snapshot().expect("failed to take snapshot")
+        );
+        let restored = Ledger::from_snapshot(ledger.snapshot().expect("failed to take snapshot"))
+            .expect("snapshot should restore");
+        assert_eq!(restored.accounts(), ledger.accounts());
+    }
+
+    fn activities(ledger: &Ledger, client_id: u16) -> Vec<(Activity, Amount, Amount)> {
+        ledger
+            .history(client(client_id))
+            .iter()
+            .map(|entry| (entry.activity, entry.balance.available, entry.balance.held))
+            .collect()
+    }
+
+    #[test]
+    fn test_history_records_running_balances() {
+        let mut ledger = Ledger::new().with_history();
+        for (tx_type, id, value) in [
+            (TransactionType::Deposit, 1, Some("100")),
+            (TransactionType::Withdrawal, 2, Some("30")),
+            (TransactionType::Withdrawal, 3, Some("500")),
+            (TransactionType::Dispute, 1, None),
+            (TransactionType::Chargeback, 1, None),
+        ] {
+            let _ = ledger.process(tx_type, client(1), tx(id), value.map(amount));
+        }
+
+        assert_eq!(
+            activities(&ledger, 1),
+            vec![
+                (Activity::Deposit, amount("100"), amount("0")),
+                (Activity::Withdrawal, amount("70"), amount("0")),
+                (Activity::Dispute, amount("-30"), amount("100")),
+                (Activity::Chargeback, amount("-30"), amount("0")),
+                (Activity::Lock, amount("-30"), amount("0")),
+            ]
+        );
+        let chargeback = ledger.history(client(1))[3];
+        assert_eq!(chargeback.tx, tx(1));
+        assert_eq!(chargeback.change.available, amount("0"));
+        assert_eq!(chargeback.change.held, amount("-100"));
+        assert!(chargeback.locked);
+    }
+
+    #[test]
+    fn test_history_of_transfer_covers_both_clients() {
+        let mut ledger = ledger_with_deposit("100").with_history();
+        ledger
+            .process_transaction(&transfer(1, 2, 2, "30"))
+            .expect("transaction should be accepted");
+        ledger
+            .process(TransactionType::Dispute, client(1), tx(2), None)
+            .expect("transaction should be accepted");
+
+        assert_eq!(
+            activities(&ledger, 1),
+            vec![
+                (Activity::TransferOut, amount("70"), amount("0")),
+                (Activity::Dispute, amount("70"), amount("0")),
+            ]
+        );
+        assert_eq!(
+            activities(&ledger, 2),
+            vec![
+                (Activity::TransferIn, amount("30"), amount("0")),
+                (Activity::Dispute, amount("0"), amount("30")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_history_is_off_by_default() {
+        let ledger = ledger_with_deposit("100");
+        assert!(ledger.history(client(1)).is_empty());
+    }
+
+    fn timed(tx_type: TransactionType, id: u32, value: Option<&str>, at: u64) -> Transaction {
+        Transaction {
+            tx_type,
+            client_id: client(1),
+            tx_id: tx(id),
+            amount: value.map(amount),
+            destination: None,
+            currency: None,
+            timestamp: Some(Timestamp(at)),
+            reason: None,
+        }
+    }
+
+    #[test]
+    fn test_dispute_window_rejects_late_disputes() {
+        let mut ledger = Ledger::new().with_dispute_window(100);
+        for (id, at) in [(1, 1_000), (2, 1_050)] {
+            ledger
+                .process_transaction(&timed(TransactionType::Deposit, id, Some("10"), at))
+                .expect("transaction should be accepted");
+        }
+        assert_eq!(
+            ledger.process_transaction(&timed(TransactionType::Dispute, 1, None, 1_101)),
+            Err(RejectionReason::DisputeWindowClosed {
+                tx_id: tx(1),
+                age: 101,
+                window: 100,
+            })
+        );
+        assert_eq!(
+            ledger.process_transaction(&timed(TransactionType::Dispute, 2, None, 1_150)),
+            Ok(ProcessOutcome::Held(amount("10")))
+        );
+        assert_eq!(
+            ledger
+                .get_transaction(tx(1))
+                .expect("failed to read storage")
+                .and_then(|t| t.timestamp),
+            Some(Timestamp(1_000))
+        );
+    }
+
+    #[test]
+    fn test_transactions_keep_their_own_timestamp() {
+        let mut ledger = Ledger::new().with_dispute_window(100);
+        ledger
+            .process_transaction(&timed(TransactionType::Deposit, 1, Some("10"), 1_000))
+            .expect("transaction should be accepted");
+        // Out of order, so older than the ledger's time
+        ledger
+            .process_transaction(&timed(TransactionType::Deposit, 2, Some("10"), 900))
+            .expect("transaction should be accepted");
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(3),
+                Some(amount("10")),
+            )
+            .expect("transaction should be accepted");
+        assert_eq!(
+            ledger
+                .get_transaction(tx(2))
+                .expect("failed to read storage")
+                .and_then(|t| t.timestamp),
+            Some(Timestamp(900))
+        );
+        assert_eq!(
+            ledger
+                .get_transaction(tx(3))
+                .expect("failed to read storage")
+                .and_then(|t| t.timestamp),
+            Some(Timestamp(1_000))
+        );
+
+        assert_eq!(
+            ledger.process_transaction(&timed(TransactionType::Dispute, 2, None, 1_050)),
+            Err(RejectionReason::DisputeWindowClosed {
+                tx_id: tx(2),
+                age: 150,
+                window: 100,
+            })
+        );
+        assert!(ledger
+            .process(TransactionType::Dispute, client(1), tx(3), None)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_dispute_window_uses_latest_timestamp_seen() {
+        let mut ledger = Ledger::new().with_dispute_window(100);
+        ledger
+            .process_transaction(&timed(TransactionType::Deposit, 1, Some("10"), 1_000))
+            .expect("transaction should be accepted");
+        ledger
+            .process_transaction(&timed(TransactionType::Deposit, 2, Some("10"), 2_000))
+            .expect("transaction should be accepted");
+        // A dispute row without timestamp happens no earlier than the last row
+        assert!(matches!(
+            ledger.process(TransactionType::Dispute, client(1), tx(1), None),
+            Err(RejectionReason::DisputeWindowClosed { .. })
+        ));
+    }
+
+    #[test]
+    fn test_dispute_window_ignores_untimed_transactions() {
+        let mut ledger = ledger_with_deposit("10").with_dispute_window(100);
+        ledger
+            .process_transaction(&timed(TransactionType::Deposit, 2, Some("10"), 5_000))
+            .expect("transaction should be accepted");
+        assert_eq!(
+            ledger.process(TransactionType::Dispute, client(1), tx(1), None),
+            Ok(ProcessOutcome::Held(amount("10")))
+        );
+    }
+
+    #[test]
+    fn test_auto_resolve_releases_expired_disputes() {
+        let mut ledger = Ledger::new().with_auto_resolve(60).with_event_log();
+        ledger
+            .process_transaction(&timed(TransactionType::Deposit, 1, Some("10"), 0))
+            .expect("transaction should be accepted");
+        ledger
+            .process_transaction(&timed(TransactionType::Dispute, 1, Some("4"), 10))
+            .expect("transaction should be accepted");
+        ledger
+            .process_transaction(&timed(TransactionType::Dispute, 1, Some("6"), 50))
+            .expect("transaction should be accepted");
+
+        // Still within 60 seconds of the first dispute
+        ledger
+            .process_transaction(&timed(TransactionType::Deposit, 2, Some("1"), 70))
+            .expect("transaction should be accepted");
+        assert_eq!(ledger.accounts()[&client(1)].held, amount("10"));
+
+        ledger
+            .process_transaction(&timed(TransactionType::Deposit, 3, Some("1"), 71))
+            .expect("transaction should be accepted");
+        let account = &ledger.accounts()[&client(1)];
+        assert_eq!(account.held, amount("0"));
+        assert_eq!(account.available, amount("12"));
+        assert_eq!(
+            ledger
+                .get_transaction(tx(1))
+                .expect("failed to read storage")
+                .map(|t| t.state),
+            Some(TransactionState::Resolved)
+        );
+        assert!(ledger.drain_events().any(|event| event
+            == LedgerEvent::FundsReleased {
+                client: client(1),
+                tx: tx(1),
+                amount: amount("10"),
+            }));
+    }
+
+    #[test]
+    fn test_rejected_rows_leave_the_clock_alone() {
+        let mut ledger = Ledger::new().with_auto_resolve(60);
+        ledger
+            .process_transaction(&timed(TransactionType::Deposit, 1, Some("10"), 0))
+            .expect("transaction should be accepted");
+        ledger
+            .process_transaction(&timed(TransactionType::Dispute, 1, None, 10))
+            .expect("transaction should be accepted");
+
+        assert!(matches!(
+            ledger.process_transaction(&timed(TransactionType::Withdrawal, 2, Some("50"), 500)),
+            Err(RejectionReason::InsufficientFunds { .. })
+        ));
+        assert_eq!(ledger.clock, Some(Timestamp(10)));
+        assert_eq!(ledger.accounts()[&client(1)].held, amount("10"));
+
+        ledger
+            .process_transaction(&timed(TransactionType::Deposit, 3, Some("1"), 500))
+            .expect("transaction should be accepted");
+        assert_eq!(ledger.accounts()[&client(1)].held, amount("0"));
+    }
+
+    #[test]
+    fn test_clock_moves_are_journaled() {
+        let mut ledger = Ledger::new().with_event_log();
+        ledger
+            .process_transaction(&timed(TransactionType::Deposit, 1, Some("10"), 100))
+            .expect("transaction should be accepted");
+        ledger
+            .process_transaction(&timed(TransactionType::Dispute, 1, None, 200))
+            .expect("transaction should be accepted");
+        ledger
+            .process_transaction(&timed(TransactionType::Resolve, 1, None, 300))
+            .expect("transaction should be accepted");
+
+        let events: Vec<_> = ledger.drain_events().collect();
+        assert_eq!(
+            events.last(),
+            Some(&LedgerEvent::ClockAdvanced {
+                client: client(1),
+                tx: tx(1),
+                timestamp: Timestamp(300),
+            })
+        );
+        let replayed = Ledger::replay(events).expect("replay should succeed");
+        assert_eq!(replayed.clock, Some(Timestamp(300)));
+    }
+
+    #[test]
+    fn test_timestamps_survive_replay_and_snapshot() {
+        let mut ledger = Ledger::new().with_event_log();
+        ledger
+            .process_transaction(&timed(TransactionType::Deposit, 1, Some("10"), 100))
+            .expect("transaction should be accepted");
+        ledger
+            .process_transaction(&timed(TransactionType::Dispute, 1, None, 200))
+            .expect("transaction should be accepted");
+
+        let replayed = Ledger::replay(ledger.drain_events()).expect("replay should succeed");
+        assert_eq!(
+            replayed.snapshot().expect("failed to take snapshot"),
+            ledger.snapshot().expect("failed to take snapshot")
+        );
+        assert_eq!(replayed.clock, Some(Timestamp(200)));
+
+        // A restored ledger still knows when the dispute was opened
+        let mut restored =
+            Ledger::from_snapshot(ledger.snapshot().expect("failed to take snapshot"))
+                .expect("snapshot should restore")
+                .with_auto_resolve(50);
+        assert_eq!(restored.clock, Some(Timestamp(200)));
+        restored
+            .process_transaction(&timed(TransactionType::Deposit, 2, Some("1"), 251))
+            .expect("transaction should be accepted");
+        assert_eq!(
+            restored
+                .get_transaction(tx(1))
+                .expect("failed to read storage")
+                .map(|t| t.state),
+            Some(TransactionState::Resolved)
+        );
+    }
+
+    fn fee_schedule() -> FeeSchedule {
+        let flat = |fee: &str| FeeRule {
+            flat: amount(fee),
+            ..FeeRule::default()
+        };
+        FeeSchedule::new(client(0))
+            .with_fee(
+                TransactionType::Withdrawal,
+                FeeRule {
+                    flat: amount("0.5"),
+                    percent: Decimal::ONE,
+                    ..FeeRule::default()
+                },
+            )
+            .with_fee(TransactionType::Chargeback, flat("15"))
+    }
+
+    #[test]
+    fn test_fees_are_paid_to_house_account() {
+        let mut ledger = Ledger::new().with_fees(fee_schedule()).with_event_log();
+        let mut process = |tx_type, client_id, tx_id, amount| {
+            ledger.process(tx_type, client(client_id), tx(tx_id), amount)
+        };
+        process(TransactionType::Deposit, 1, 1, Some(amount("100"))).expect("deposit");
+        process(TransactionType::Deposit, 1, 2, Some(amount("10"))).expect("deposit");
+        process(TransactionType::Withdrawal, 1, 3, Some(amount("50"))).expect("withdrawal");
+        process(TransactionType::Dispute, 1, 2, None).expect("dispute");
+        process(TransactionType::Chargeback, 1, 2, None).expect("chargeback");
+
+        // 110 - 50 - 1 (0.5 + 1%) - 10 charged back - 15
+        let account = &ledger.accounts()[&client(1)];
+        assert_eq!(account.available, amount("34"));
+        assert!(account.locked);
+        assert_eq!(ledger.accounts()[&client(0)].available, amount("16"));
+
+        let fees: Vec<_> = ledger
+            .drain_events()
+            .filter(|event| matches!(event, LedgerEvent::FeeCharged { .. }))
+            .collect();
+        assert_eq!(
+            fees,
+            vec![
+                LedgerEvent::FeeCharged {
+                    client: client(1),
+                    tx: tx(3),
+                    fee_for: TransactionType::Withdrawal,
+                    house: client(0),
+                    amount: amount("1"),
+                    currency: None,
+                },
+                LedgerEvent::FeeCharged {
+                    client: client(1),
+                    tx: tx(2),
+                    fee_for: TransactionType::Chargeback,
+                    house: client(0),
+                    amount: amount("15"),
+                    currency: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_withdrawal_must_cover_its_fee() {
+        let mut ledger = Ledger::new().with_fees(fee_schedule());
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("10")),
+            )
+            .expect("deposit should be accepted");
+        assert!(matches!(
+            ledger.process(
+                TransactionType::Withdrawal,
+                client(1),
+                tx(2),
+                Some(amount("10"))
+            ),
+            Err(RejectionReason::InsufficientFunds { .. })
+        ));
+        assert!(!ledger
+            .storage
+            .is_processed(tx(2))
+            .expect("failed to read storage"));
+
+        // 9.4 + 0.5 + 0.094 rounds to 9.994
+        ledger
+            .process(
+                TransactionType::Withdrawal,
+                client(1),
+                tx(2),
+                Some(amount("9.4")),
+            )
+            .expect("withdrawal should be accepted");
+        assert_eq!(ledger.accounts()[&client(1)].available, amount("0.006"));
+        assert_eq!(ledger.accounts()[&client(0)].available, amount("0.594"));
+    }
+
+    #[test]
+    fn test_house_account_pays_no_fees_and_fees_replay() {
+        let mut ledger = Ledger::new().with_fees(fee_schedule()).with_event_log();
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("20")),
+            )
+            .expect("deposit should be accepted");
+        ledger
+            .process(
+                TransactionType::Withdrawal,
+                client(1),
+                tx(2),
+                Some(amount("10")),
+            )
+            .expect("withdrawal should be accepted");
+        ledger
+            .process(
+                TransactionType::Withdrawal,
+                client(0),
+                tx(3),
+                Some(amount("0.6")),
+            )
+            .expect("withdrawal should be accepted");
+        assert_eq!(ledger.accounts()[&client(0)].available, amount("0"));
+
+        let replayed = Ledger::replay(ledger.drain_events()).expect("replay should succeed");
+        assert_eq!(
+            replayed.snapshot().expect("failed to take snapshot"),
+            ledger.snapshot().expect("failed to take snapshot")
+        );
+    }
+
+    #[test]
+    fn test_books_balance_across_every_operation() {
+        let mut ledger = Ledger::new()
+            .with_withdrawal_disputes()
+            .with_fees(fee_schedule());
+        let eur = "EUR".parse().ok();
+        let rows = [
+            (TransactionType::Deposit, 1, 1, Some("100"), None, None),
+            (TransactionType::Deposit, 2, 2, Some("50"), eur, None),
+            (TransactionType::Withdrawal, 1, 3, Some("20"), None, None),
+            (
+                TransactionType::Transfer,
+                1,
+                4,
+                Some("30"),
+                None,
+                Some(client(2)),
+            ),
+            (TransactionType::Dispute, 1, 3, None, None, None),
+            (TransactionType::Chargeback, 1, 3, None, None, None),
+            (TransactionType::Dispute, 1, 4, None, None, None),
+            (TransactionType::Chargeback, 1, 4, None, None, None),
+            (TransactionType::Dispute, 2, 2, None, None, None),
+            (TransactionType::Resolve, 2, 2, None, None, None),
+            (TransactionType::Dispute, 1, 1, None, None, None),
+            (TransactionType::Chargeback, 1, 1, None, None, None),
+        ];
+        for (tx_type, client_id, tx_id, value, currency, destination) in rows {
+            ledger
+                .process_transaction(&Transaction {
+                    tx_type,
+                    client_id: client(client_id),
+                    tx_id: tx(tx_id),
+                    amount: value.map(amount),
+                    destination,
+                    currency,
+                    timestamp: None,
+                    reason: None,
+                })
+                .expect("transaction should be accepted");
+        }
+
+        let books = ledger.trial_balance().expect("books should balance");
+        assert_eq!(
+            books.balance(BookAccount::ExternalFunding, None),
+            amount("-80")
+        );
+        // The deposit went back to its payer, the withdrawal was refunded from the loss
+        assert_eq!(
+            books.balance(BookAccount::ChargebackLoss, None),
+            amount("80")
+        );
+        assert_eq!(
+            books.balance(BookAccount::Available(client(0)), None),
+            amount("45.7")
+        );
+        assert_eq!(
+            books.balance(BookAccount::ExternalFunding, eur),
+            amount("-50")
+        );
+
+        let restored = Ledger::from_snapshot(ledger.snapshot().expect("failed to take snapshot"))
+            .expect("snapshot should restore");
+        assert_eq!(restored.trial_balance(), Ok(books));
+    }
+
+    #[test]
+    fn test_books_open_from_older_snapshots() {
+        let mut ledger = Ledger::new();
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("10")),
+            )
+            .expect("deposit should be accepted");
+        ledger
+            .process(TransactionType::Dispute, client(1), tx(1), None)
+            .expect("dispute should be accepted");
+        let mut snapshot = ledger.snapshot().expect("failed to take snapshot");
+        snapshot.version = 7;
+        snapshot.books.clear();
+
+        let restored = Ledger::from_snapshot(snapshot).expect("snapshot should restore");
+        let books = restored.trial_balance().expect("books should balance");
+        assert_eq!(
+            books.balance(BookAccount::Held(client(1)), None),
+            amount("10")
+        );
+        assert_eq!(
+            books.balance(BookAccount::ExternalFunding, None),
+            amount("-10")
+        );
+    }
+
+    #[test]
+    fn test_policy_allows_redispute_after_resolve() {
+        let policy = LedgerPolicy {
+            redispute_resolved: true,
+            ..LedgerPolicy::default()
+        };
+        let mut ledger = Ledger::new().with_policy(policy).with_event_log();
+        let mut process = |tx_type, value: Option<&str>| {
+            ledger.process(tx_type, client(1), tx(1), value.map(amount))
+        };
+        process(TransactionType::Deposit, Some("100")).expect("deposit");
+        process(TransactionType::Dispute, Some("40")).expect("dispute");
+        process(TransactionType::Chargeback, Some("10")).expect("chargeback");
+        process(TransactionType::Resolve, None).expect("resolve");
+        // Everything but the charged back part can be disputed again
+        assert_eq!(
+            process(TransactionType::Dispute, None),
+            Ok(ProcessOutcome::Held(amount("90")))
+        );
+        process(TransactionType::Resolve, None).expect("resolve");
+
+        let stored = ledger
+            .get_transaction(tx(1))
+            .expect("failed to read storage")
+            .expect("tx 1 should be stored");
+        assert_eq!(stored.state, TransactionState::Resolved);
+        assert_eq!(
+            (stored.settled, stored.charged_back),
+            (amount("100"), amount("10"))
+        );
+        assert_eq!(ledger.accounts()[&client(1)].available, amount("90"));
+        assert_eq!(ledger.audit().expect("failed to audit"), Vec::new());
+
+        let replayed = Ledger::replay(ledger.drain_events()).expect("replay should succeed");
+        assert_eq!(
+            replayed.snapshot().expect("failed to take snapshot"),
+            ledger.snapshot().expect("failed to take snapshot")
+        );
+    }
+
+    #[test]
+    fn test_default_policy_keeps_resolved_transactions_final() {
+        let mut ledger = Ledger::new();
+        let mut process = |tx_type| ledger.process(tx_type, client(1), tx(1), None);
+        process(TransactionType::Dispute).unwrap_err();
+        ledger
+            .process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("5")),
+            )
+            .expect("deposit should be accepted");
+        for tx_type in [TransactionType::Dispute, TransactionType::Resolve] {
+            ledger
+                .process(tx_type, client(1), tx(1), None)
+                .expect("transaction should be accepted");
+        }
+        assert_eq!(
+            ledger.process(TransactionType::Dispute, client(1), tx(1), None),
+            Err(RejectionReason::InvalidState {
+                tx_id: tx(1),
+                state: TransactionState::Resolved,
+            })
+        );
+    }
+
+    #[test]
+    fn test_policy_restricts_disputes() {
+        let policy = LedgerPolicy {
+            dispute_locked_accounts: false,
+            dispute_into_negative: false,
+            ..LedgerPolicy::default()
+        };
+        let mut ledger = Ledger::new().with_policy(policy);
+        for (tx_type, tx_id, value) in [
+            (TransactionType::Deposit, 1, Some("100")),
+            (TransactionType::Deposit, 2, Some("10")),
+            (TransactionType::Withdrawal, 3, Some("95")),
+        ] {
+            ledger
+                .process(tx_type, client(1), tx(tx_id), value.map(amount))
+                .expect("transaction should be accepted");
+        }
+
+        assert_eq!(
+            ledger.process(TransactionType::Dispute, client(1), tx(1), None),
+            Err(RejectionReason::InsufficientFunds {
+                available: amount("15"),
+                requested: amount("100"),
+                overdraft_limit: Amount::ZERO,
+            })
+        );
+        for tx_type in [TransactionType::Dispute, TransactionType::Chargeback] {
+            ledger
+                .process(tx_type, client(1), tx(2), None)
+                .expect("transaction should be accepted");
+        }
+        assert_eq!(
+            ledger.process(
+                TransactionType::Dispute,
+                client(1),
+                tx(1),
+                Some(amount("5"))
+            ),
+            Err(RejectionReason::AccountLocked)
+        );
+    }
+
+    #[test]
+    fn test_policy_allows_deposits_to_locked_accounts() {
+        let policy = LedgerPolicy {
+            deposit_to_locked_accounts: true,
+            ..LedgerPolicy::default()
+        };
+        let mut ledger = Ledger::new().with_policy(policy).with_event_log();
+        for (tx_type, tx_id, value) in [
+            (TransactionType::Deposit, 1, Some("10")),
+            (TransactionType::Lock, 2, None),
+            (TransactionType::Deposit, 3, Some("5")),
+        ] {
+            ledger
+                .process(tx_type, client(1), tx(tx_id), value.map(amount))
+                .expect("transaction should be accepted");
+        }
+        assert_eq!(
+            ledger.process(
+                TransactionType::Withdrawal,
+                client(1),
+                tx(4),
+                Some(amount("1"))
+            ),
+            Err(RejectionReason::AccountLocked)
+        );
+        assert_eq!(ledger.accounts()[&client(1)].available, amount("15"));
+
+        // Replay does not need the policy
+        let replayed = Ledger::replay(ledger.drain_events()).expect("replay should succeed");
+        assert_eq!(replayed.accounts()[&client(1)].available, amount("15"));
+
+        let mut strict = ledger_with_deposit("10");
+        strict
+            .process(TransactionType::Lock, client(1), tx(2), None)
+            .expect("lock should be accepted");
+        assert_eq!(
+            strict.process(
+                TransactionType::Deposit,
+                client(1),
+                tx(3),
+                Some(amount("5"))
+            ),
+            Err(RejectionReason::AccountLocked)
+        );
+        assert_eq!(
+            strict.process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("5"))
+            ),
+            Err(RejectionReason::DuplicateTransaction(tx(1)))
+        );
+    }
+
+    /// Keeps everything in memory, but cannot store any transaction.
+    #[derive(Debug, Default)]
+    struct FullStorage(MemoryStorage);
+
+    impl Storage for FullStorage {
+        fn accounts(&self) -> &BTreeMap<ClientId, Account> {
+            self.0.accounts()
+        }
+
+        fn accounts_mut(&mut self) -> &mut BTreeMap<ClientId, Account> {
+            self.0.accounts_mut()
+        }
+
+        fn transaction(&self, tx_id: TransactionId) -> io::Result<Option<StoredTransaction>> {
+            self.0.transaction(tx_id)
+        }
+
+        fn insert_transaction(&mut self, _: TransactionId, _: StoredTransaction) -> io::Result<()> {
+            Err(io::Error::new(io::ErrorKind::StorageFull, "disk full"))
+        }
+
+        fn transactions(
+            &self,
+        ) -> Box<dyn Iterator<Item = io::Result<(TransactionId, StoredTransaction)>> + '_> {
+            self.0.transactions()
+        }
+
+        fn is_processed(&self, tx_id: TransactionId) -> io::Result<bool> {
+            self.0.is_processed(tx_id)
+        }
+
+        fn mark_processed(&mut self, tx_id: TransactionId) -> io::Result<()> {
+            self.0.mark_processed(tx_id)
+        }
+
+        fn processed_tx_ids(&self) -> Box<dyn Iterator<Item = io::Result<TransactionId>> + '_> {
+            self.0.processed_tx_ids()
+        }
+    }
+
+    #[test]
+    fn test_storage_failure_stops_processing() {
+        let mut ledger = Ledger::new()
+            .with_storage(Box::<FullStorage>::default())
+            .expect("an empty ledger should move");
+        let failure = RejectionReason::Storage("disk full".to_string());
+        assert_eq!(
+            ledger.process(
+                TransactionType::Deposit,
+                client(1),
+                tx(1),
+                Some(amount("10"))
+            ),
+            Err(failure.clone())
+        );
+        assert_eq!(failure.category(), "storage_failed");
+
+        // Even transactions that would not touch the storage are refused
+        assert_eq!(
+            ledger.process(TransactionType::Lock, client(1), tx(2), None),
+            Err(failure)
+        );
+        assert!(!ledger.accounts()[&client(1)].locked);
     }
 }
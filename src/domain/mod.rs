@@ -1,7 +1,25 @@
 pub mod account;
+pub mod audit;
+pub mod books;
+pub mod event;
+pub mod fees;
+pub mod history;
 pub mod ledger;
+pub mod policy;
+pub mod rejection;
+pub mod snapshot;
+pub mod storage;
 pub mod types;
 
 pub use account::Account;
-pub use ledger::Ledger;
-pub use types::{Amount, ClientId, TransactionId};
+pub use audit::Violation;
+pub use books::{BookAccount, Books, BooksError, Posting};
+pub use event::LedgerEvent;
+pub use fees::{FeeRule, FeeSchedule};
+pub use history::{Activity, HistoryEntry};
+pub use ledger::{Ledger, ProcessOutcome, ReplayError};
+pub use policy::LedgerPolicy;
+pub use rejection::RejectionReason;
+pub use snapshot::{BookEntry, LedgerSnapshot, SnapshotError, SnapshotStream};
+pub use storage::{MemoryStorage, Storage};
+pub use types::{Amount, ClientId, Transaction, TransactionId};
@@ -0,0 +1,54 @@
+use serde::{Deserialize, Serialize};
+
+/// Rules the ledger applies to disputes and locked accounts, see [`Ledger::with_policy`](crate::domain::Ledger::with_policy)
+///
+/// Every field is optional in a config file; the defaults are the rules described in
+/// the README.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LedgerPolicy {
+    /// Withdrawals can be disputed, not only deposits and transfers.
+    pub dispute_withdrawals: bool,
+    /// A resolved transaction can be disputed again, except for any part charged back.
+    pub redispute_resolved: bool,
+    /// Disputes are accepted on locked accounts.
+    pub dispute_locked_accounts: bool,
+    /// Disputes may hold more than is available, taking `available` below zero.
+    pub dispute_into_negative: bool,
+    /// Deposits are accepted on locked accounts; withdrawals and transfers never are.
+    pub deposit_to_locked_accounts: bool,
+}
+
+impl Default for LedgerPolicy {
+    fn default() -> Self {
+        LedgerPolicy {
+            dispute_withdrawals: false,
+            redispute_resolved: false,
+            dispute_locked_accounts: true,
+            dispute_into_negative: true,
+            deposit_to_locked_accounts: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_from_json_keeps_defaults() {
+        let policy: LedgerPolicy =
+            serde_json::from_str(r#"{"redispute_resolved": true, "dispute_into_negative": false}"#)
+                .expect("policy should parse");
+        assert_eq!(
+            policy,
+            LedgerPolicy {
+                redispute_resolved: true,
+                dispute_into_negative: false,
+                ..LedgerPolicy::default()
+            }
+        );
+        assert!(policy.dispute_locked_accounts);
+        assert!(serde_json::from_str::<LedgerPolicy>(r#"{"redispute": true}"#).is_err());
+    }
+}
@@ -0,0 +1,235 @@
+use std::fmt;
+use std::io;
+
+use crate::domain::types::{
+    currency_name, Amount, ClientId, Currency, TransactionId, TransactionKind, TransactionState,
+};
+
+/// Why the ledger refused to apply a transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RejectionReason {
+    MissingAmount,
+    NegativeAmount(Amount),
+    /// A dispute, resolve or chargeback amount that is zero or negative.
+    InvalidAmount(Amount),
+    DuplicateTransaction(TransactionId),
+    AccountLocked,
+    AccountNotLocked,
+    AccountNotFound(ClientId),
+    DestinationLocked(ClientId),
+    MissingDestination,
+    TransferToSelf,
+    InsufficientFunds {
+        available: Amount,
+        requested: Amount,
+        /// How far `available` may go below zero; zero without a credit line.
+        overdraft_limit: Amount,
+    },
+    TransactionNotFound(TransactionId),
+    NotDisputable {
+        tx_id: TransactionId,
+        kind: TransactionKind,
+    },
+    ClientMismatch {
+        tx_id: TransactionId,
+        owner: ClientId,
+        requested_by: ClientId,
+    },
+    InvalidState {
+        tx_id: TransactionId,
+        state: TransactionState,
+    },
+    ExceedsDisputable {
+        tx_id: TransactionId,
+        requested: Amount,
+        remaining: Amount,
+    },
+    CurrencyMismatch {
+        tx_id: TransactionId,
+        expected: Option<Currency>,
+        requested: Option<Currency>,
+    },
+    /// Ages are in seconds.
+    DisputeWindowClosed {
+        tx_id: TransactionId,
+        age: u64,
+        window: u64,
+    },
+    /// The storage backend failed to read or write, with the I/O error's message.
+    ///
+    /// The transaction may be half-applied, so the ledger refuses every later one.
+    Storage(String),
+}
+
+impl RejectionReason {
+    /// Stable machine-readable category, suitable for reports and metrics.
+    pub fn category(&self) -> &'static str {
+        match self {
+            RejectionReason::MissingAmount => "missing_amount",
+            RejectionReason::NegativeAmount(_) => "negative_amount",
+            RejectionReason::InvalidAmount(_) => "invalid_amount",
+            RejectionReason::DuplicateTransaction(_) => "duplicate_tx",
+            RejectionReason::AccountLocked => "account_locked",
+            RejectionReason::AccountNotLocked => "account_not_locked",
+            RejectionReason::AccountNotFound(_) => "account_not_found",
+            RejectionReason::DestinationLocked(_) => "destination_locked",
+            RejectionReason::MissingDestination => "missing_destination",
+            RejectionReason::TransferToSelf => "transfer_to_self",
+            RejectionReason::InsufficientFunds { .. } => "insufficient_funds",
+            RejectionReason::TransactionNotFound(_) => "tx_not_found",
+            RejectionReason::NotDisputable { .. } => "not_disputable",
+            RejectionReason::ClientMismatch { .. } => "client_mismatch",
+            RejectionReason::InvalidState { .. } => "invalid_state",
+            RejectionReason::ExceedsDisputable { .. } => "exceeds_disputable",
+            RejectionReason::CurrencyMismatch { .. } => "currency_mismatch",
+            RejectionReason::DisputeWindowClosed { .. } => "dispute_window_closed",
+            RejectionReason::Storage(_) => "storage_failed",
+        }
+    }
+}
+
+impl fmt::Display for RejectionReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectionReason::MissingAmount => write!(f, "amount is required"),
+            RejectionReason::NegativeAmount(amount) => {
+                write!(f, "negative amount not allowed: {}", amount)
+            }
+            RejectionReason::InvalidAmount(amount) => {
+                write!(f, "amount must be greater than zero: {}", amount)
+            }
+            RejectionReason::DuplicateTransaction(tx_id) => {
+                write!(f, "transaction {} was already processed", tx_id)
+            }
+            RejectionReason::AccountLocked => write!(f, "account is locked"),
+            RejectionReason::AccountNotLocked => write!(f, "account is not locked"),
+            RejectionReason::AccountNotFound(client_id) => {
+                write!(f, "client {} has no account", client_id)
+            }
+            RejectionReason::DestinationLocked(client_id) => {
+                write!(f, "destination account of client {} is locked", client_id)
+            }
+            RejectionReason::MissingDestination => write!(f, "transfer requires a destination"),
+            RejectionReason::TransferToSelf => {
+                write!(f, "transfer source and destination are the same client")
+            }
+            RejectionReason::InsufficientFunds {
+                available,
+                requested,
+                overdraft_limit,
+            } => {
+                write!(
+                    f,
+                    "insufficient funds: available {}, requested {}",
+                    available, requested
+                )?;
+                if !overdraft_limit.is_zero() {
+                    write!(f, ", overdraft limit {}", overdraft_limit)?;
+                }
+                Ok(())
+            }
+            RejectionReason::TransactionNotFound(tx_id) => {
+                write!(f, "transaction {} not found", tx_id)
+            }
+            RejectionReason::NotDisputable { tx_id, kind } => {
+                write!(
+                    f,
+                    "transaction {} is a {} and cannot be disputed",
+                    tx_id, kind
+                )
+            }
+            RejectionReason::ClientMismatch {
+                tx_id,
+                owner,
+                requested_by,
+            } => write!(
+                f,
+                "transaction {} belongs to client {}, not client {}",
+                tx_id, owner, requested_by
+            ),
+            RejectionReason::InvalidState { tx_id, state } => {
+                write!(f, "transaction {} is {}", tx_id, state)
+            }
+            RejectionReason::ExceedsDisputable {
+                tx_id,
+                requested,
+                remaining,
+            } => write!(
+                f,
+                "requested {} exceeds the {} remaining on transaction {}",
+                requested, remaining, tx_id
+            ),
+            RejectionReason::CurrencyMismatch {
+                tx_id,
+                expected,
+                requested,
+            } => write!(
+                f,
+                "transaction {} is in {}, not {}",
+                tx_id,
+                currency_name(expected),
+                currency_name(requested)
+            ),
+            RejectionReason::DisputeWindowClosed { tx_id, age, window } => write!(
+                f,
+                "transaction {} is {}s old, past the {}s dispute window",
+                tx_id, age, window
+            ),
+            RejectionReason::Storage(message) => write!(f, "storage failed: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for RejectionReason {}
+
+impl From<io::Error> for RejectionReason {
+    fn from(error: io::Error) -> Self {
+        RejectionReason::Storage(error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_category_is_stable() {
+        assert_eq!(RejectionReason::AccountLocked.category(), "account_locked");
+        assert_eq!(
+            RejectionReason::DuplicateTransaction(TransactionId(1)).category(),
+            "duplicate_tx"
+        );
+    }
+
+    #[test]
+    fn test_display_insufficient_funds() {
+        let reason = RejectionReason::InsufficientFunds {
+            available: Amount::from_str_rounded("50").expect("failed to parse amount"),
+            requested: Amount::from_str_rounded("100").expect("failed to parse amount"),
+            overdraft_limit: Amount::ZERO,
+        };
+        assert_eq!(
+            reason.to_string(),
+            "insufficient funds: available 50.0000, requested 100.0000"
+        );
+
+        let reason = RejectionReason::InsufficientFunds {
+            available: Amount::from_str_rounded("-20").expect("failed to parse amount"),
+            requested: Amount::from_str_rounded("40").expect("failed to parse amount"),
+            overdraft_limit: Amount::from_str_rounded("50").expect("failed to parse amount"),
+        };
+        assert_eq!(
+            reason.to_string(),
+            "insufficient funds: available -20.0000, requested 40.0000, overdraft limit 50.0000"
+        );
+    }
+
+    #[test]
+    fn test_display_invalid_state() {
+        let reason = RejectionReason::InvalidState {
+            tx_id: TransactionId(7),
+            state: TransactionState::ChargedBack,
+        };
+        assert_eq!(reason.to_string(), "transaction 7 is charged back");
+    }
+}
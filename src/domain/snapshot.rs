@@ -0,0 +1,159 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::io;
+
+use serde::ser::{Error as _, SerializeSeq, SerializeStruct, Serializer};
+use serde::{Deserialize, Serialize};
+
+use crate::domain::account::Account;
+use crate::domain::books::BookAccount;
+use crate::domain::ledger::StoredTransaction;
+use crate::domain::storage::Storage;
+use crate::domain::types::{Amount, ClientId, Currency, TransactionId};
+
+/// Format version written into every snapshot; bump when the layout changes.
+pub const SNAPSHOT_VERSION: u32 = 9;
+
+/// Complete, serializable state of a [`Ledger`](crate::domain::Ledger)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LedgerSnapshot {
+    pub version: u32,
+    pub accounts: Vec<AccountEntry>,
+    pub transactions: Vec<TransactionEntry>,
+    pub processed_tx_ids: Vec<TransactionId>,
+    /// Absent before version 8, when the books are opened from the accounts.
+    #[serde(default)]
+    pub books: Vec<BookEntry>,
+}
+
+/// Serializes like [`LedgerSnapshot`], reading transactions and tx ids from storage
+/// while they are written instead of collecting them first
+///
+/// They come in storage order rather than sorted by tx id. Serialization stops at the
+/// first storage error, which [`SnapshotStream::take_error`] then returns.
+pub struct SnapshotStream<'a> {
+    pub(crate) storage: &'a dyn Storage,
+    pub(crate) books: Vec<BookEntry>,
+    pub(crate) error: RefCell<Option<io::Error>>,
+}
+
+impl SnapshotStream<'_> {
+    /// The storage error that stopped serialization, if any.
+    pub fn take_error(&self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    /// Serializes `entries` as a sequence, keeping the first error.
+    fn entries<S: Serializer, T: Serialize>(
+        &self,
+        serializer: S,
+        entries: impl Iterator<Item = io::Result<T>>,
+    ) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(None)?;
+        for entry in entries {
+            match entry {
+                Ok(entry) => seq.serialize_element(&entry)?,
+                Err(error) => {
+                    let message = error.to_string();
+                    self.error.replace(Some(error));
+                    return Err(S::Error::custom(message));
+                }
+            }
+        }
+        seq.end()
+    }
+}
+
+impl Serialize for SnapshotStream<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        /// Serializes a sequence from [`SnapshotStream::entries`] as a struct field.
+        struct Entries<'s, 'a, F>(&'s SnapshotStream<'a>, F);
+
+        impl<F, I, T> Serialize for Entries<'_, '_, F>
+        where
+            F: Fn() -> I,
+            I: Iterator<Item = io::Result<T>>,
+            T: Serialize,
+        {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                self.0.entries(serializer, (self.1)())
+            }
+        }
+
+        let storage = self.storage;
+        let accounts = || {
+            storage.accounts().iter().map(|(client, account)| {
+                Ok(AccountEntry {
+                    client: *client,
+                    account: account.clone(),
+                })
+            })
+        };
+        let transactions = || {
+            storage
+                .transactions()
+                .map(|entry| entry.map(|(tx, transaction)| TransactionEntry { tx, transaction }))
+        };
+        let processed_tx_ids = || storage.processed_tx_ids();
+
+        let mut snapshot = serializer.serialize_struct("LedgerSnapshot", 5)?;
+        snapshot.serialize_field("version", &SNAPSHOT_VERSION)?;
+        snapshot.serialize_field("accounts", &Entries(self, accounts))?;
+        snapshot.serialize_field("transactions", &Entries(self, transactions))?;
+        snapshot.serialize_field("processed_tx_ids", &Entries(self, processed_tx_ids))?;
+        snapshot.serialize_field("books", &self.books)?;
+        snapshot.end()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountEntry {
+    pub client: ClientId,
+    #[serde(flatten)]
+    pub account: Account,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransactionEntry {
+    pub tx: TransactionId,
+    #[serde(flatten)]
+    pub transaction: StoredTransaction,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BookEntry {
+    pub account: BookAccount,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
+    pub balance: Amount,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    UnsupportedVersion(u32),
+    Format(String),
+    /// Reading or writing the ledger's storage failed.
+    Storage(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported snapshot version {} (expected {})",
+                version, SNAPSHOT_VERSION
+            ),
+            SnapshotError::Format(message) => write!(f, "invalid snapshot: {}", message),
+            SnapshotError::Storage(message) => write!(f, "storage failed: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(error: io::Error) -> Self {
+        SnapshotError::Storage(error.to_string())
+    }
+}
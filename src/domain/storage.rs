@@ -0,0 +1,102 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::io;
+
+use crate::domain::account::Account;
+use crate::domain::ledger::StoredTransaction;
+use crate::domain::types::{ClientId, TransactionId};
+
+/// Where a [`Ledger`](crate::domain::Ledger) keeps its accounts, stored transactions
+/// and processed tx ids
+///
+/// Accounts stay in memory with every backend, as there are at most 65536 clients;
+/// transactions and tx ids grow with the input and may live elsewhere, so reading
+/// and writing them can fail.
+pub trait Storage: fmt::Debug + Send {
+    /// Accounts in ascending client order.
+    fn accounts(&self) -> &BTreeMap<ClientId, Account>;
+
+    fn accounts_mut(&mut self) -> &mut BTreeMap<ClientId, Account>;
+
+    fn transaction(&self, tx_id: TransactionId) -> io::Result<Option<StoredTransaction>>;
+
+    /// Adds or replaces the stored transaction `tx_id`.
+    fn insert_transaction(
+        &mut self,
+        tx_id: TransactionId,
+        transaction: StoredTransaction,
+    ) -> io::Result<()>;
+
+    /// Every stored transaction, in no particular order.
+    fn transactions(
+        &self,
+    ) -> Box<dyn Iterator<Item = io::Result<(TransactionId, StoredTransaction)>> + '_>;
+
+    fn is_processed(&self, tx_id: TransactionId) -> io::Result<bool>;
+
+    fn mark_processed(&mut self, tx_id: TransactionId) -> io::Result<()>;
+
+    /// Every processed tx id, in no particular order.
+    fn processed_tx_ids(&self) -> Box<dyn Iterator<Item = io::Result<TransactionId>> + '_>;
+}
+
+/// Keeps everything in memory; the default backend
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    /// Ordered by client so output and snapshots are deterministic.
+    accounts: BTreeMap<ClientId, Account>,
+    transactions: HashMap<TransactionId, StoredTransaction>,
+    processed_tx_ids: HashSet<TransactionId>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn accounts(&self) -> &BTreeMap<ClientId, Account> {
+        &self.accounts
+    }
+
+    fn accounts_mut(&mut self) -> &mut BTreeMap<ClientId, Account> {
+        &mut self.accounts
+    }
+
+    fn transaction(&self, tx_id: TransactionId) -> io::Result<Option<StoredTransaction>> {
+        Ok(self.transactions.get(&tx_id).copied())
+    }
+
+    fn insert_transaction(
+        &mut self,
+        tx_id: TransactionId,
+        transaction: StoredTransaction,
+    ) -> io::Result<()> {
+        self.transactions.insert(tx_id, transaction);
+        Ok(())
+    }
+
+    fn transactions(
+        &self,
+    ) -> Box<dyn Iterator<Item = io::Result<(TransactionId, StoredTransaction)>> + '_> {
+        Box::new(
+            self.transactions
+                .iter()
+                .map(|(tx_id, stored)| Ok((*tx_id, *stored))),
+        )
+    }
+
+    fn is_processed(&self, tx_id: TransactionId) -> io::Result<bool> {
+        Ok(self.processed_tx_ids.contains(&tx_id))
+    }
+
+    fn mark_processed(&mut self, tx_id: TransactionId) -> io::Result<()> {
+        self.processed_tx_ids.insert(tx_id);
+        Ok(())
+    }
+
+    fn processed_tx_ids(&self) -> Box<dyn Iterator<Item = io::Result<TransactionId>> + '_> {
+        Box::new(self.processed_tx_ids.iter().copied().map(Ok))
+    }
+}
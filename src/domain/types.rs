@@ -3,7 +3,7 @@ use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::str::FromStr;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct ClientId(pub u16);
 
@@ -13,7 +13,7 @@ impl fmt::Display for ClientId {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct TransactionId(pub u32);
 
@@ -23,8 +23,78 @@ impl fmt::Display for TransactionId {
     }
 }
 
+/// Seconds since the Unix epoch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Timestamp(pub u64);
+
+impl Timestamp {
+    /// Seconds from `earlier` to `self`, zero if `earlier` is not earlier.
+    pub fn seconds_since(&self, earlier: Timestamp) -> u64 {
+        self.0.saturating_sub(earlier.0)
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Three-letter currency code such as `EUR`, stored uppercase
+///
+/// Balances without a currency use the ledger's default currency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    pub fn as_str(&self) -> &str {
+        // Only ASCII letters are accepted by `from_str`
+        std::str::from_utf8(&self.0).unwrap_or("???")
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Names `currency` in messages.
+pub(crate) fn currency_name(currency: &Option<Currency>) -> &str {
+    currency
+        .as_ref()
+        .map_or("the default currency", Currency::as_str)
+}
+
+impl FromStr for Currency {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let code: [u8; 3] = s.trim().as_bytes().try_into().map_err(|_| ())?;
+        if !code.iter().all(u8::is_ascii_alphabetic) {
+            return Err(());
+        }
+        Ok(Currency(code.map(|b| b.to_ascii_uppercase())))
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse()
+            .map_err(|_| serde::de::Error::custom(format!("invalid currency code '{}'", s)))
+    }
+}
+
 /// Decimal amount with up to 4 decimal places precision
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct Amount(pub Decimal);
 
@@ -81,13 +151,41 @@ impl fmt::Display for Amount {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum TransactionType {
     Deposit,
     Withdrawal,
     Dispute,
     Resolve,
     Chargeback,
+    Lock,
+    Unlock,
+    Transfer,
+    /// Sets the client's overdraft limit to the amount.
+    Limit,
+}
+
+impl TransactionType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Lock => "lock",
+            TransactionType::Unlock => "unlock",
+            TransactionType::Transfer => "transfer",
+            TransactionType::Limit => "limit",
+        }
+    }
+}
+
+impl fmt::Display for TransactionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 impl FromStr for TransactionType {
@@ -100,13 +198,160 @@ impl FromStr for TransactionType {
             "dispute" => Ok(TransactionType::Dispute),
             "resolve" => Ok(TransactionType::Resolve),
             "chargeback" => Ok(TransactionType::Chargeback),
+            "lock" => Ok(TransactionType::Lock),
+            "unlock" => Ok(TransactionType::Unlock),
+            "transfer" => Ok(TransactionType::Transfer),
+            "limit" => Ok(TransactionType::Limit),
             _ => Err(()),
         }
     }
 }
 
+/// A single transaction request as handed to the ledger
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transaction {
+    pub tx_type: TransactionType,
+    pub client_id: ClientId,
+    pub tx_id: TransactionId,
+    pub amount: Option<Amount>,
+    /// Receiving client of a transfer; ignored by every other type.
+    pub destination: Option<ClientId>,
+    /// `None` for the default currency.
+    pub currency: Option<Currency>,
+    /// When the transaction happened, if the input says.
+    pub timestamp: Option<Timestamp>,
+    /// Why a `lock` row freezes the account; ignored by every other type.
+    pub reason: Option<LockReason>,
+}
+
+/// Kind of a stored transaction, which decides how disputes move funds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionKind {
+    #[default]
+    Deposit,
+    Withdrawal,
+    Transfer,
+}
+
+impl fmt::Display for TransactionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransactionKind::Deposit => f.write_str("deposit"),
+            TransactionKind::Withdrawal => f.write_str("withdrawal"),
+            TransactionKind::Transfer => f.write_str("transfer"),
+        }
+    }
+}
+
+/// Why an account is locked: [`LockReason::CHARGEBACK`] after a deposit chargeback,
+/// otherwise the free text of the `lock` row, [`LockReason::ADMINISTRATIVE`] when it
+/// gives none
+///
+/// Kept inline like [`Currency`], so transactions and events stay `Copy`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LockReason {
+    len: u8,
+    bytes: [u8; LockReason::MAX_LEN],
+}
+
+impl LockReason {
+    /// Longest reason accepted, in bytes.
+    pub const MAX_LEN: usize = 64;
+
+    /// Set by deposit chargebacks; the only reason before `lock` rows existed.
+    pub const CHARGEBACK: LockReason = LockReason::from_static("chargeback");
+
+    /// Set by a `lock` row without a reason.
+    pub const ADMINISTRATIVE: LockReason = LockReason::from_static("administrative");
+
+    const fn from_static(text: &str) -> Self {
+        let mut bytes = [0; LockReason::MAX_LEN];
+        let mut i = 0;
+        while i < text.len() {
+            bytes[i] = text.as_bytes()[i];
+            i += 1;
+        }
+        LockReason {
+            len: text.len() as u8,
+            bytes,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only copied from a `&str` by `from_str`
+        std::str::from_utf8(&self.bytes[..usize::from(self.len)]).unwrap_or("???")
+    }
+}
+
+impl Default for LockReason {
+    /// Chargebacks, as in journals written before lock reasons existed.
+    fn default() -> Self {
+        LockReason::CHARGEBACK
+    }
+}
+
+impl fmt::Debug for LockReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for LockReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl PartialOrd for LockReason {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for LockReason {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+/// Trims the text, which must be non-empty, at most [`LockReason::MAX_LEN`] bytes and
+/// free of control characters.
+impl FromStr for LockReason {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let text = s.trim();
+        if text.is_empty() || text.len() > LockReason::MAX_LEN || text.chars().any(char::is_control)
+        {
+            return Err(());
+        }
+        let mut bytes = [0; LockReason::MAX_LEN];
+        bytes[..text.len()].copy_from_slice(text.as_bytes());
+        Ok(LockReason {
+            len: text.len() as u8,
+            bytes,
+        })
+    }
+}
+
+impl Serialize for LockReason {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for LockReason {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse()
+            .map_err(|_| serde::de::Error::custom(format!("invalid lock reason '{}'", s)))
+    }
+}
+
 /// State of a stored transaction (for dispute tracking)
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum TransactionState {
     #[default]
     None,
@@ -115,6 +360,30 @@ pub enum TransactionState {
     ChargedBack,
 }
 
+impl TransactionState {
+    /// Stable machine-readable name, as used in snapshots.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionState::None => "none",
+            TransactionState::Disputed => "disputed",
+            TransactionState::Resolved => "resolved",
+            TransactionState::ChargedBack => "charged_back",
+        }
+    }
+}
+
+impl fmt::Display for TransactionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            TransactionState::None => "not disputed",
+            TransactionState::Disputed => "disputed",
+            TransactionState::Resolved => "resolved",
+            TransactionState::ChargedBack => "charged back",
+        };
+        f.write_str(s)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -157,9 +426,47 @@ mod tests {
             TransactionType::from_str("Dispute"),
             Ok(TransactionType::Dispute)
         );
+        assert_eq!(TransactionType::from_str("lock"), Ok(TransactionType::Lock));
+        assert_eq!(
+            TransactionType::from_str("Unlock"),
+            Ok(TransactionType::Unlock)
+        );
+        assert_eq!(
+            TransactionType::from_str("limit"),
+            Ok(TransactionType::Limit)
+        );
         assert!(TransactionType::from_str("invalid").is_err());
     }
 
+    #[test]
+    fn test_currency_parsing() {
+        let eur = Currency::from_str(" eur ").expect("failed to parse currency");
+        assert_eq!(eur.to_string(), "EUR");
+        assert!(Currency::from_str("EURO").is_err());
+        assert!(Currency::from_str("E1R").is_err());
+        assert!(Currency::from_str("").is_err());
+    }
+
+    #[test]
+    fn test_lock_reason_parsing() {
+        let reason = LockReason::from_str(" sanctions review, case 12 ")
+            .expect("failed to parse lock reason");
+        assert_eq!(reason.to_string(), "sanctions review, case 12");
+        assert!(LockReason::from_str(&"x".repeat(LockReason::MAX_LEN)).is_ok());
+        assert!(LockReason::from_str(&"x".repeat(LockReason::MAX_LEN + 1)).is_err());
+        assert!(LockReason::from_str("line\nbreak").is_err());
+        assert!(LockReason::from_str("  ").is_err());
+
+        // Journals and snapshots from before free-text reasons name the same two
+        let parsed: LockReason =
+            serde_json::from_str(r#""administrative""#).expect("failed to read lock reason");
+        assert_eq!(parsed, LockReason::ADMINISTRATIVE);
+        assert_eq!(
+            serde_json::to_string(&LockReason::CHARGEBACK).expect("failed to write lock reason"),
+            r#""chargeback""#
+        );
+    }
+
     #[test]
     fn test_client_id_max() {
         let client = ClientId(u16::MAX);
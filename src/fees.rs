@@ -0,0 +1,43 @@
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use crate::domain::FeeSchedule;
+
+/// Parses a JSON fee schedule, rejecting negative fees and inverted caps.
+pub fn read_fee_schedule<R: Read>(reader: R) -> Result<FeeSchedule, String> {
+    let schedule: FeeSchedule = serde_json::from_reader(reader).map_err(|e| e.to_string())?;
+    schedule.validate()?;
+    Ok(schedule)
+}
+
+/// Loads a fee schedule file from disk.
+pub fn load_fee_schedule(path: &Path) -> Result<FeeSchedule, String> {
+    let file =
+        File::open(path).map_err(|e| format!("Failed to open '{}': {}", path.display(), e))?;
+    read_fee_schedule(BufReader::new(file))
+        .map_err(|e| format!("Failed to load '{}': {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_read_fee_schedule_rejects_invalid_input() {
+        let error = read_fee_schedule(Cursor::new(
+            r#"{"house_account": 0, "fees": {"deposit": {"flat": "-1"}}}"#,
+        ))
+        .unwrap_err();
+        assert_eq!(error, "invalid deposit fee: fees cannot be negative");
+
+        let error = read_fee_schedule(Cursor::new(
+            r#"{"house_account": 0, "fees": {"refund": {"flat": "1"}}}"#,
+        ))
+        .unwrap_err();
+        assert!(error.contains("unknown variant `refund`"), "{}", error);
+
+        assert!(read_fee_schedule(Cursor::new(r#"{"house_account": 0}"#)).is_ok());
+    }
+}
@@ -0,0 +1,555 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+
+use serde_json::{json, Value};
+
+use crate::domain::ledger::StoredTransaction;
+use crate::domain::types::{ClientId, Currency, TransactionId};
+use crate::domain::{Ledger, ProcessOutcome, RejectionReason};
+use crate::parser::{parse_json, ParseErrorKind};
+use crate::server::{
+    line_too_long, lock, not_utf8, read_line, ReadLine, Server, LEDGER_UNAVAILABLE,
+};
+use crate::writer::OutputRecord;
+
+/// Largest request body accepted, far above any single transaction.
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// Binds a [`Server`] answering JSON requests over HTTP/1.1:
+///
+/// - `POST /transactions` applies one transaction
+/// - `GET /accounts` lists every balance, `GET /accounts/{client}` those of one client
+/// - `GET /transactions/{tx}` returns a stored transaction with its dispute state
+///
+/// Amounts are strings with 4 decimal places, as in the CSV output.
+pub fn bind(addr: impl ToSocketAddrs, ledger: Ledger) -> io::Result<Server> {
+    Ok(Server::bind(addr, ledger)?.with_handler(handle_connection, refuse_connection))
+}
+
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub body: Value,
+}
+
+impl Response {
+    fn ok(body: Value) -> Self {
+        Response { status: 200, body }
+    }
+
+    fn error(status: u16, category: &str, message: impl std::fmt::Display) -> Self {
+        Response {
+            status,
+            body: json!({ "error": category, "message": message.to_string() }),
+        }
+    }
+}
+
+/// Answers a single request per connection.
+pub fn handle_connection(stream: TcpStream, ledger: &Mutex<Ledger>) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let response = match read_request(&mut reader) {
+        Ok(Some(request)) => handle(ledger, &request),
+        Ok(None) => return Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => Response::error(400, "bad_request", e),
+        Err(e) => return Err(e),
+    };
+    write_response(stream, &response)
+}
+
+/// Answers 503 without reading the request, for connections over the server's limit.
+pub fn refuse_connection(stream: TcpStream, error: io::Error) -> io::Result<()> {
+    write_response(stream, &unavailable(error))
+}
+
+/// Routes a request to the ledger.
+pub fn handle(ledger: &Mutex<Ledger>, request: &Request) -> Response {
+    let path = request.path.split('?').next().unwrap_or_default();
+    let segments: Vec<_> = path.trim_matches('/').split('/').collect();
+    match (request.method.as_str(), segments.as_slice()) {
+        ("POST", ["transactions"]) => submit(ledger, &request.body),
+        ("GET", ["accounts"]) => list_accounts(ledger),
+        ("GET", ["accounts", client]) => get_account(ledger, client),
+        ("GET", ["transactions", tx]) => get_transaction(ledger, tx),
+        (_, ["transactions" | "accounts"] | ["transactions" | "accounts", _]) => {
+            Response::error(405, "method_not_allowed", "method not allowed")
+        }
+        _ => Response::error(404, "not_found", format!("no route for '{}'", path)),
+    }
+}
+
+/// The body is an object with the same fields as a CSV row, validated like an NDJSON row.
+fn submit(ledger: &Mutex<Ledger>, body: &[u8]) -> Response {
+    let Ok(body) = std::str::from_utf8(body) else {
+        return Response::error(400, "invalid_json", "request body is not UTF-8");
+    };
+    let transaction = match parse_json(body, 1) {
+        Ok(record) => record.transaction(),
+        Err(e) if e.kind == ParseErrorKind::Json => {
+            return Response::error(400, "invalid_json", e.message)
+        }
+        Err(e) => return Response::error(400, e.kind.category(), e.message),
+    };
+    let result = match lock(ledger) {
+        Ok(mut ledger) => ledger.process_transaction(&transaction),
+        Err(e) => return unavailable(e),
+    };
+    match result {
+        Ok(outcome) => Response::ok(outcome_json(outcome)),
+        // The ledger refuses every later transaction, like a poisoned one
+        Err(reason @ RejectionReason::Storage(_)) => {
+            Response::error(503, LEDGER_UNAVAILABLE, reason)
+        }
+        Err(reason) => Response::error(422, reason.category(), reason),
+    }
+}
+
+fn list_accounts(ledger: &Mutex<Ledger>) -> Response {
+    let ledger = match lock(ledger) {
+        Ok(ledger) => ledger,
+        Err(e) => return unavailable(e),
+    };
+    let records: Vec<_> = ledger
+        .accounts()
+        .iter()
+        .flat_map(|(client_id, account)| OutputRecord::from_account_balances(*client_id, account))
+        .collect();
+    Response::ok(json!(records))
+}
+
+/// One entry per currency held by the client, like the rows of the CSV output.
+fn get_account(ledger: &Mutex<Ledger>, client: &str) -> Response {
+    let Ok(client_id) = client.parse().map(ClientId) else {
+        return Response::error(
+            400,
+            ParseErrorKind::InvalidClient.category(),
+            format!("Invalid client ID: '{}'", client),
+        );
+    };
+    let ledger = match lock(ledger) {
+        Ok(ledger) => ledger,
+        Err(e) => return unavailable(e),
+    };
+    match ledger.get_account(client_id) {
+        Some(account) => Response::ok(json!(OutputRecord::from_account_balances(
+            client_id, account
+        ))),
+        None => Response::error(
+            404,
+            "account_not_found",
+            format!("client {} has no account", client_id),
+        ),
+    }
+}
+
+fn get_transaction(ledger: &Mutex<Ledger>, tx: &str) -> Response {
+    let Ok(tx_id) = tx.parse().map(TransactionId) else {
+        return Response::error(
+            400,
+            ParseErrorKind::InvalidTransaction.category(),
+            format!("Invalid transaction ID: '{}'", tx),
+        );
+    };
+    let ledger = match lock(ledger) {
+        Ok(ledger) => ledger,
+        Err(e) => return unavailable(e),
+    };
+    match ledger.get_transaction(tx_id) {
+        Ok(Some(transaction)) => Response::ok(transaction_json(tx_id, &transaction)),
+        Ok(None) => Response::error(
+            404,
+            "tx_not_found",
+            format!("transaction {} not found", tx_id),
+        ),
+        Err(e) => unavailable(e),
+    }
+}
+
+/// Answers every request once the ledger is no longer served, or a storage read failed,
+/// and connections over the server's limit.
+fn unavailable(error: io::Error) -> Response {
+    Response::error(503, LEDGER_UNAVAILABLE, error)
+}
+
+fn outcome_json(outcome: ProcessOutcome) -> Value {
+    let (name, amount) = match outcome {
+        ProcessOutcome::Deposited(amount) => ("deposited", Some(amount)),
+        ProcessOutcome::Withdrawn(amount) => ("withdrawn", Some(amount)),
+        ProcessOutcome::Held(amount) => ("held", Some(amount)),
+        ProcessOutcome::Released(amount) => ("released", Some(amount)),
+        ProcessOutcome::ChargedBack(amount) => ("charged_back", Some(amount)),
+        ProcessOutcome::Transferred(amount) => ("transferred", Some(amount)),
+        ProcessOutcome::Locked => ("locked", None),
+        ProcessOutcome::Unlocked => ("unlocked", None),
+        ProcessOutcome::LimitSet(limit) => ("limit_set", Some(limit)),
+    };
+    json!({ "outcome": name, "amount": amount.map(|amount| amount.to_string()) })
+}
+
+fn transaction_json(tx_id: TransactionId, transaction: &StoredTransaction) -> Value {
+    json!({
+        "tx": tx_id,
+        "client": transaction.client_id,
+        "kind": transaction.kind.to_string(),
+        "amount": transaction.amount.to_string(),
+        "state": transaction.state.as_str(),
+        "disputed": transaction.disputed.to_string(),
+        "settled": transaction.settled.to_string(),
+        "charged_back": transaction.charged_back.to_string(),
+        "destination": transaction.destination,
+        "currency": transaction.currency.as_ref().map(Currency::as_str),
+        "timestamp": transaction.timestamp,
+    })
+}
+
+/// Reads the request line, headers and body; `None` when the client sent nothing.
+fn read_request(reader: &mut impl BufRead) -> io::Result<Option<Request>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+
+    let mut next_line = || match read_line(reader)? {
+        ReadLine::Text(line) => Ok(Some(line)),
+        ReadLine::End => Ok(None),
+        ReadLine::TooLong => Err(invalid(line_too_long())),
+        ReadLine::NotUtf8 => Err(invalid(not_utf8().to_string())),
+    };
+
+    let Some(line) = next_line()? else {
+        return Ok(None);
+    };
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(invalid(format!(
+            "malformed request line: '{}'",
+            line.trim()
+        )));
+    };
+    let (method, path) = (method.to_string(), path.to_string());
+
+    let mut content_length = 0;
+    while let Some(line) = next_line()? {
+        let header = line.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid(format!("invalid content length: '{}'", value.trim())))?;
+            }
+        }
+    }
+    if content_length > MAX_BODY_BYTES {
+        return Err(invalid(format!(
+            "request body exceeds {} bytes",
+            MAX_BODY_BYTES
+        )));
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    Ok(Some(Request { method, path, body }))
+}
+
+fn write_response(mut stream: impl Write, response: &Response) -> io::Result<()> {
+    let body = serde_json::to_vec(&response.body)?;
+    let reason = match response.status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        422 => "Unprocessable Entity",
+        503 => "Service Unavailable",
+        _ => "",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason,
+        body.len()
+    )?;
+    stream.write_all(&body)?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::thread;
+
+    fn request(method: &str, path: &str, body: &str) -> Request {
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    fn post(ledger: &Mutex<Ledger>, body: &str) -> Response {
+        handle(ledger, &request("POST", "/transactions", body))
+    }
+
+    #[test]
+    fn test_submit_applies_transaction() {
+        let ledger = Mutex::new(Ledger::new());
+        let response = post(
+            &ledger,
+            r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "2.5"}"#,
+        );
+        assert_eq!(
+            response,
+            Response::ok(json!({ "outcome": "deposited", "amount": "2.5000" }))
+        );
+
+        let response = post(
+            &ledger,
+            r#"{"type": "withdrawal", "client": 1, "tx": 2, "amount": "3"}"#,
+        );
+        assert_eq!(response.status, 422);
+        assert_eq!(response.body["error"], "insufficient_funds");
+
+        let response = post(
+            &ledger,
+            r#"{"type": "deposit", "client": 1, "tx": 3, "amount": 0.12346}"#,
+        );
+        assert_eq!(
+            response,
+            Response::ok(json!({ "outcome": "deposited", "amount": "0.1235" }))
+        );
+    }
+
+    #[test]
+    fn test_submit_rejects_malformed_body() {
+        let ledger = Mutex::new(Ledger::new());
+        let cases = [
+            (r#"{"type": "deposit", "client": 1"#, "invalid_json"),
+            (r#"["deposit", 1, 1, "2"]"#, "invalid_json"),
+            (r#"{"type": "deposit", "client": 1}"#, "invalid_tx"),
+            (
+                r#"{"type": "refund", "client": 1, "tx": 1}"#,
+                "unknown_type",
+            ),
+            (
+                r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "1e2"}"#,
+                "invalid_amount",
+            ),
+            (
+                r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "-2"}"#,
+                "negative_amount",
+            ),
+            (
+                r#"{"type": "withdrawal", "client": 1, "tx": 1}"#,
+                "missing_amount",
+            ),
+            (
+                r#"{"type": "transfer", "client": 1, "tx": 1, "amount": "2"}"#,
+                "missing_destination",
+            ),
+        ];
+        for (body, category) in cases {
+            let response = post(&ledger, body);
+            assert_eq!(response.status, 400);
+            assert_eq!(response.body["error"], category);
+        }
+        assert!(lock(&ledger)
+            .expect("ledger should be available")
+            .accounts()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_get_accounts() {
+        let ledger = Mutex::new(Ledger::new());
+        for (client, tx) in [(2, 1), (1, 2)] {
+            let body = format!(
+                r#"{{"type": "deposit", "client": {}, "tx": {}, "amount": "1"}}"#,
+                client, tx
+            );
+            assert_eq!(post(&ledger, &body).status, 200);
+        }
+
+        let response = handle(&ledger, &request("GET", "/accounts", ""));
+        let clients: Vec<_> = response
+            .body
+            .as_array()
+            .expect("accounts should be a list")
+            .iter()
+            .map(|account| account["client"].clone())
+            .collect();
+        assert_eq!(clients, vec![json!(1), json!(2)]);
+
+        let response = handle(&ledger, &request("GET", "/accounts/2", ""));
+        assert_eq!(
+            response,
+            Response::ok(json!([{
+                "client": 2,
+                "available": "1.0000",
+                "held": "0.0000",
+                "total": "1.0000",
+                "locked": false,
+                "lock_reason": null,
+                "currency": null,
+                "overdraft_limit": "0.0000",
+                "headroom": "1.0000",
+            }]))
+        );
+        assert_eq!(
+            handle(&ledger, &request("GET", "/accounts/3", "")).status,
+            404
+        );
+        assert_eq!(
+            handle(&ledger, &request("GET", "/accounts/abc", "")).status,
+            400
+        );
+    }
+
+    #[test]
+    fn test_get_transaction_dispute_state() {
+        let ledger = Mutex::new(Ledger::new());
+        post(
+            &ledger,
+            r#"{"type": "deposit", "client": 1, "tx": 7, "amount": "10"}"#,
+        );
+        post(
+            &ledger,
+            r#"{"type": "dispute", "client": 1, "tx": 7, "amount": "4"}"#,
+        );
+        post(
+            &ledger,
+            r#"{"type": "chargeback", "client": 1, "tx": 7, "amount": "1"}"#,
+        );
+
+        let response = handle(&ledger, &request("GET", "/transactions/7", ""));
+        assert_eq!(
+            response,
+            Response::ok(json!({
+                "tx": 7,
+                "client": 1,
+                "kind": "deposit",
+                "amount": "10.0000",
+                "state": "disputed",
+                "disputed": "3.0000",
+                "settled": "1.0000",
+                "charged_back": "1.0000",
+                "destination": null,
+                "currency": null,
+                "timestamp": null,
+            }))
+        );
+        assert_eq!(
+            handle(&ledger, &request("GET", "/transactions/8", "")).status,
+            404
+        );
+    }
+
+    #[test]
+    fn test_unknown_routes() {
+        let ledger = Mutex::new(Ledger::new());
+        assert_eq!(handle(&ledger, &request("GET", "/", "")).status, 404);
+        assert_eq!(
+            handle(&ledger, &request("DELETE", "/accounts/1", "")).status,
+            405
+        );
+    }
+
+    #[test]
+    fn test_poisoned_ledger_is_unavailable() {
+        let ledger = Mutex::new(Ledger::new());
+        let _ = thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let _guard = ledger.lock();
+                    panic!("panic while holding the ledger");
+                })
+                .join()
+        });
+        for response in [
+            post(
+                &ledger,
+                r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "1"}"#,
+            ),
+            handle(&ledger, &request("GET", "/accounts", "")),
+            handle(&ledger, &request("GET", "/transactions/1", "")),
+        ] {
+            assert_eq!(response.status, 503);
+            assert_eq!(response.body["error"], "ledger_unavailable");
+
+            let mut written = Vec::new();
+            write_response(&mut written, &response).expect("failed to write response");
+            assert!(written.starts_with(b"HTTP/1.1 503 Service Unavailable\r\n"));
+        }
+    }
+
+    #[test]
+    fn test_long_header_line_is_refused() {
+        let head = format!(
+            "GET /accounts HTTP/1.1\r\nX-Padding: {}\r\n\r\n",
+            "x".repeat(crate::server::MAX_LINE_BYTES)
+        );
+        let error = read_request(&mut io::Cursor::new(head)).expect_err("line should be refused");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "line exceeds 65536 bytes");
+    }
+
+    #[test]
+    fn test_invalid_utf8_request_line_is_refused() {
+        let error = read_request(&mut io::Cursor::new(b"GET /\xff HTTP/1.1\r\n\r\n"))
+            .expect_err("line should be refused");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "line is not valid UTF-8");
+    }
+
+    #[test]
+    fn test_connections_over_limit_are_unavailable() {
+        let server = bind("127.0.0.1:0", Ledger::new())
+            .expect("failed to bind server")
+            .with_max_connections(0);
+        let addr = server.local_addr().expect("server should have an address");
+        thread::spawn(move || server.run());
+
+        let mut response = String::new();
+        TcpStream::connect(addr)
+            .expect("failed to connect")
+            .read_to_string(&mut response)
+            .expect("failed to read response");
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(response.ends_with(
+            r#"{"error":"ledger_unavailable","message":"the server is serving its limit of 0 connections"}"#
+        ));
+    }
+
+    #[test]
+    fn test_serves_over_loopback() {
+        let server = bind("127.0.0.1:0", Ledger::new()).expect("failed to bind server");
+        let addr = server.local_addr().expect("server should have an address");
+        thread::spawn(move || server.run());
+
+        let body = r#"{"type":"deposit","client":1,"tx":1,"amount":"1.25"}"#;
+        let mut stream = TcpStream::connect(addr).expect("failed to connect");
+        write!(
+            stream,
+            "POST /transactions HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .expect("failed to send request");
+        let mut response = String::new();
+        stream
+            .read_to_string(&mut response)
+            .expect("failed to read response");
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(r#"{"amount":"1.2500","outcome":"deposited"}"#));
+    }
+}
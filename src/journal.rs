@@ -0,0 +1,131 @@
+use std::io::{BufRead, Write};
+
+use crate::domain::LedgerEvent;
+
+/// A journal line that could not be decoded
+#[derive(Debug)]
+pub struct JournalError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for JournalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Journal line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for JournalError {}
+
+/// Appends ledger events as newline-delimited JSON, one event per line.
+pub struct JournalWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JournalWriter<W> {
+    pub fn new(writer: W) -> Self {
+        JournalWriter { writer }
+    }
+
+    pub fn append(&mut self, event: &LedgerEvent) -> std::io::Result<()> {
+        serde_json::to_writer(&mut self.writer, event)?;
+        self.writer.write_all(b"\n")
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Reads events back from a journal written by [`JournalWriter`], skipping blank lines.
+pub fn read_journal<R: BufRead>(
+    reader: R,
+) -> impl Iterator<Item = Result<LedgerEvent, JournalError>> {
+    reader.lines().enumerate().filter_map(|(index, line)| {
+        let line_number = index + 1;
+        match line {
+            Ok(text) if text.trim().is_empty() => None,
+            Ok(text) => Some(serde_json::from_str(&text).map_err(|e| JournalError {
+                line: line_number,
+                message: format!("Invalid event: {}", e),
+            })),
+            Err(e) => Some(Err(JournalError {
+                line: line_number,
+                message: format!("Read error: {}", e),
+            })),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::types::LockReason;
+    use crate::domain::{Amount, ClientId, TransactionId};
+    use std::io::Cursor;
+
+    fn amount(s: &str) -> Amount {
+        Amount::from_str_rounded(s).expect("failed to parse amount")
+    }
+
+    #[test]
+    fn test_journal_round_trip() {
+        let events = vec![
+            LedgerEvent::DepositApplied {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: amount("100.5"),
+                currency: None,
+                timestamp: None,
+            },
+            LedgerEvent::AccountLocked {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                reason: LockReason::CHARGEBACK,
+            },
+            LedgerEvent::OverdraftLimitSet {
+                client: ClientId(2),
+                tx: None,
+                limit: amount("50"),
+            },
+        ];
+
+        let mut output = Vec::new();
+        let mut journal = JournalWriter::new(&mut output);
+        for event in &events {
+            journal.append(event).expect("failed to append event");
+        }
+
+        let read: Vec<_> = read_journal(Cursor::new(output))
+            .collect::<Result<_, _>>()
+            .expect("journal should decode");
+        assert_eq!(read, events);
+    }
+
+    #[test]
+    fn test_journal_line_format() {
+        let mut output = Vec::new();
+        JournalWriter::new(&mut output)
+            .append(&LedgerEvent::FundsHeld {
+                client: ClientId(2),
+                tx: TransactionId(7),
+                amount: amount("1.25"),
+                timestamp: None,
+            })
+            .expect("failed to append event");
+        let text = String::from_utf8(output).expect("journal should be valid UTF-8");
+        assert_eq!(
+            text,
+            "{\"event\":\"funds_held\",\"client\":2,\"tx\":7,\"amount\":\"1.25\"}\n"
+        );
+    }
+
+    #[test]
+    fn test_invalid_journal_line_reports_line_number() {
+        let input = "{\"event\":\"account_locked\",\"client\":1,\"tx\":1}\n\nnot json\n";
+        let results: Vec<_> = read_journal(Cursor::new(input)).collect();
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_ok());
+        assert_eq!(results[1].as_ref().unwrap_err().line, 3);
+    }
+}
@@ -1,3 +1,14 @@
+pub mod client_config;
 pub mod domain;
+pub mod fees;
+pub mod http;
+pub mod journal;
 pub mod parser;
+pub mod policy;
+pub mod server;
+pub mod sharded;
+pub mod snapshot;
+pub mod statement;
+pub mod storage;
+pub mod validate;
 pub mod writer;
@@ -1,11 +1,95 @@
 use std::env;
-use std::fs::File;
-use std::io::{self, BufReader};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
 use std::process;
 
-use simple_rust_ledger::domain::Ledger;
-use simple_rust_ledger::parser::CsvParser;
-use simple_rust_ledger::writer::{write_csv, OutputRecord};
+use simple_rust_ledger::client_config::load_client_config;
+use simple_rust_ledger::domain::types::{ClientId, Transaction};
+use simple_rust_ledger::domain::{Ledger, LedgerPolicy, RejectionReason};
+use simple_rust_ledger::fees::load_fee_schedule;
+use simple_rust_ledger::journal::{read_journal, JournalWriter};
+use simple_rust_ledger::parser::{InputFormat, RecordSource};
+use simple_rust_ledger::policy::load_policy;
+use simple_rust_ledger::server::Server;
+use simple_rust_ledger::sharded::ShardedLedger;
+use simple_rust_ledger::snapshot::{load_snapshot, save_snapshot, tmp_path};
+use simple_rust_ledger::statement::Statement;
+use simple_rust_ledger::storage::DiskStorage;
+use simple_rust_ledger::validate::validate;
+use simple_rust_ledger::writer::{
+    FeeRecord, FeeWriter, OutputFormat, OutputRecord, RejectionRecord, RejectionWriter, SortOrder,
+};
+
+const USAGE: &str = "Usage: simple-rust-ledger [OPTIONS] <transactions.csv>
+       simple-rust-ledger serve [OPTIONS] [address]
+       simple-rust-ledger validate [OPTIONS] <transactions.csv>
+       simple-rust-ledger statement [OPTIONS] <client> <transactions.csv>
+       simple-rust-ledger audit [OPTIONS] [transactions.csv]
+
+The serve mode listens on a TCP address (default 127.0.0.1:7878) and applies the CSV
+rows sent by every connection to one ledger; send `dump` for the current balances.
+With --http it serves a JSON API instead (POST /transactions, GET /accounts,
+GET /accounts/<client>, GET /transactions/<tx>). It accepts --replay,
+--load-snapshot and the dispute options.
+
+The validate mode runs the input through a throwaway ledger and prints a summary
+instead of the accounts, exiting with an error if any line would be dropped.
+It accepts --input-format, --replay, --load-snapshot and the dispute options.
+
+The statement mode processes the input like a normal run, then prints the client's
+activity with the running balances instead of the accounts. Opening balances are
+taken after --replay or --load-snapshot. It accepts every option but --shards,
+--output-format and --sort.
+
+The audit mode processes the input like a normal run, then checks the ledger's
+invariants (held funds against open disputes, dispute states, the double-entry
+books) and prints the violations instead of the accounts, exiting with an error if
+there are any. The input is optional with --replay or --load-snapshot. It accepts
+every option but --output-format and --sort.
+
+Options:
+  --input-format <csv|json|ndjson>
+                       Input format; guessed from the file extension by default
+                       (.json for a JSON array, .ndjson or .jsonl for NDJSON)
+  --output-format <csv|json|ndjson|table>
+                       Format of the accounts written to stdout (default csv)
+  --sort <column>[:asc|desc]
+                       Sort accounts by an output column (default: client, ascending)
+  --strict             Fail at the first malformed or rejected line, writing no
+                       accounts, snapshot or journal (no rejections report
+                       or shards)
+  --rejections <path>  Write dropped lines to a CSV report
+  --journal <path>     Append every applied ledger event to a journal
+  --replay <path>      Rebuild the ledger from a journal before processing
+                       (the input file is optional when restoring state)
+  --load-snapshot <path>  Start from a saved ledger snapshot
+  --save-snapshot <path>  Save the ledger state after processing
+  --dispute-withdrawals   Allow disputes, resolves and chargebacks on withdrawals
+  --policy <path>         Set the dispute and lock rules from a JSON file
+                          (dispute_withdrawals, redispute_resolved,
+                          dispute_locked_accounts, dispute_into_negative,
+                          deposit_to_locked_accounts)
+  --client-config <path>  Set per-client overdraft limits from a CSV file with
+                          the columns client,overdraft_limit
+  --fee-schedule <path>   Charge fees to clients, paid to a house account, from a
+                          JSON schedule of flat and percentage fees per type
+  --fee-report <path>     Write every charged fee to a CSV report
+  --dispute-window <duration>
+                          Reject disputes arriving later than this after the
+                          transaction, e.g. 90d (needs a timestamp column)
+  --auto-resolve <duration>
+                          Resolve disputes still open this long after they were
+                          opened; durations are seconds or end in s, m, h or d
+  --disk-storage <path>   Keep transactions in a scratch file at path (and path.ids)
+                          instead of memory, for inputs too large for RAM
+  --shards <n>            Process clients on n worker threads (no journal, replay,
+                          snapshot loading, disk storage, client config, fees or
+                          dispute time limits; the first transfer between
+                          shards merges them and finishes on one thread)";
+
+/// Address the serve mode listens on when none is given.
+const DEFAULT_LISTEN: &str = "127.0.0.1:7878";
 
 fn main() {
     if let Err(e) = run() {
@@ -14,50 +98,782 @@ fn main() {
     }
 }
 
-fn run() -> Result<(), String> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        return Err(format!(
-            "Usage: {} <transactions.csv>\nExpected exactly 1 argument, got {}",
-            args[0],
-            args.len() - 1
-        ));
-    }
+struct Options {
+    input: Option<String>,
+    /// Address to listen on, `Some` in serve mode only.
+    listen: Option<String>,
+    http: bool,
+    /// Print a dry-run summary instead of the accounts.
+    validate: bool,
+    /// Client whose statement is printed instead of the accounts.
+    statement: Option<ClientId>,
+    /// Print the invariant violations instead of the accounts.
+    audit: bool,
+    input_format: Option<InputFormat>,
+    output_format: OutputFormat,
+    sort: Option<SortOrder>,
+    strict: bool,
+    rejections: Option<String>,
+    journal: Option<String>,
+    replay: Option<String>,
+    load_snapshot: Option<String>,
+    save_snapshot: Option<String>,
+    dispute_withdrawals: bool,
+    policy: Option<LedgerPolicy>,
+    client_config: Option<String>,
+    fee_schedule: Option<String>,
+    fee_report: Option<String>,
+    /// Seconds, see `Ledger::with_dispute_window`.
+    dispute_window: Option<u64>,
+    /// Seconds, see `Ledger::with_auto_resolve`.
+    auto_resolve: Option<u64>,
+    /// Scratch file for `DiskStorage`.
+    disk_storage: Option<String>,
+    shards: Option<usize>,
+}
 
-    let file_path = &args[1];
+impl Options {
+    fn from_args() -> Result<Self, String> {
+        let args: Vec<String> = env::args().skip(1).collect();
+        let serve = args.first().is_some_and(|arg| arg == "serve");
+        let validate = args.first().is_some_and(|arg| arg == "validate");
+        let statement_mode = args.first().is_some_and(|arg| arg == "statement");
+        let audit = args.first().is_some_and(|arg| arg == "audit");
+        let mut statement = None;
+        let mut input = None;
+        let mut rejections = None;
+        let mut journal = None;
+        let mut replay = None;
+        let mut load_snapshot = None;
+        let mut save_snapshot = None;
+        let mut dispute_withdrawals = false;
+        let mut policy = None;
+        let mut client_config = None;
+        let mut fee_schedule = None;
+        let mut fee_report = None;
+        let mut dispute_window = None;
+        let mut auto_resolve = None;
+        let mut disk_storage = None;
+        let mut shards = None;
+        let mut http = false;
+        let mut input_format = None;
+        let mut output_format = None;
+        let mut sort = None;
+        let mut strict = false;
 
-    let file =
-        File::open(file_path).map_err(|e| format!("Failed to open '{}': {}", file_path, e))?;
-    let reader = BufReader::new(file);
+        let mut i = usize::from(serve || validate || statement_mode || audit);
+        while i < args.len() {
+            match args[i].as_str() {
+                "--input-format" => {
+                    i += 1;
+                    let value = args.get(i).ok_or("Missing value for --input-format")?;
+                    input_format = Some(value.parse()?);
+                }
+                "--output-format" => {
+                    i += 1;
+                    let value = args.get(i).ok_or("Missing value for --output-format")?;
+                    output_format = Some(value.parse()?);
+                }
+                "--sort" => {
+                    i += 1;
+                    let value = args.get(i).ok_or("Missing value for --sort")?;
+                    sort = Some(value.parse()?);
+                }
+                "--strict" => strict = true,
+                "--rejections" => {
+                    i += 1;
+                    rejections = Some(args.get(i).ok_or("Missing value for --rejections")?.clone());
+                }
+                "--journal" => {
+                    i += 1;
+                    journal = Some(args.get(i).ok_or("Missing value for --journal")?.clone());
+                }
+                "--replay" => {
+                    i += 1;
+                    replay = Some(args.get(i).ok_or("Missing value for --replay")?.clone());
+                }
+                "--load-snapshot" => {
+                    i += 1;
+                    load_snapshot = Some(
+                        args.get(i)
+                            .ok_or("Missing value for --load-snapshot")?
+                            .clone(),
+                    );
+                }
+                "--save-snapshot" => {
+                    i += 1;
+                    save_snapshot = Some(
+                        args.get(i)
+                            .ok_or("Missing value for --save-snapshot")?
+                            .clone(),
+                    );
+                }
+                "--dispute-withdrawals" => dispute_withdrawals = true,
+                "--policy" => {
+                    i += 1;
+                    let path = args.get(i).ok_or("Missing value for --policy")?;
+                    policy = Some(load_policy(Path::new(path))?);
+                }
+                "--client-config" => {
+                    i += 1;
+                    client_config = Some(
+                        args.get(i)
+                            .ok_or("Missing value for --client-config")?
+                            .clone(),
+                    );
+                }
+                "--fee-schedule" => {
+                    i += 1;
+                    fee_schedule = Some(
+                        args.get(i)
+                            .ok_or("Missing value for --fee-schedule")?
+                            .clone(),
+                    );
+                }
+                "--fee-report" => {
+                    i += 1;
+                    fee_report = Some(args.get(i).ok_or("Missing value for --fee-report")?.clone());
+                }
+                "--dispute-window" => {
+                    i += 1;
+                    let value = args.get(i).ok_or("Missing value for --dispute-window")?;
+                    dispute_window = Some(parse_duration(value)?);
+                }
+                "--auto-resolve" => {
+                    i += 1;
+                    let value = args.get(i).ok_or("Missing value for --auto-resolve")?;
+                    auto_resolve = Some(parse_duration(value)?);
+                }
+                "--disk-storage" => {
+                    i += 1;
+                    disk_storage = Some(
+                        args.get(i)
+                            .ok_or("Missing value for --disk-storage")?
+                            .clone(),
+                    );
+                }
+                "--http" if serve => http = true,
+                "--shards" => {
+                    i += 1;
+                    let value = args.get(i).ok_or("Missing value for --shards")?;
+                    shards = match value.parse::<usize>() {
+                        Ok(n) if n > 0 => Some(n),
+                        _ => return Err(format!("Invalid value for --shards: '{}'", value)),
+                    };
+                }
+                "-h" | "--help" => {
+                    eprintln!("{}", USAGE);
+                    process::exit(0);
+                }
+                arg if arg.starts_with("--") => {
+                    return Err(format!("Unknown argument: {}\n{}", arg, USAGE))
+                }
+                arg if statement_mode && statement.is_none() => {
+                    statement = Some(
+                        arg.parse()
+                            .map(ClientId)
+                            .map_err(|_| format!("Invalid client: '{}'", arg))?,
+                    );
+                }
+                arg => {
+                    if input.is_some() {
+                        return Err(format!("Expected exactly 1 input file\n{}", USAGE));
+                    }
+                    input = Some(arg.to_string());
+                }
+            }
+            i += 1;
+        }
 
-    let parser = CsvParser::new(reader)?;
+        // Only the accounts written by a normal run are formatted and sorted
+        let formats_accounts = output_format.is_some() || sort.is_some();
+        if serve {
+            // Clients send rows in the format of their protocol, not of an input file
+            if shards.is_some()
+                || journal.is_some()
+                || rejections.is_some()
+                || save_snapshot.is_some()
+                || fee_report.is_some()
+                || strict
+                || formats_accounts
+                || input_format.is_some()
+            {
+                return Err(
+                    "serve cannot be combined with --shards, --journal, --rejections, --save-snapshot, --fee-report, --strict, --output-format, --sort or --input-format"
+                        .to_string(),
+                );
+            }
+            if replay.is_some() && load_snapshot.is_some() {
+                return Err("--replay and --load-snapshot cannot be combined".to_string());
+            }
+            return Ok(Options {
+                input: None,
+                listen: Some(input.unwrap_or_else(|| DEFAULT_LISTEN.to_string())),
+                http,
+                validate,
+                statement,
+                audit,
+                input_format,
+                output_format: output_format.unwrap_or_default(),
+                sort,
+                strict,
+                rejections,
+                journal,
+                replay,
+                load_snapshot,
+                save_snapshot,
+                dispute_withdrawals,
+                policy,
+                client_config,
+                fee_schedule,
+                fee_report,
+                dispute_window,
+                auto_resolve,
+                disk_storage,
+                shards,
+            });
+        }
 
-    let mut ledger = Ledger::new();
-    for result in parser {
-        match result {
-            Ok(record) => {
-                ledger.process(
-                    record.tx_type,
-                    record.client_id,
-                    record.tx_id,
-                    record.amount,
+        if validate {
+            if input.is_none() {
+                return Err(format!("Missing input file\n{}", USAGE));
+            }
+            if shards.is_some()
+                || journal.is_some()
+                || rejections.is_some()
+                || save_snapshot.is_some()
+                || fee_report.is_some()
+                || strict
+                || formats_accounts
+            {
+                return Err(
+                    "validate cannot be combined with --shards, --journal, --rejections, --save-snapshot, --fee-report, --strict, --output-format or --sort"
+                        .to_string(),
                 );
             }
-            Err(e) => {
-                eprintln!("Warning: {}", e);
+        }
+        if statement_mode {
+            if statement.is_none() || input.is_none() {
+                return Err(format!("Missing client or input file\n{}", USAGE));
             }
+            if shards.is_some() || formats_accounts {
+                return Err(
+                    "statement cannot be combined with --shards, --output-format or --sort"
+                        .to_string(),
+                );
+            }
+        }
+        if audit && formats_accounts {
+            return Err("audit cannot be combined with --output-format or --sort".to_string());
+        }
+        if input.is_none() && replay.is_none() && load_snapshot.is_none() {
+            return Err(format!("Missing input file\n{}", USAGE));
+        }
+        if replay.is_some() && load_snapshot.is_some() {
+            return Err("--replay and --load-snapshot cannot be combined".to_string());
+        }
+        if strict && rejections.is_some() {
+            return Err("--strict and --rejections cannot be combined".to_string());
+        }
+        if strict && shards.is_some() {
+            return Err("--strict and --shards cannot be combined".to_string());
+        }
+        if shards.is_some()
+            && (journal.is_some()
+                || replay.is_some()
+                || load_snapshot.is_some()
+                || disk_storage.is_some()
+                || client_config.is_some()
+                || fee_schedule.is_some()
+                || dispute_window.is_some()
+                || auto_resolve.is_some())
+        {
+            return Err(
+                "--shards cannot be combined with --journal, --replay, --load-snapshot, --disk-storage, --client-config, --fee-schedule, --dispute-window or --auto-resolve"
+                    .to_string(),
+            );
+        }
+        if fee_report.is_some() && fee_schedule.is_none() {
+            return Err("--fee-report requires --fee-schedule".to_string());
+        }
+        Ok(Options {
+            input,
+            listen: None,
+            http,
+            validate,
+            statement,
+            audit,
+            input_format,
+            output_format: output_format.unwrap_or_default(),
+            sort,
+            strict,
+            rejections,
+            journal,
+            replay,
+            load_snapshot,
+            save_snapshot,
+            dispute_withdrawals,
+            policy,
+            client_config,
+            fee_schedule,
+            fee_report,
+            dispute_window,
+            auto_resolve,
+            disk_storage,
+            shards,
+        })
+    }
+}
+
+fn run() -> Result<(), String> {
+    let options = Options::from_args()?;
+
+    let ledger = match (&options.replay, &options.load_snapshot) {
+        (Some(path), _) => replay_journal(path)?,
+        (None, Some(path)) => load_snapshot(Path::new(path))?,
+        (None, None) => Ledger::new(),
+    };
+
+    let mut ledger = if options.journal.is_some() || options.fee_report.is_some() {
+        ledger.with_event_log()
+    } else {
+        ledger
+    };
+    ledger = configure(ledger, &options);
+    if let Some(path) = &options.fee_schedule {
+        ledger = ledger.with_fees(load_fee_schedule(Path::new(path))?);
+    }
+    if let Some(path) = &options.disk_storage {
+        let storage = DiskStorage::create(Path::new(path))
+            .map_err(|e| format!("Failed to create '{}': {}", path, e))?;
+        ledger = ledger
+            .with_storage(Box::new(storage))
+            .map_err(|e| format!("Failed to write '{}': {}", path, e))?;
+    }
+    if let Some(path) = &options.client_config {
+        load_client_config(Path::new(path))?.apply(&mut ledger);
+    }
+
+    if let Some(addr) = &options.listen {
+        return serve(ledger, addr, options.http);
+    }
+    if let (true, Some(file_path)) = (options.validate, &options.input) {
+        return validate_file(file_path, &options, ledger);
+    }
+
+    let opening = options
+        .statement
+        .map(|client_id| Statement::balances(&ledger, client_id));
+    if options.statement.is_some() {
+        ledger = ledger.with_history();
+    }
+
+    if let Some(file_path) = &options.input {
+        match options.shards {
+            Some(shard_count) => ledger = process_file_sharded(file_path, &options, shard_count)?,
+            None => process_file(file_path, &options, &mut ledger)?,
         }
     }
 
+    if let Some(path) = &options.save_snapshot {
+        save_snapshot(Path::new(path), &ledger)?;
+    }
+
+    if options.audit {
+        return audit(&ledger);
+    }
+    if let (Some(client_id), Some(opening)) = (options.statement, opening) {
+        print!("{}", Statement::new(&ledger, client_id, opening));
+        return Ok(());
+    }
+
     let stdout = io::stdout();
     let mut handle = stdout.lock();
 
-    let records = ledger
+    let mut records: Vec<_> = ledger
         .accounts()
         .iter()
-        .map(|(client_id, account)| OutputRecord::from_account(*client_id, account));
+        .flat_map(|(client_id, account)| OutputRecord::from_account_balances(*client_id, account))
+        .collect();
+    if let Some(order) = &options.sort {
+        order.sort(&mut records);
+    }
 
-    write_csv(&mut handle, records).map_err(|e| format!("Failed to write output: {}", e))?;
+    options
+        .output_format
+        .write(&mut handle, records.into_iter())
+        .map_err(|e| format!("Failed to write output: {}", e))?;
 
     Ok(())
 }
+
+/// Applies the ledger settings given on the command line.
+fn configure(mut ledger: Ledger, options: &Options) -> Ledger {
+    if let Some(policy) = options.policy {
+        ledger = ledger.with_policy(policy);
+    }
+    if options.dispute_withdrawals {
+        ledger = ledger.with_withdrawal_disputes();
+    }
+    if let Some(seconds) = options.dispute_window {
+        ledger = ledger.with_dispute_window(seconds);
+    }
+    if let Some(seconds) = options.auto_resolve {
+        ledger = ledger.with_auto_resolve(seconds);
+    }
+    ledger
+}
+
+/// Parses seconds, optionally with an `s`, `m`, `h` or `d` unit.
+fn parse_duration(value: &str) -> Result<u64, String> {
+    let invalid = || format!("Invalid duration: '{}'", value);
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => value.split_at(index),
+        None => (value, "s"),
+    };
+    let scale = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(scale))
+        .ok_or_else(invalid)
+}
+
+fn serve(ledger: Ledger, addr: &str, http: bool) -> Result<(), String> {
+    let server = if http {
+        simple_rust_ledger::http::bind(addr, ledger)
+    } else {
+        Server::bind(addr, ledger)
+    }
+    .map_err(|e| format!("Failed to listen on '{}': {}", addr, e))?;
+    if let Ok(local) = server.local_addr() {
+        eprintln!("Listening on {}", local);
+    }
+    server.run().map_err(|e| format!("Server stopped: {}", e))
+}
+
+fn validate_file(file_path: &str, options: &Options, ledger: Ledger) -> Result<(), String> {
+    let summary = validate(open_input(file_path, options)?, ledger)
+        .map_err(|e| format!("Failed to validate '{}': {}", file_path, e))?;
+    print!("{}", summary);
+    if summary.is_clean() {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} line(s) would be dropped",
+            summary.dropped_lines()
+        ))
+    }
+}
+
+fn audit(ledger: &Ledger) -> Result<(), String> {
+    let violations = ledger
+        .audit()
+        .map_err(|e| format!("Failed to audit the ledger: {}", e))?;
+    println!("Accounts: {}", ledger.accounts().len());
+    println!("Violations: {}", violations.len());
+    for violation in &violations {
+        println!("  {}: {}", violation.category(), violation);
+    }
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("{} invariant(s) violated", violations.len()))
+    }
+}
+
+fn replay_journal(path: &str) -> Result<Ledger, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open '{}': {}", path, e))?;
+    let events = read_journal(BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ledger::replay(events).map_err(|e| format!("Failed to replay '{}': {}", path, e))
+}
+
+fn process_file(file_path: &str, options: &Options, ledger: &mut Ledger) -> Result<(), String> {
+    let mut parser = open_input(file_path, options)?;
+
+    let mut report = options.rejections.as_deref().map(open_report).transpose()?;
+    // A strict run writes the journal and fee report to temp files and only moves them
+    // into place once the whole input is known to be valid, so a failed run leaves
+    // existing ones untouched
+    let journal_path = match (&options.journal, options.strict) {
+        (Some(path), true) => Some(copy_to_tmp(path)?),
+        (path, _) => path.as_deref().map(PathBuf::from),
+    };
+    let fees_path = options
+        .fee_report
+        .as_deref()
+        .map(|path| match options.strict {
+            true => tmp_path(Path::new(path)),
+            false => PathBuf::from(path),
+        });
+    let mut journal = journal_path.as_deref().map(open_journal).transpose()?;
+    let mut fees = fees_path.as_deref().map(open_fee_report).transpose()?;
+
+    let result = process_records(
+        &mut *parser,
+        options,
+        ledger,
+        journal.as_mut(),
+        fees.as_mut(),
+        report.as_mut(),
+    );
+    drop((journal, fees));
+
+    if options.strict {
+        let outputs = [
+            (journal_path, options.journal.as_deref()),
+            (fees_path, options.fee_report.as_deref()),
+        ];
+        for (tmp_path, path) in outputs {
+            let (Some(tmp_path), Some(path)) = (tmp_path, path) else {
+                continue;
+            };
+            if result.is_err() {
+                let _ = fs::remove_file(&tmp_path);
+                continue;
+            }
+            fs::rename(&tmp_path, path).map_err(|e| {
+                format!(
+                    "Failed to move '{}' to '{}': {}",
+                    tmp_path.display(),
+                    path,
+                    e
+                )
+            })?;
+        }
+    }
+    result
+}
+
+/// Processes every record, appending the applied events as each line is done.
+fn process_records(
+    parser: &mut dyn RecordSource,
+    options: &Options,
+    ledger: &mut Ledger,
+    mut journal: Option<&mut JournalWriter<BufWriter<File>>>,
+    mut fees: Option<&mut FeeWriter<BufWriter<File>>>,
+    mut report: Option<&mut RejectionWriter<BufWriter<File>>>,
+) -> Result<(), String> {
+    while let Some(result) = parser.next() {
+        let rejection = match result {
+            Ok(record) => match ledger.process_transaction(&record.transaction()) {
+                Ok(_) => None,
+                // The ledger refuses every later line, so the run cannot go on
+                Err(reason @ RejectionReason::Storage(_)) => {
+                    return Err(format!("Line {}: {}", record.line, reason))
+                }
+                Err(reason) => {
+                    let message = rejection_message(record.line, &record.transaction(), &reason);
+                    if options.strict {
+                        return Err(message);
+                    }
+                    eprintln!("Warning: {}", message);
+                    Some((record.line, reason.category(), reason.to_string()))
+                }
+            },
+            Err(e) if options.strict => return Err(e.to_string()),
+            Err(e) => {
+                eprintln!("Warning: {}", e);
+                Some((e.line, e.kind.category(), e.message))
+            }
+        };
+
+        append_events(ledger, journal.as_deref_mut(), fees.as_deref_mut())?;
+
+        if let (Some(report), Some((line, category, message))) = (report.as_deref_mut(), rejection)
+        {
+            report
+                .write(&RejectionRecord {
+                    line,
+                    row: parser.current_row(),
+                    category,
+                    message,
+                })
+                .map_err(|e| format!("Failed to write rejections: {}", e))?;
+        }
+    }
+
+    if let Some(journal) = journal {
+        journal
+            .flush()
+            .map_err(|e| format!("Failed to write journal: {}", e))?;
+    }
+    if let Some(fees) = fees {
+        fees.flush()
+            .map_err(|e| format!("Failed to write fee report: {}", e))?;
+    }
+    if let Some(report) = report {
+        report
+            .flush()
+            .map_err(|e| format!("Failed to write rejections: {}", e))?;
+    }
+
+    Ok(())
+}
+
+/// Copies the journal to its temp file, which is created empty when there is no journal yet.
+fn copy_to_tmp(path: &str) -> Result<PathBuf, String> {
+    let tmp_path = tmp_path(Path::new(path));
+    let copied = match fs::copy(path, &tmp_path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => File::create(&tmp_path).map(|_| 0),
+        copied => copied,
+    };
+    copied.map_err(|e| {
+        format!(
+            "Failed to copy '{}' to '{}': {}",
+            path,
+            tmp_path.display(),
+            e
+        )
+    })?;
+    Ok(tmp_path)
+}
+
+/// Writes the events applied since the last call to the journal and the fee report.
+fn append_events(
+    ledger: &mut Ledger,
+    mut journal: Option<&mut JournalWriter<BufWriter<File>>>,
+    mut fees: Option<&mut FeeWriter<BufWriter<File>>>,
+) -> Result<(), String> {
+    if journal.is_none() && fees.is_none() {
+        return Ok(());
+    }
+    for event in ledger.drain_events() {
+        if let Some(journal) = journal.as_deref_mut() {
+            journal
+                .append(&event)
+                .map_err(|e| format!("Failed to write journal: {}", e))?;
+        }
+        if let (Some(fees), Some(record)) = (fees.as_deref_mut(), FeeRecord::from_event(&event)) {
+            fees.write(&record)
+                .map_err(|e| format!("Failed to write fee report: {}", e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Same as [`process_file`], with clients spread over `shard_count` worker threads.
+///
+/// Ledger rejections are only known at the end, so they are reported after parse errors,
+/// while the rejections report is still written in line order.
+fn process_file_sharded(
+    file_path: &str,
+    options: &Options,
+    shard_count: usize,
+) -> Result<Ledger, String> {
+    let mut parser = open_input(file_path, options)?;
+    let mut report = options.rejections.as_deref().map(open_report).transpose()?;
+
+    let mut sharded = ShardedLedger::new(shard_count, || configure(Ledger::new(), options));
+
+    // The original row is only kept around when a report will need it
+    let mut dropped = Vec::new();
+    while let Some(result) = parser.next() {
+        let row = report.as_ref().map(|_| parser.current_row());
+        match result {
+            Ok(record) => sharded
+                .process(record.transaction(), (record.line, row))
+                .map_err(|e| format!("Line {}: {}", record.line, e))?,
+            Err(e) => {
+                eprintln!("Warning: {}", e);
+                if let Some(row) = row {
+                    dropped.push(RejectionRecord {
+                        line: e.line,
+                        row,
+                        category: e.kind.category(),
+                        message: e.message,
+                    });
+                }
+            }
+        }
+    }
+
+    let (ledger, mut rejected) = sharded
+        .finish()
+        .map_err(|e| format!("Failed to merge the shards: {}", e))?;
+    rejected.sort_by_key(|r| r.tag.0);
+    for rejection in rejected {
+        let (line, row) = rejection.tag;
+        if let RejectionReason::Storage(_) = rejection.reason {
+            return Err(format!("Line {}: {}", line, rejection.reason));
+        }
+        let message = rejection_message(line, &rejection.transaction, &rejection.reason);
+        eprintln!("Warning: {}", message);
+        if let Some(row) = row {
+            dropped.push(RejectionRecord {
+                line,
+                row,
+                category: rejection.reason.category(),
+                message: rejection.reason.to_string(),
+            });
+        }
+    }
+
+    if let Some(report) = report.as_mut() {
+        dropped.sort_by_key(|record| record.line);
+        for record in &dropped {
+            report
+                .write(record)
+                .map_err(|e| format!("Failed to write rejections: {}", e))?;
+        }
+        report
+            .flush()
+            .map_err(|e| format!("Failed to write rejections: {}", e))?;
+    }
+
+    Ok(ledger)
+}
+
+fn open_input(file_path: &str, options: &Options) -> Result<Box<dyn RecordSource>, String> {
+    let file =
+        File::open(file_path).map_err(|e| format!("Failed to open '{}': {}", file_path, e))?;
+    let format = options
+        .input_format
+        .unwrap_or_else(|| InputFormat::from_path(Path::new(file_path)));
+    if options.strict {
+        format.strict_parser(BufReader::new(file))
+    } else {
+        format.parser(BufReader::new(file))
+    }
+}
+
+fn open_journal(path: &Path) -> Result<JournalWriter<BufWriter<File>>, String> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("Failed to open '{}': {}", path.display(), e))?;
+    Ok(JournalWriter::new(BufWriter::new(file)))
+}
+
+fn open_report(path: &str) -> Result<RejectionWriter<BufWriter<File>>, String> {
+    let file = File::create(path).map_err(|e| format!("Failed to create '{}': {}", path, e))?;
+    RejectionWriter::new(BufWriter::new(file))
+        .map_err(|e| format!("Failed to write rejections: {}", e))
+}
+
+fn open_fee_report(path: &Path) -> Result<FeeWriter<BufWriter<File>>, String> {
+    let file =
+        File::create(path).map_err(|e| format!("Failed to create '{}': {}", path.display(), e))?;
+    FeeWriter::new(BufWriter::new(file)).map_err(|e| format!("Failed to write fee report: {}", e))
+}
+
+fn rejection_message(line: usize, transaction: &Transaction, reason: &RejectionReason) -> String {
+    format!(
+        "Line {}: rejected {} (client {}, tx {}): [{}] {}",
+        line,
+        transaction.tx_type,
+        transaction.client_id,
+        transaction.tx_id,
+        reason.category(),
+        reason
+    )
+}
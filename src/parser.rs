@@ -1,19 +1,85 @@
 use csv::ReaderBuilder;
-use std::io::Read;
+use serde_json::value::RawValue;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Lines, Read};
+use std::path::Path;
+use std::str::FromStr;
 
-use crate::domain::types::{Amount, ClientId, TransactionId, TransactionType};
+use crate::domain::types::{
+    Amount, ClientId, Currency, LockReason, Timestamp, Transaction, TransactionId, TransactionType,
+};
 
 #[derive(Debug)]
 pub struct InputRecord {
+    pub line: usize,
     pub tx_type: TransactionType,
     pub client_id: ClientId,
     pub tx_id: TransactionId,
     pub amount: Option<Amount>,
+    pub destination: Option<ClientId>,
+    pub currency: Option<Currency>,
+    pub timestamp: Option<Timestamp>,
+    pub reason: Option<LockReason>,
+}
+
+impl InputRecord {
+    pub fn transaction(&self) -> Transaction {
+        Transaction {
+            tx_type: self.tx_type,
+            client_id: self.client_id,
+            tx_id: self.tx_id,
+            amount: self.amount,
+            destination: self.destination,
+            currency: self.currency,
+            timestamp: self.timestamp,
+            reason: self.reason,
+        }
+    }
+}
+
+/// Category of a malformed input line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    Csv,
+    Json,
+    UnknownType,
+    InvalidClient,
+    InvalidTransaction,
+    InvalidAmount,
+    NegativeAmount,
+    MissingAmount,
+    InvalidDestination,
+    MissingDestination,
+    InvalidCurrency,
+    InvalidTimestamp,
+    InvalidReason,
+}
+
+impl ParseErrorKind {
+    /// Stable machine-readable category, suitable for reports and metrics.
+    pub fn category(&self) -> &'static str {
+        match self {
+            ParseErrorKind::Csv => "csv_error",
+            ParseErrorKind::Json => "json_error",
+            ParseErrorKind::UnknownType => "unknown_type",
+            ParseErrorKind::InvalidClient => "invalid_client",
+            ParseErrorKind::InvalidTransaction => "invalid_tx",
+            ParseErrorKind::InvalidAmount => "invalid_amount",
+            ParseErrorKind::NegativeAmount => "negative_amount",
+            ParseErrorKind::MissingAmount => "missing_amount",
+            ParseErrorKind::InvalidDestination => "invalid_destination",
+            ParseErrorKind::MissingDestination => "missing_destination",
+            ParseErrorKind::InvalidCurrency => "invalid_currency",
+            ParseErrorKind::InvalidTimestamp => "invalid_timestamp",
+            ParseErrorKind::InvalidReason => "invalid_reason",
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct ParseError {
     pub line: usize,
+    pub kind: ParseErrorKind,
     pub message: String,
 }
 
@@ -23,57 +89,93 @@ impl std::fmt::Display for ParseError {
     }
 }
 
-#[derive(Debug)]
-struct ColumnIndices {
-    type_idx: usize,
-    client_idx: usize,
-    tx_idx: usize,
-    amount_idx: usize,
+/// A stream of parsed input rows, whatever the input format.
+pub trait RecordSource: Iterator<Item = Result<InputRecord, ParseError>> {
+    /// The most recently read row as text, for rejection reporting.
+    fn current_row(&self) -> String;
 }
 
-pub struct CsvParser<R: Read> {
-    reader: csv::Reader<R>,
-    line_number: usize,
-    columns: ColumnIndices,
+/// Format of an input file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    Csv,
+    /// A single JSON array of objects.
+    Json,
+    /// One JSON object per line.
+    Ndjson,
 }
 
-impl<R: Read> std::fmt::Debug for CsvParser<R> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("CsvParser")
-            .field("line_number", &self.line_number)
-            .field("columns", &self.columns)
-            .finish_non_exhaustive()
+impl InputFormat {
+    /// Guesses the format from the file extension, defaulting to CSV.
+    pub fn from_path(path: &Path) -> Self {
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        match extension.to_ascii_lowercase().as_str() {
+            "json" => InputFormat::Json,
+            "ndjson" | "jsonl" => InputFormat::Ndjson,
+            _ => InputFormat::Csv,
+        }
     }
-}
 
-impl<R: Read> CsvParser<R> {
-    pub fn new(reader: R) -> Result<Self, String> {
-        let mut csv_reader = ReaderBuilder::new()
-            .flexible(true)
-            .trim(csv::Trim::All)
-            .has_headers(true)
-            .from_reader(reader);
+    /// Opens the matching parser over `reader`.
+    pub fn parser<R: Read + 'static>(self, reader: R) -> Result<Box<dyn RecordSource>, String> {
+        Ok(match self {
+            InputFormat::Csv => Box::new(CsvParser::new(reader)?),
+            InputFormat::Json => Box::new(JsonParser::array(reader)?),
+            InputFormat::Ndjson => Box::new(JsonParser::ndjson(reader)),
+        })
+    }
 
-        let headers = csv_reader
-            .headers()
-            .map_err(|e| format!("Failed to read headers: {}", e))?
-            .clone();
+    /// Same as [`InputFormat::parser`], ending the stream at the first malformed row.
+    pub fn strict_parser<R: Read + 'static>(
+        self,
+        reader: R,
+    ) -> Result<Box<dyn RecordSource>, String> {
+        Ok(match self {
+            InputFormat::Csv => Box::new(CsvParser::new(reader)?.with_strict()),
+            InputFormat::Json => Box::new(JsonParser::array(reader)?.with_strict()),
+            InputFormat::Ndjson => Box::new(JsonParser::ndjson(reader).with_strict()),
+        })
+    }
+}
 
-        let columns = Self::extract_column_indices(&headers)?;
+impl FromStr for InputFormat {
+    type Err = String;
 
-        Ok(CsvParser {
-            reader: csv_reader,
-            line_number: 1,
-            columns,
-        })
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(InputFormat::Csv),
+            "json" => Ok(InputFormat::Json),
+            "ndjson" | "jsonl" => Ok(InputFormat::Ndjson),
+            _ => Err(format!("Unknown input format: '{}'", s)),
+        }
     }
+}
 
-    fn extract_column_indices(headers: &csv::StringRecord) -> Result<ColumnIndices, String> {
-        let find_col = |name: &str| -> Result<usize, String> {
+#[derive(Debug)]
+struct ColumnIndices {
+    type_idx: usize,
+    client_idx: usize,
+    tx_idx: usize,
+    amount_idx: usize,
+    /// Optional column, only read for transfers.
+    destination_idx: Option<usize>,
+    /// Optional column; rows without a currency use the default one.
+    currency_idx: Option<usize>,
+    /// Optional column of Unix timestamps in seconds.
+    timestamp_idx: Option<usize>,
+    /// Optional free text, only read for locks.
+    reason_idx: Option<usize>,
+}
+
+impl ColumnIndices {
+    fn from_headers(headers: &csv::StringRecord) -> Result<Self, String> {
+        let position = |name: &str| {
             headers
                 .iter()
                 .position(|h| h.trim().eq_ignore_ascii_case(name))
-                .ok_or_else(|| format!("Missing required column: '{}'", name))
+        };
+        let find_col = |name: &str| -> Result<usize, String> {
+            position(name).ok_or_else(|| format!("Missing required column: '{}'", name))
         };
 
         Ok(ColumnIndices {
@@ -81,87 +183,304 @@ impl<R: Read> CsvParser<R> {
             client_idx: find_col("client")?,
             tx_idx: find_col("tx")?,
             amount_idx: find_col("amount")?,
+            destination_idx: position("destination"),
+            currency_idx: position("currency"),
+            timestamp_idx: position("timestamp"),
+            reason_idx: position("reason"),
         })
     }
 
-    pub fn next_record(&mut self) -> Option<Result<InputRecord, ParseError>> {
-        let mut record = csv::StringRecord::new();
-
-        self.line_number += 1;
-        let current_line = self.line_number;
-
-        match self.reader.read_record(&mut record) {
-            Ok(true) => match self.parse_record(&record, current_line) {
-                Ok(input) => Some(Ok(input)),
-                Err(e) => Some(Err(e)),
-            },
-            Ok(false) => None,
-            Err(e) => Some(Err(ParseError {
-                line: current_line,
-                message: format!("CSV error: {}", e),
-            })),
+    fn parse(&self, record: &csv::StringRecord, line: usize) -> Result<InputRecord, ParseError> {
+        let field = |idx: Option<usize>| idx.and_then(|idx| record.get(idx)).unwrap_or("");
+        RawRecord {
+            tx_type: field(Some(self.type_idx)),
+            client: field(Some(self.client_idx)),
+            tx: field(Some(self.tx_idx)),
+            amount: field(Some(self.amount_idx)),
+            destination: field(self.destination_idx),
+            currency: field(self.currency_idx),
+            timestamp: field(self.timestamp_idx),
+            reason: field(self.reason_idx),
         }
+        .parse(line)
     }
+}
 
-    fn parse_record(
-        &self,
-        record: &csv::StringRecord,
-        line: usize,
-    ) -> Result<InputRecord, ParseError> {
-        let tx_type_str = record.get(self.columns.type_idx).unwrap_or("").trim();
+/// Values of one input row as text, empty when absent.
+struct RawRecord<'a> {
+    tx_type: &'a str,
+    client: &'a str,
+    tx: &'a str,
+    amount: &'a str,
+    destination: &'a str,
+    currency: &'a str,
+    timestamp: &'a str,
+    reason: &'a str,
+}
+
+impl RawRecord<'_> {
+    /// Validation shared by every input format.
+    fn parse(&self, line: usize) -> Result<InputRecord, ParseError> {
+        let tx_type_str = self.tx_type.trim();
         let tx_type: TransactionType = tx_type_str.parse().map_err(|_| ParseError {
             line,
+            kind: ParseErrorKind::UnknownType,
             message: format!("Unknown transaction type: '{}'", tx_type_str),
         })?;
 
-        let client_str = record.get(self.columns.client_idx).unwrap_or("").trim();
+        let client_str = self.client.trim();
         let client_id: u16 = client_str.parse().map_err(|_| ParseError {
             line,
+            kind: ParseErrorKind::InvalidClient,
             message: format!("Invalid client ID: '{}'", client_str),
         })?;
 
-        let tx_str = record.get(self.columns.tx_idx).unwrap_or("").trim();
+        let tx_str = self.tx.trim();
         let tx_id: u32 = tx_str.parse().map_err(|_| ParseError {
             line,
+            kind: ParseErrorKind::InvalidTransaction,
             message: format!("Invalid transaction ID: '{}'", tx_str),
         })?;
 
-        let amount_str = record.get(self.columns.amount_idx).unwrap_or("").trim();
+        let amount_str = self.amount.trim();
         let amount = if amount_str.is_empty() {
             None
         } else {
             let parsed = Amount::from_str_rounded(amount_str).map_err(|_| ParseError {
                 line,
+                kind: ParseErrorKind::InvalidAmount,
                 message: format!("Invalid amount: '{}'", amount_str),
             })?;
             if parsed.is_negative() {
                 return Err(ParseError {
                     line,
+                    kind: ParseErrorKind::NegativeAmount,
                     message: format!("Negative amount not allowed: '{}'", amount_str),
                 });
             }
             Some(parsed)
         };
 
-        match tx_type {
-            TransactionType::Deposit | TransactionType::Withdrawal => {
-                if amount.is_none() {
-                    return Err(ParseError {
-                        line,
-                        message: "Deposit/withdrawal requires amount".to_string(),
-                    });
-                }
-            }
-            _ => {}
+        if matches!(
+            tx_type,
+            TransactionType::Deposit
+                | TransactionType::Withdrawal
+                | TransactionType::Transfer
+                | TransactionType::Limit
+        ) && amount.is_none()
+        {
+            return Err(ParseError {
+                line,
+                kind: ParseErrorKind::MissingAmount,
+                message: "Deposit/withdrawal/transfer/limit requires amount".to_string(),
+            });
         }
 
+        let destination = if tx_type == TransactionType::Transfer {
+            let destination_str = self.destination.trim();
+            if destination_str.is_empty() {
+                return Err(ParseError {
+                    line,
+                    kind: ParseErrorKind::MissingDestination,
+                    message: "Transfer requires destination".to_string(),
+                });
+            }
+            let destination: u16 = destination_str.parse().map_err(|_| ParseError {
+                line,
+                kind: ParseErrorKind::InvalidDestination,
+                message: format!("Invalid destination client ID: '{}'", destination_str),
+            })?;
+            Some(ClientId(destination))
+        } else {
+            None
+        };
+
+        let currency_str = self.currency.trim();
+        let currency = if currency_str.is_empty() {
+            None
+        } else {
+            Some(currency_str.parse().map_err(|_| ParseError {
+                line,
+                kind: ParseErrorKind::InvalidCurrency,
+                message: format!("Invalid currency: '{}'", currency_str),
+            })?)
+        };
+
+        let timestamp_str = self.timestamp.trim();
+        let timestamp = if timestamp_str.is_empty() {
+            None
+        } else {
+            Some(Timestamp(timestamp_str.parse().map_err(|_| {
+                ParseError {
+                    line,
+                    kind: ParseErrorKind::InvalidTimestamp,
+                    message: format!("Invalid timestamp: '{}'", timestamp_str),
+                }
+            })?))
+        };
+
+        let reason_str = self.reason.trim();
+        let reason = if tx_type != TransactionType::Lock || reason_str.is_empty() {
+            None
+        } else {
+            Some(reason_str.parse().map_err(|_| ParseError {
+                line,
+                kind: ParseErrorKind::InvalidReason,
+                message: format!(
+                    "Invalid lock reason: '{}' (at most {} bytes, no control characters)",
+                    reason_str,
+                    LockReason::MAX_LEN
+                ),
+            })?)
+        };
+
         Ok(InputRecord {
+            line,
             tx_type,
             client_id: ClientId(client_id),
             tx_id: TransactionId(tx_id),
             amount,
+            destination,
+            currency,
+            timestamp,
+            reason,
+        })
+    }
+}
+
+pub struct CsvParser<R: Read> {
+    reader: csv::Reader<R>,
+    line_number: usize,
+    columns: ColumnIndices,
+    /// Last row read, kept for rejection reporting.
+    current: csv::StringRecord,
+    /// Whether the stream ends at the first malformed row.
+    strict: bool,
+    stopped: bool,
+}
+
+impl<R: Read> std::fmt::Debug for CsvParser<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CsvParser")
+            .field("line_number", &self.line_number)
+            .field("columns", &self.columns)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<R: Read> CsvParser<R> {
+    pub fn new(reader: R) -> Result<Self, String> {
+        let mut csv_reader = ReaderBuilder::new()
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .has_headers(true)
+            .from_reader(reader);
+
+        let headers = csv_reader
+            .headers()
+            .map_err(|e| format!("Failed to read headers: {}", e))?
+            .clone();
+
+        let columns = ColumnIndices::from_headers(&headers)?;
+
+        Ok(CsvParser {
+            reader: csv_reader,
+            line_number: 1,
+            columns,
+            current: csv::StringRecord::new(),
+            strict: false,
+            stopped: false,
+        })
+    }
+
+    /// Ends the stream after the first malformed row instead of skipping it.
+    pub fn with_strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    pub fn next_record(&mut self) -> Option<Result<InputRecord, ParseError>> {
+        if self.stopped {
+            return None;
+        }
+        let result = self.read_record();
+        self.stopped = self.strict && matches!(result, Some(Err(_)));
+        result
+    }
+
+    fn read_record(&mut self) -> Option<Result<InputRecord, ParseError>> {
+        self.line_number += 1;
+        let current_line = self.line_number;
+
+        match self.reader.read_record(&mut self.current) {
+            Ok(true) => match self.columns.parse(&self.current, current_line) {
+                Ok(input) => Some(Ok(input)),
+                Err(e) => Some(Err(e)),
+            },
+            Ok(false) => None,
+            Err(e) => {
+                self.current.clear();
+                Some(Err(ParseError {
+                    line: current_line,
+                    kind: ParseErrorKind::Csv,
+                    message: format!("CSV error: {}", e),
+                }))
+            }
+        }
+    }
+
+    /// Re-encodes the most recently read row as a CSV line (without terminator).
+    ///
+    /// Values are trimmed as read, so this may differ from the input in whitespace only.
+    pub fn current_row(&self) -> String {
+        let mut writer = csv::WriterBuilder::new()
+            .terminator(csv::Terminator::Any(b'\n'))
+            .from_writer(Vec::new());
+        if writer.write_record(&self.current).is_err() {
+            return self.current.iter().collect::<Vec<_>>().join(",");
+        }
+        let mut bytes = writer.into_inner().unwrap_or_default();
+        if bytes.last() == Some(&b'\n') {
+            bytes.pop();
+        }
+        String::from_utf8(bytes).unwrap_or_default()
+    }
+}
+
+/// Parses CSV rows one at a time against a header line, for input that arrives line by line.
+#[derive(Debug)]
+pub struct LineParser {
+    columns: ColumnIndices,
+}
+
+impl LineParser {
+    pub fn new(header: &str) -> Result<Self, String> {
+        let headers = read_row(header).map_err(|e| format!("Failed to read headers: {}", e))?;
+        Ok(LineParser {
+            columns: ColumnIndices::from_headers(&headers)?,
         })
     }
+
+    /// Parses a single row; `line` is only used to number errors and records.
+    pub fn parse(&self, row: &str, line: usize) -> Result<InputRecord, ParseError> {
+        let record = read_row(row).map_err(|e| ParseError {
+            line,
+            kind: ParseErrorKind::Csv,
+            message: format!("CSV error: {}", e),
+        })?;
+        self.columns.parse(&record, line)
+    }
+}
+
+fn read_row(row: &str) -> csv::Result<csv::StringRecord> {
+    let mut record = csv::StringRecord::new();
+    ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .has_headers(false)
+        .from_reader(row.as_bytes())
+        .read_record(&mut record)?;
+    Ok(record)
 }
 
 impl<R: Read> Iterator for CsvParser<R> {
@@ -172,6 +491,171 @@ impl<R: Read> Iterator for CsvParser<R> {
     }
 }
 
+impl<R: Read> RecordSource for CsvParser<R> {
+    fn current_row(&self) -> String {
+        CsvParser::current_row(self)
+    }
+}
+
+/// Reads objects with the CSV column names as keys (matched case-insensitively), such as
+/// `{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}`.
+///
+/// Values may be strings or numbers and are validated like CSV values; a missing key or
+/// `null` counts as an empty column. Numbers are read as written rather than as floating
+/// point, so `1.5` and `"1.5"` are the same amount and `1e2` is as invalid as in CSV.
+pub struct JsonParser<R: Read> {
+    rows: JsonRows<R>,
+    line_number: usize,
+    /// Last row read, kept for rejection reporting.
+    current: String,
+    /// Whether the stream ends at the first malformed row.
+    strict: bool,
+    stopped: bool,
+}
+
+enum JsonRows<R: Read> {
+    Lines(Lines<BufReader<R>>),
+    Array(std::vec::IntoIter<Box<RawValue>>),
+}
+
+impl<R: Read> std::fmt::Debug for JsonParser<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JsonParser")
+            .field("line_number", &self.line_number)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<R: Read> JsonParser<R> {
+    /// Parses newline-delimited JSON, skipping blank lines; records carry their line number.
+    pub fn ndjson(reader: R) -> Self {
+        JsonParser {
+            rows: JsonRows::Lines(BufReader::new(reader).lines()),
+            line_number: 0,
+            current: String::new(),
+            strict: false,
+            stopped: false,
+        }
+    }
+
+    /// Parses a JSON array, read into memory up front; records carry their 1-based position.
+    pub fn array(reader: R) -> Result<Self, String> {
+        let rows: Vec<Box<RawValue>> = serde_json::from_reader(reader)
+            .map_err(|e| format!("Failed to read JSON array: {}", e))?;
+        Ok(JsonParser {
+            rows: JsonRows::Array(rows.into_iter()),
+            line_number: 0,
+            current: String::new(),
+            strict: false,
+            stopped: false,
+        })
+    }
+
+    /// Ends the stream after the first malformed row instead of skipping it.
+    pub fn with_strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    pub fn next_record(&mut self) -> Option<Result<InputRecord, ParseError>> {
+        if self.stopped {
+            return None;
+        }
+        let result = self.read_record();
+        self.stopped = self.strict && matches!(result, Some(Err(_)));
+        result
+    }
+
+    fn read_record(&mut self) -> Option<Result<InputRecord, ParseError>> {
+        let result = match &mut self.rows {
+            JsonRows::Lines(lines) => loop {
+                self.line_number += 1;
+                match lines.next()? {
+                    Ok(line) if line.trim().is_empty() => continue,
+                    Ok(line) => {
+                        self.current = line;
+                        break parse_json(&self.current, self.line_number);
+                    }
+                    Err(e) => {
+                        self.current.clear();
+                        break Err(ParseError {
+                            line: self.line_number,
+                            kind: ParseErrorKind::Json,
+                            message: format!("JSON error: {}", e),
+                        });
+                    }
+                }
+            },
+            JsonRows::Array(values) => {
+                self.line_number += 1;
+                self.current = values.next()?.get().to_string();
+                parse_json(&self.current, self.line_number)
+            }
+        };
+        Some(result)
+    }
+}
+
+/// A JSON object with its values kept as written, so numbers never pass through `f64`.
+type JsonObject = BTreeMap<String, Box<RawValue>>;
+
+/// Validates one JSON object like a CSV row, see [`JsonParser`].
+pub(crate) fn parse_json(json: &str, line: usize) -> Result<InputRecord, ParseError> {
+    let error = |message| ParseError {
+        line,
+        kind: ParseErrorKind::Json,
+        message,
+    };
+    let value: &RawValue =
+        serde_json::from_str(json).map_err(|e| error(format!("JSON error: {}", e)))?;
+    if !value.get().starts_with('{') {
+        return Err(error(format!("Expected a JSON object, found '{}'", value)));
+    }
+    let object: JsonObject =
+        serde_json::from_str(value.get()).map_err(|e| error(format!("JSON error: {}", e)))?;
+    parse_object(&object, line)
+}
+
+fn parse_object(object: &JsonObject, line: usize) -> Result<InputRecord, ParseError> {
+    let field = |name: &str| -> String {
+        let value = object
+            .iter()
+            .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.get());
+        match value {
+            None | Some("null") => String::new(),
+            // Unescapes the string, which is valid JSON as the object was read
+            Some(text) if text.starts_with('"') => serde_json::from_str(text).unwrap_or_default(),
+            Some(text) => text.to_string(),
+        }
+    };
+    RawRecord {
+        tx_type: &field("type"),
+        client: &field("client"),
+        tx: &field("tx"),
+        amount: &field("amount"),
+        destination: &field("destination"),
+        currency: &field("currency"),
+        timestamp: &field("timestamp"),
+        reason: &field("reason"),
+    }
+    .parse(line)
+}
+
+impl<R: Read> Iterator for JsonParser<R> {
+    type Item = Result<InputRecord, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record()
+    }
+}
+
+impl<R: Read> RecordSource for JsonParser<R> {
+    fn current_row(&self) -> String {
+        self.current.clone()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -202,6 +686,58 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_error_kind_categorizes_failure() {
+        let input = "type,client,tx,amount\ndeposit,abc,1,100\ndeposit,1,2,-5\nwithdrawal,1,3,\n";
+        let results: Vec<_> = parse_csv(input);
+        let kinds: Vec<_> = results
+            .iter()
+            .map(|r| r.as_ref().unwrap_err().kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ParseErrorKind::InvalidClient,
+                ParseErrorKind::NegativeAmount,
+                ParseErrorKind::MissingAmount
+            ]
+        );
+    }
+
+    #[test]
+    fn test_current_row_reencodes_last_read_line() {
+        let input = "type,client,tx,amount\ndeposit, 1 ,1,\"1,000\"\n";
+        let mut parser = CsvParser::new(Cursor::new(input)).expect("failed to create parser");
+        assert!(parser.next_record().is_some());
+        assert_eq!(parser.current_row(), "deposit,1,1,\"1,000\"");
+    }
+
+    #[test]
+    fn test_strict_stops_at_first_error() {
+        let input = "type,client,tx,amount\ndeposit,1,1,100\nbogus,1,2,5\ndeposit,1,3,100\n";
+        let parser = CsvParser::new(Cursor::new(input))
+            .expect("failed to create parser")
+            .with_strict();
+        let results: Vec<_> = parser.collect();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1].as_ref().unwrap_err().line, 3);
+
+        let input = "{\"type\": \"bogus\"}\n{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": 1}\n";
+        let results: Vec<_> = JsonParser::ndjson(Cursor::new(input))
+            .with_strict()
+            .collect();
+        assert_eq!(results.len(), 1);
+    }
+
+    #[test]
+    fn test_record_carries_line_number() {
+        let input = "type,client,tx,amount\ndeposit,1,1,100\nbogus,1,2,5\ndeposit,1,3,100\n";
+        let results: Vec<_> = parse_csv(input);
+        assert_eq!(results[0].as_ref().expect("record should be ok").line, 2);
+        assert_eq!(results[1].as_ref().unwrap_err().line, 3);
+        assert_eq!(results[2].as_ref().expect("record should be ok").line, 4);
+    }
+
     #[test]
     fn test_parse_whitespace_in_headers_and_values() {
         let input = " type , client , tx , amount \n deposit , 1 , 1 , 100.0 \n";
@@ -238,6 +774,248 @@ mod tests {
         assert!(record.amount.is_none());
     }
 
+    #[test]
+    fn test_parse_transfer_destination() {
+        let input = "type,client,tx,amount,destination\ntransfer,1,1,2.5,7\ndeposit,1,2,1,9\n";
+        let results: Vec<_> = parse_csv(input);
+        let transfer = results[0].as_ref().expect("record should be ok");
+        assert_eq!(transfer.destination, Some(ClientId(7)));
+        let deposit = results[1].as_ref().expect("record should be ok");
+        assert_eq!(deposit.destination, None);
+    }
+
+    #[test]
+    fn test_transfer_without_destination_rejected() {
+        let input = "type,client,tx,amount,destination\n\
+                     transfer,1,1,2.5,\n\
+                     transfer,1,2,2.5,abc\n\
+                     transfer,1,3,,2\n";
+        let results: Vec<_> = parse_csv(input);
+        let kinds: Vec<_> = results
+            .iter()
+            .map(|r| r.as_ref().expect_err("transfer should be rejected").kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ParseErrorKind::MissingDestination,
+                ParseErrorKind::InvalidDestination,
+                ParseErrorKind::MissingAmount,
+            ]
+        );
+
+        let input = "type,client,tx,amount\ntransfer,1,1,2.5\n";
+        let results: Vec<_> = parse_csv(input);
+        assert_eq!(
+            results[0]
+                .as_ref()
+                .expect_err("transfer should be rejected")
+                .kind,
+            ParseErrorKind::MissingDestination
+        );
+    }
+
+    #[test]
+    fn test_parse_optional_currency() {
+        let input = "type,client,tx,amount,currency\n\
+                     deposit,1,1,2.5,eur\n\
+                     deposit,1,2,1,\n\
+                     deposit,1,3,1,EURO\n";
+        let results: Vec<_> = parse_csv(input);
+        let record = results[0].as_ref().expect("record should be ok");
+        assert_eq!(record.currency, "EUR".parse().ok());
+        let record = results[1].as_ref().expect("record should be ok");
+        assert_eq!(record.currency, None);
+        assert_eq!(
+            results[2]
+                .as_ref()
+                .expect_err("currency should be rejected")
+                .kind,
+            ParseErrorKind::InvalidCurrency
+        );
+    }
+
+    #[test]
+    fn test_parse_optional_reason() {
+        let input = "type,client,tx,amount,reason\n\
+                     lock,1,1,, court order \n\
+                     lock,1,2,,\n\
+                     deposit,1,3,1,ignored\n\
+                     lock,1,4,,\"a\u{7}b\"\n";
+        let results: Vec<_> = parse_csv(input);
+        let record = results[0].as_ref().expect("record should be ok");
+        assert_eq!(record.reason, "court order".parse().ok());
+        assert_eq!(record.transaction().reason, record.reason);
+        let record = results[1].as_ref().expect("record should be ok");
+        assert_eq!(record.reason, None);
+        let record = results[2].as_ref().expect("record should be ok");
+        assert_eq!(record.reason, None);
+        assert_eq!(
+            results[3]
+                .as_ref()
+                .expect_err("reason should be rejected")
+                .kind,
+            ParseErrorKind::InvalidReason
+        );
+    }
+
+    #[test]
+    fn test_parse_optional_timestamp() {
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,2.5,1700000000\n\
+                     dispute,1,1,,\n\
+                     deposit,1,2,1,2024-01-01\n";
+        let results: Vec<_> = parse_csv(input);
+        let record = results[0].as_ref().expect("record should be ok");
+        assert_eq!(record.timestamp, Some(Timestamp(1_700_000_000)));
+        assert_eq!(record.transaction().timestamp, record.timestamp);
+        let record = results[1].as_ref().expect("record should be ok");
+        assert_eq!(record.timestamp, None);
+        assert_eq!(
+            results[2]
+                .as_ref()
+                .expect_err("timestamp should be rejected")
+                .kind,
+            ParseErrorKind::InvalidTimestamp
+        );
+    }
+
+    #[test]
+    fn test_line_parser_matches_csv_parser() {
+        let parser = LineParser::new("amount, type,client,tx").expect("failed to create parser");
+        let record = parser
+            .parse("\"1.5\",deposit,3,9", 7)
+            .expect("record should be ok");
+        assert_eq!(record.line, 7);
+        assert_eq!(record.tx_type, TransactionType::Deposit);
+        assert_eq!(record.client_id, ClientId(3));
+        assert_eq!(record.amount, Some(amount("1.5")));
+        assert_eq!(
+            parser
+                .parse(",deposit,3,10", 8)
+                .expect_err("deposit should be rejected")
+                .kind,
+            ParseErrorKind::MissingAmount
+        );
+        assert!(LineParser::new("type,client,tx").is_err());
+    }
+
+    fn parse_ndjson(input: &str) -> Vec<Result<InputRecord, ParseError>> {
+        JsonParser::ndjson(Cursor::new(input.to_string())).collect()
+    }
+
+    #[test]
+    fn test_parse_ndjson() {
+        let input = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5", "currency": "eur"}
+
+{"TYPE": "transfer", "client": "1", "tx": 2, "amount": "0.5", "destination": 2}
+{"type": "dispute", "client": 1, "tx": 1, "amount": null}
+"#;
+        let results = parse_ndjson(input);
+        assert_eq!(results.len(), 3);
+        let deposit = results[0].as_ref().expect("record should be ok");
+        assert_eq!(deposit.amount, Some(amount("1.5")));
+        assert_eq!(deposit.currency, "EUR".parse().ok());
+        let transfer = results[1].as_ref().expect("record should be ok");
+        assert_eq!(transfer.line, 3);
+        assert_eq!(transfer.amount, Some(amount("0.5")));
+        assert_eq!(transfer.destination, Some(ClientId(2)));
+        let dispute = results[2].as_ref().expect("record should be ok");
+        assert_eq!(dispute.amount, None);
+    }
+
+    #[test]
+    fn test_ndjson_validated_like_csv() {
+        let input = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "-5"}
+{"type": "withdrawal", "client": 1, "tx": 2}
+{"type": "refund", "client": 1, "tx": 3, "amount": "1"}
+{"type": "deposit", "client": 1, "tx": 4, "amount": "1"
+[1, 2]
+"#;
+        let kinds: Vec<_> = parse_ndjson(input)
+            .iter()
+            .map(|r| r.as_ref().expect_err("row should be rejected").kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ParseErrorKind::NegativeAmount,
+                ParseErrorKind::MissingAmount,
+                ParseErrorKind::UnknownType,
+                ParseErrorKind::Json,
+                ParseErrorKind::Json,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_json_amounts_read_as_written() {
+        let input = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 1e2}
+{"type": "deposit", "client": 1, "tx": 2, "amount": 1.2345678901234567890}
+{"type": "deposit", "client": 1, "tx": 3, "amount": 100}
+{"type": "deposit", "client": 1, "tx": 4, "amount": "1e2"}
+{"type": "deposit", "client": 1, "tx": 5, "amount": "1.2345678901234567890"}
+"#;
+        let results = parse_ndjson(input);
+        for index in [0, 3] {
+            let error = results[index].as_ref().expect_err("row should be rejected");
+            assert_eq!(error.kind, ParseErrorKind::InvalidAmount);
+            assert_eq!(error.message, "Invalid amount: '1e2'");
+        }
+        for (index, expected) in [(1, "1.2346"), (2, "100"), (4, "1.2346")] {
+            let record = results[index].as_ref().expect("record should be ok");
+            assert_eq!(record.amount, Some(amount(expected)));
+        }
+    }
+
+    #[test]
+    fn test_parse_json_array() {
+        let input =
+            r#"[{"type": "deposit", "client": 1, "tx": 1, "amount": "2"}, {"type": "deposit"}]"#;
+        let mut parser = JsonParser::array(Cursor::new(input)).expect("failed to create parser");
+        let record = parser
+            .next_record()
+            .expect("first record")
+            .expect("record should be ok");
+        assert_eq!(record.line, 1);
+        assert_eq!(record.amount, Some(amount("2")));
+        let error = parser
+            .next_record()
+            .expect("second record")
+            .expect_err("record should be rejected");
+        assert_eq!((error.line, error.kind), (2, ParseErrorKind::InvalidClient));
+        assert_eq!(parser.current_row(), r#"{"type": "deposit"}"#);
+        assert!(parser.next_record().is_none());
+
+        assert!(JsonParser::array(Cursor::new("{}")).is_err());
+    }
+
+    #[test]
+    fn test_input_format_from_path() {
+        assert_eq!(
+            InputFormat::from_path(Path::new("a.JSON")),
+            InputFormat::Json
+        );
+        assert_eq!(
+            InputFormat::from_path(Path::new("a.jsonl")),
+            InputFormat::Ndjson
+        );
+        assert_eq!(
+            InputFormat::from_path(Path::new("/dev/stdin")),
+            InputFormat::Csv
+        );
+        assert_eq!("ndjson".parse(), Ok(InputFormat::Ndjson));
+        assert!("xml".parse::<InputFormat>().is_err());
+    }
+
+    #[test]
+    fn test_parse_partial_dispute_amount() {
+        let input = "type,client,tx,amount\ndispute,1,1,2.5\n";
+        let results: Vec<_> = parse_csv(input);
+        let record = results[0].as_ref().expect("record should be ok");
+        assert_eq!(record.amount, Some(amount("2.5")));
+    }
+
     #[test]
     fn test_parse_max_precision_4_decimals() {
         let input = "type,client,tx,amount\ndeposit,1,1,1.2345\n";
@@ -320,7 +1098,7 @@ mod tests {
 
     #[test]
     fn test_unknown_transaction_type() {
-        let input = "type,client,tx,amount\ntransfer,1,1,100\n";
+        let input = "type,client,tx,amount\nrefund,1,1,100\n";
         let results: Vec<_> = parse_csv(input);
         assert!(results[0].is_err());
     }
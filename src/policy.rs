@@ -0,0 +1,18 @@
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use crate::domain::LedgerPolicy;
+
+/// Parses a JSON ledger policy; fields left out keep their defaults.
+pub fn read_policy<R: Read>(reader: R) -> Result<LedgerPolicy, String> {
+    serde_json::from_reader(reader).map_err(|e| e.to_string())
+}
+
+/// Loads a ledger policy file from disk.
+pub fn load_policy(path: &Path) -> Result<LedgerPolicy, String> {
+    let file =
+        File::open(path).map_err(|e| format!("Failed to open '{}': {}", path.display(), e))?;
+    read_policy(BufReader::new(file))
+        .map_err(|e| format!("Failed to load '{}': {}", path.display(), e))
+}
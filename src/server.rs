@@ -0,0 +1,490 @@
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+use crate::domain::{Ledger, RejectionReason};
+use crate::parser::LineParser;
+use crate::writer::{write_csv, OutputRecord};
+
+/// Line sent instead of a CSV row to receive the current balances.
+pub const DUMP_COMMAND: &str = "dump";
+
+/// Error category answered once the ledger is no longer served, see [`lock`].
+pub const LEDGER_UNAVAILABLE: &str = "ledger_unavailable";
+
+/// Longest line read from a client, line ending included.
+pub const MAX_LINE_BYTES: usize = 64 * 1024;
+
+/// Error category answered for a line longer than [`MAX_LINE_BYTES`].
+pub const LINE_TOO_LONG: &str = "line_too_long";
+
+/// Error category answered for a line that is not valid UTF-8.
+pub const INVALID_UTF8: &str = "invalid_utf8";
+
+/// Connections served at once by default, see [`Server::with_max_connections`].
+pub const MAX_CONNECTIONS: usize = 256;
+
+/// Accepts TCP connections and applies what they send to one ledger shared by all of them.
+///
+/// By default, every connection starts with a header line in the format read by
+/// [`CsvParser`](crate::parser::CsvParser), followed by rows. Each row is answered with
+/// one line: `ok`, `rejected <category>: <message>` or `error <category>: <message>`.
+/// A `dump` line is answered with the balances as written by [`write_csv`], followed by
+/// an empty line. A line longer than [`MAX_LINE_BYTES`] is answered with
+/// `error line_too_long: <message>` and skipped, one that is not UTF-8 with
+/// `error invalid_utf8: <message>`. Once a connection panicked while changing the ledger, or its storage
+/// failed, every line is answered with `error ledger_unavailable: <message>` and the
+/// connection is closed. Connections beyond the limit get the same answer right away.
+pub struct Server {
+    listener: TcpListener,
+    ledger: Arc<Mutex<Ledger>>,
+    handler: Handler,
+    refusal: Refusal,
+    max_connections: usize,
+    connections: Arc<AtomicUsize>,
+}
+
+/// Serves one accepted connection against the shared ledger.
+pub type Handler = fn(TcpStream, &Mutex<Ledger>) -> io::Result<()>;
+
+/// Answers a connection that is not served with the reason, without reading from it.
+pub type Refusal = fn(TcpStream, io::Error) -> io::Result<()>;
+
+impl Server {
+    pub fn bind(addr: impl ToSocketAddrs, ledger: Ledger) -> io::Result<Self> {
+        Ok(Server {
+            listener: TcpListener::bind(addr)?,
+            ledger: Arc::new(Mutex::new(ledger)),
+            handler: handle_connection,
+            refusal: refuse_connection,
+            max_connections: MAX_CONNECTIONS,
+            connections: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// Serves connections with `handler` instead of the line protocol, answering those
+    /// over the limit with `refusal`, e.g. [`crate::http::handle_connection`] and
+    /// [`crate::http::refuse_connection`].
+    pub fn with_handler(mut self, handler: Handler, refusal: Refusal) -> Self {
+        self.handler = handler;
+        self.refusal = refusal;
+        self
+    }
+
+    /// Serves at most `max_connections` at once; each one takes a thread.
+    pub fn with_max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections;
+        self
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn ledger(&self) -> Arc<Mutex<Ledger>> {
+        Arc::clone(&self.ledger)
+    }
+
+    /// Serves every accepted connection on its own thread; only returns on listener errors.
+    pub fn run(&self) -> io::Result<()> {
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                // Failures of a single handshake do not affect other clients
+                Err(e) if e.kind() == io::ErrorKind::ConnectionAborted => continue,
+                Err(e) => return Err(e),
+            };
+            let Some(slot) = Slot::take(&self.connections, self.max_connections) else {
+                let error = io::Error::other(format!(
+                    "the server is serving its limit of {} connections",
+                    self.max_connections
+                ));
+                let _ = (self.refusal)(stream, error);
+                continue;
+            };
+            let ledger = self.ledger();
+            let handler = self.handler;
+            thread::spawn(move || {
+                // A client hanging up mid-stream is not an error for the server
+                let _ = handler(stream, &ledger);
+                drop(slot);
+            });
+        }
+        Ok(())
+    }
+}
+
+/// One of the connections counted against [`Server::with_max_connections`], given
+/// back when dropped, even by a panicking handler.
+struct Slot(Arc<AtomicUsize>);
+
+impl Slot {
+    fn take(connections: &Arc<AtomicUsize>, max_connections: usize) -> Option<Self> {
+        connections
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+                (count < max_connections).then_some(count + 1)
+            })
+            .ok()
+            .map(|_| Slot(Arc::clone(connections)))
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Answers with [`LEDGER_UNAVAILABLE`] and closes the connection.
+pub fn refuse_connection(stream: TcpStream, error: io::Error) -> io::Result<()> {
+    refuse(BufWriter::new(stream), error)
+}
+
+/// Reads a header and rows from `stream` until it is closed, answering every line.
+pub fn handle_connection(stream: TcpStream, ledger: &Mutex<Ledger>) -> io::Result<()> {
+    let mut writer = BufWriter::new(stream.try_clone()?);
+    let mut reader = BufReader::new(stream);
+    let line = match read_line(&mut reader)? {
+        ReadLine::Text(line) => line,
+        ReadLine::End => return Ok(()),
+        ReadLine::TooLong => {
+            writeln!(writer, "error {}: {}", LINE_TOO_LONG, line_too_long())?;
+            return writer.flush();
+        }
+        ReadLine::NotUtf8 => {
+            writeln!(writer, "error {}: {}", INVALID_UTF8, not_utf8())?;
+            return writer.flush();
+        }
+    };
+    let parser = match LineParser::new(line.trim_end_matches(['\r', '\n'])) {
+        Ok(parser) => parser,
+        Err(e) => {
+            writeln!(writer, "error {}", e)?;
+            return writer.flush();
+        }
+    };
+
+    let mut line_number = 1;
+    loop {
+        line_number += 1;
+        let line = match read_line(&mut reader)? {
+            ReadLine::Text(line) => line,
+            ReadLine::End => break,
+            ReadLine::TooLong => {
+                writeln!(writer, "error {}: {}", LINE_TOO_LONG, line_too_long())?;
+                writer.flush()?;
+                // Dropped without buffering, however long it goes on
+                reader.skip_until(b'\n')?;
+                continue;
+            }
+            ReadLine::NotUtf8 => {
+                writeln!(writer, "error {}: {}", INVALID_UTF8, not_utf8())?;
+                writer.flush()?;
+                continue;
+            }
+        };
+        let row = line.trim();
+        if row.is_empty() {
+            continue;
+        }
+
+        if row.eq_ignore_ascii_case(DUMP_COMMAND) {
+            // Copy the balances so a slow reader does not hold up other connections
+            let records: Vec<_> = match lock(ledger) {
+                Ok(ledger) => ledger
+                    .accounts()
+                    .iter()
+                    .flat_map(|(client_id, account)| {
+                        OutputRecord::from_account_balances(*client_id, account)
+                    })
+                    .collect(),
+                Err(e) => return refuse(writer, e),
+            };
+            write_csv(&mut writer, records.into_iter())?;
+            writeln!(writer)?;
+        } else {
+            match parser.parse(row, line_number) {
+                Ok(record) => {
+                    let result = match lock(ledger) {
+                        Ok(mut ledger) => ledger.process_transaction(&record.transaction()),
+                        Err(e) => return refuse(writer, e),
+                    };
+                    match result {
+                        Ok(_) => writeln!(writer, "ok")?,
+                        // The ledger refuses every later row, like a poisoned one
+                        Err(reason @ RejectionReason::Storage(_)) => {
+                            return refuse(writer, io::Error::other(reason.to_string()))
+                        }
+                        Err(reason) => {
+                            writeln!(writer, "rejected {}: {}", reason.category(), reason)?
+                        }
+                    }
+                }
+                Err(e) => writeln!(writer, "error {}: {}", e.kind.category(), e.message)?,
+            }
+        }
+
+        // Replies to pipelined rows are batched, but never held back while the client waits
+        if reader.buffer().is_empty() {
+            writer.flush()?;
+        }
+    }
+
+    writer.flush()
+}
+
+/// A line read by [`read_line`]
+pub(crate) enum ReadLine {
+    Text(String),
+    End,
+    /// Longer than [`MAX_LINE_BYTES`]; what was read of it is dropped and the rest is
+    /// left in the reader.
+    TooLong,
+    /// Read whole but not valid UTF-8.
+    NotUtf8,
+}
+
+/// Reads a line like [`BufRead::read_line`], but never buffers more than
+/// [`MAX_LINE_BYTES`] of it, so a client cannot grow the server's memory by never
+/// ending a line.
+pub(crate) fn read_line(reader: &mut impl BufRead) -> io::Result<ReadLine> {
+    let mut bytes = Vec::new();
+    let limit = MAX_LINE_BYTES as u64;
+    if reader.by_ref().take(limit).read_until(b'\n', &mut bytes)? == 0 {
+        return Ok(ReadLine::End);
+    }
+    if bytes.len() == MAX_LINE_BYTES && !bytes.ends_with(b"\n") {
+        return Ok(ReadLine::TooLong);
+    }
+    Ok(String::from_utf8(bytes).map_or(ReadLine::NotUtf8, ReadLine::Text))
+}
+
+pub(crate) fn line_too_long() -> String {
+    format!("line exceeds {} bytes", MAX_LINE_BYTES)
+}
+
+pub(crate) fn not_utf8() -> &'static str {
+    "line is not valid UTF-8"
+}
+
+/// Answers a line with [`LEDGER_UNAVAILABLE`] and closes the connection.
+fn refuse(mut writer: BufWriter<TcpStream>, error: io::Error) -> io::Result<()> {
+    writeln!(writer, "error {}: {}", LEDGER_UNAVAILABLE, error)?;
+    writer.flush()?;
+    Err(error)
+}
+
+/// Locks the shared ledger, failing for good once a connection panicked while holding it.
+///
+/// A row can change the ledger in several steps (a chargeback locking the account, a fee,
+/// disputes resolved as time passes), so a panic may leave the ledger half-updated; it is
+/// no longer served rather than built upon.
+pub(crate) fn lock(ledger: &Mutex<Ledger>) -> io::Result<MutexGuard<'_, Ledger>> {
+    ledger.lock().map_err(|_| {
+        io::Error::other("the ledger may be half-updated after a panic and is no longer served")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::types::{Amount, ClientId};
+
+    fn amount(s: &str) -> Amount {
+        Amount::from_str_rounded(s).expect("failed to parse amount")
+    }
+
+    fn start(ledger: Ledger) -> (SocketAddr, Arc<Mutex<Ledger>>) {
+        let server = Server::bind("127.0.0.1:0", ledger).expect("failed to bind server");
+        let addr = server.local_addr().expect("server should have an address");
+        let shared = server.ledger();
+        thread::spawn(move || server.run());
+        (addr, shared)
+    }
+
+    struct Client {
+        reader: BufReader<TcpStream>,
+        writer: TcpStream,
+    }
+
+    impl Client {
+        fn connect(addr: SocketAddr, header: &str) -> Self {
+            let writer = TcpStream::connect(addr).expect("failed to connect");
+            let reader = BufReader::new(writer.try_clone().expect("failed to clone stream"));
+            let mut client = Client { reader, writer };
+            client.send(header);
+            client
+        }
+
+        fn send(&mut self, line: &str) {
+            writeln!(self.writer, "{}", line).expect("failed to send line");
+        }
+
+        fn reply(&mut self) -> String {
+            let mut line = String::new();
+            self.reader
+                .read_line(&mut line)
+                .expect("failed to read reply");
+            line.trim_end().to_string()
+        }
+
+        fn request(&mut self, line: &str) -> String {
+            self.send(line);
+            self.reply()
+        }
+    }
+
+    #[test]
+    fn test_concurrent_connections_share_ledger() {
+        let (addr, ledger) = start(Ledger::new());
+        let handles: Vec<_> = (0..4u32)
+            .map(|worker| {
+                thread::spawn(move || {
+                    let mut client = Client::connect(addr, "type,client,tx,amount");
+                    for i in 0..50 {
+                        client.send(&format!("deposit,1,{},1.5", worker * 100 + i));
+                    }
+                    (0..50).all(|_| client.reply() == "ok")
+                })
+            })
+            .collect();
+        for handle in handles {
+            assert!(handle.join().expect("client thread panicked"));
+        }
+
+        let account = lock(&ledger)
+            .expect("ledger should be available")
+            .get_account(ClientId(1))
+            .cloned()
+            .expect("client 1 account should exist");
+        assert_eq!(account.available, amount("300"));
+    }
+
+    #[test]
+    fn test_replies_to_each_row() {
+        let (addr, _) = start(Ledger::new());
+        let mut client = Client::connect(addr, "type,client,tx,amount");
+        assert_eq!(client.request("deposit,1,1,10"), "ok");
+        assert_eq!(
+            client.request("withdrawal,1,2,15"),
+            "rejected insufficient_funds: insufficient funds: available 10.0000, requested 15.0000"
+        );
+        assert_eq!(
+            client.request("deposit,abc,3,1"),
+            "error invalid_client: Invalid client ID: 'abc'"
+        );
+        assert_eq!(
+            client.request("deposit,1,1,10"),
+            "rejected duplicate_tx: transaction 1 was already processed"
+        );
+    }
+
+    #[test]
+    fn test_long_line_is_refused_and_skipped() {
+        let (addr, _) = start(Ledger::new());
+        let mut client = Client::connect(addr, "type,client,tx,amount");
+        assert_eq!(
+            client.request(&format!("deposit,1,1,{}", "9".repeat(2 * MAX_LINE_BYTES))),
+            "error line_too_long: line exceeds 65536 bytes"
+        );
+        assert_eq!(client.request("deposit,1,2,10"), "ok");
+    }
+
+    #[test]
+    fn test_invalid_utf8_line_is_answered() {
+        let (addr, _) = start(Ledger::new());
+        let mut client = Client::connect(addr, "type,client,tx,amount");
+        client
+            .writer
+            .write_all(b"deposit,1,1,\xff10\n")
+            .expect("failed to send line");
+        assert_eq!(
+            client.reply(),
+            "error invalid_utf8: line is not valid UTF-8"
+        );
+        assert_eq!(client.request("deposit,1,2,10"), "ok");
+    }
+
+    #[test]
+    fn test_connections_over_limit_are_refused() {
+        let server = Server::bind("127.0.0.1:0", Ledger::new())
+            .expect("failed to bind server")
+            .with_max_connections(1);
+        let addr = server.local_addr().expect("server should have an address");
+        thread::spawn(move || server.run());
+
+        let mut served = Client::connect(addr, "type,client,tx,amount");
+        assert_eq!(served.request("deposit,1,1,10"), "ok");
+
+        let refused = TcpStream::connect(addr).expect("failed to connect");
+        let mut reply = String::new();
+        BufReader::new(refused)
+            .read_to_string(&mut reply)
+            .expect("failed to read reply");
+        assert_eq!(
+            reply,
+            "error ledger_unavailable: the server is serving its limit of 1 connections\n"
+        );
+
+        // The slot is given back once the served connection is closed
+        drop(served);
+        let served_again = (2..100).any(|tx| {
+            thread::sleep(std::time::Duration::from_millis(10));
+            let mut reply = String::new();
+            TcpStream::connect(addr)
+                .and_then(|mut stream| {
+                    writeln!(stream, "type,client,tx,amount\ndeposit,1,{},1", tx)?;
+                    BufReader::new(stream).read_line(&mut reply)
+                })
+                .is_ok_and(|_| reply == "ok\n")
+        });
+        assert!(served_again);
+    }
+
+    #[test]
+    fn test_dump_writes_balances() {
+        let (addr, _) = start(Ledger::new());
+        let mut client = Client::connect(addr, "type,client,tx,amount");
+        assert_eq!(client.request("deposit,2,1,2.5"), "ok");
+
+        client.send("dump");
+        let dump: Vec<_> = std::iter::from_fn(|| Some(client.reply()))
+            .take_while(|line| !line.is_empty())
+            .collect();
+        assert_eq!(
+            dump,
+            vec![
+                "client,available,held,total,locked",
+                "2,2.5000,0.0000,2.5000,false"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_poisoned_ledger_is_no_longer_served() {
+        let (addr, ledger) = start(Ledger::new());
+        let mut client = Client::connect(addr, "type,client,tx,amount");
+        assert_eq!(client.request("deposit,1,1,10"), "ok");
+
+        let _ = thread::spawn(move || {
+            let _guard = ledger.lock();
+            panic!("panic while holding the ledger");
+        })
+        .join();
+        assert_eq!(
+            client.request("deposit,1,2,10"),
+            "error ledger_unavailable: the ledger may be half-updated after a panic and is no longer served"
+        );
+        assert_eq!(client.reply(), "");
+    }
+
+    #[test]
+    fn test_invalid_header_closes_connection() {
+        let (addr, _) = start(Ledger::new());
+        let mut client = Client::connect(addr, "type,client,tx");
+        assert_eq!(client.reply(), "error Missing required column: 'amount'");
+        assert_eq!(client.reply(), "");
+    }
+}
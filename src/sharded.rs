@@ -0,0 +1,574 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::thread::{self, JoinHandle};
+
+use crate::domain::types::{ClientId, Transaction, TransactionId, TransactionType};
+use crate::domain::{Ledger, RejectionReason};
+
+/// Transactions handed to a worker per channel message, to keep channel overhead low.
+const BATCH_SIZE: usize = 256;
+/// Batches buffered per worker before the router blocks.
+const QUEUE_DEPTH: usize = 64;
+/// Tx ids per page of a [`TxIdSet`], 8 KiB of bits.
+const PAGE_BITS: u32 = 1 << 16;
+
+/// A transaction refused by the router or a shard, with the caller's tag
+#[derive(Debug)]
+pub struct Rejected<T> {
+    pub tag: T,
+    pub transaction: Transaction,
+    pub reason: RejectionReason,
+}
+
+/// A transaction queued for a worker
+struct Routed<T> {
+    transaction: Transaction,
+    tag: T,
+    /// The tx id was accepted by a shard before this transaction was routed.
+    taken: bool,
+}
+
+/// Deposit, withdrawal and transfer tx ids of one batch, with whether each was accepted.
+type Report = Vec<(TransactionId, bool)>;
+
+/// What a worker hands back: its ledger, its rejections, and disputes of transactions
+/// that turned out to belong to a client of another shard.
+type ShardResult<T> = (Ledger, Vec<Rejected<T>>, Vec<Rejected<T>>);
+
+struct Shard<T> {
+    sender: SyncSender<Vec<Routed<T>>>,
+    reports: Receiver<Report>,
+    batch: Vec<Routed<T>>,
+    handle: JoinHandle<ShardResult<T>>,
+}
+
+/// Processes transactions on worker threads, each owning the [`Ledger`] of the clients
+/// with `client_id % shard_count` equal to its index.
+///
+/// Transactions of one client always reach the same worker in submission order. As in a
+/// single ledger, a tx id belongs to the first deposit, withdrawal or transfer accepted
+/// with it: workers report back which ids they accepted, and the router only waits for a
+/// worker when a transaction reuses an id still queued there.
+///
+/// A transfer between clients of different shards needs both accounts at once, so the
+/// first one stops the workers: their ledgers are merged and every later transaction is
+/// processed by the merged ledger on the calling thread, giving the same results as a
+/// single ledger throughout.
+pub struct ShardedLedger<T: Send + 'static> {
+    shards: Vec<Shard<T>>,
+    /// Tx ids accepted by any shard, as far as reported back.
+    accepted: TxIdSet,
+    /// Deposit, withdrawal and transfer tx ids queued but not reported back yet, with
+    /// the shard they went to and how many are queued there; bounded by the queues.
+    in_flight: HashMap<TransactionId, (usize, usize)>,
+    rejected: Vec<Rejected<T>>,
+    /// The merged shards, once a transfer crossed them.
+    merged: Option<Ledger>,
+}
+
+impl<T: Send + 'static> ShardedLedger<T> {
+    /// Spawns `shard_count` workers, each starting from `new_ledger()`.
+    pub fn new(shard_count: usize, new_ledger: impl Fn() -> Ledger) -> Self {
+        assert!(shard_count > 0, "at least one shard is required");
+        let shards = (0..shard_count)
+            .map(|_| {
+                let (sender, receiver) = mpsc::sync_channel::<Vec<Routed<T>>>(QUEUE_DEPTH);
+                let (report_sender, reports) = mpsc::channel();
+                let ledger = new_ledger();
+                let handle = thread::spawn(move || run_shard(ledger, receiver, report_sender));
+                Shard {
+                    sender,
+                    reports,
+                    batch: Vec::with_capacity(BATCH_SIZE),
+                    handle,
+                }
+            })
+            .collect();
+
+        ShardedLedger {
+            shards,
+            accepted: TxIdSet::new(),
+            in_flight: HashMap::new(),
+            rejected: Vec::new(),
+            merged: None,
+        }
+    }
+
+    fn shard_of(&self, client_id: ClientId) -> usize {
+        usize::from(client_id.0) % self.shards.len()
+    }
+
+    /// Queues a transaction; rejections are only known once [`ShardedLedger::finish`] returns.
+    ///
+    /// Fails when a worker panicked, or when the shards could not be merged for a
+    /// transfer between them.
+    pub fn process(&mut self, transaction: Transaction, tag: T) -> io::Result<()> {
+        if self.merged.is_none() && self.crosses_shards(&transaction) {
+            self.merged = Some(self.merge_shards()?);
+        }
+        if let Some(ledger) = &mut self.merged {
+            if let Err(reason) = ledger.process_transaction(&transaction) {
+                self.rejected.push(Rejected {
+                    tag,
+                    transaction,
+                    reason,
+                });
+            }
+            return Ok(());
+        }
+
+        let Transaction {
+            tx_type,
+            client_id,
+            tx_id,
+            ..
+        } = transaction;
+        let index = self.shard_of(client_id);
+        let taken = match tx_type {
+            TransactionType::Lock | TransactionType::Unlock | TransactionType::Limit => false,
+            _ => {
+                self.wait_for_other_shards(tx_id, index)?;
+                self.accepted.contains(tx_id)
+            }
+        };
+        if claims_tx_id(tx_type) {
+            self.in_flight.entry(tx_id).or_insert((index, 0)).1 += 1;
+        }
+
+        let shard = &mut self.shards[index];
+        shard.batch.push(Routed {
+            transaction,
+            tag,
+            taken,
+        });
+        if shard.batch.len() >= BATCH_SIZE {
+            self.flush(index);
+            self.collect_reports();
+        }
+        Ok(())
+    }
+
+    fn crosses_shards(&self, transaction: &Transaction) -> bool {
+        transaction.tx_type == TransactionType::Transfer
+            && transaction.destination.is_some_and(|destination| {
+                self.shard_of(destination) != self.shard_of(transaction.client_id)
+            })
+    }
+
+    /// Waits until no shard but `index` has a deposit, withdrawal or transfer with
+    /// `tx_id` queued, so `accepted` knows whether an earlier transaction took the id.
+    fn wait_for_other_shards(&mut self, tx_id: TransactionId, index: usize) -> io::Result<()> {
+        while let Some(&(other, _)) = self.in_flight.get(&tx_id) {
+            if other == index {
+                break;
+            }
+            self.flush(other);
+            // The worker only drops its report sender by panicking
+            let report = self.shards[other]
+                .reports
+                .recv()
+                .map_err(|_| shard_panicked())?;
+            self.record(report);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self, index: usize) {
+        let shard = &mut self.shards[index];
+        if shard.batch.is_empty() {
+            return;
+        }
+        let batch = std::mem::replace(&mut shard.batch, Vec::with_capacity(BATCH_SIZE));
+        // A worker only stops early by panicking, which `finish` reports
+        let _ = shard.sender.send(batch);
+    }
+
+    /// Takes in every report already sent back, so `in_flight` stays bounded.
+    fn collect_reports(&mut self) {
+        for index in 0..self.shards.len() {
+            while let Ok(report) = self.shards[index].reports.try_recv() {
+                self.record(report);
+            }
+        }
+    }
+
+    fn record(&mut self, report: Report) {
+        for (tx_id, accepted) in report {
+            if accepted {
+                self.accepted.insert(tx_id);
+            }
+            if let Some((_, queued)) = self.in_flight.get_mut(&tx_id) {
+                *queued -= 1;
+                if *queued == 0 {
+                    self.in_flight.remove(&tx_id);
+                }
+            }
+        }
+    }
+
+    /// Waits for every queued transaction and merges the shards into one ledger.
+    ///
+    /// Rejections are grouped per shard; sort them by tag for input order. Fails when a
+    /// worker panicked or the storage of a shard cannot be read.
+    pub fn finish(mut self) -> io::Result<(Ledger, Vec<Rejected<T>>)> {
+        let ledger = match self.merged.take() {
+            Some(ledger) => ledger,
+            None => self.merge_shards()?,
+        };
+        Ok((ledger, self.rejected))
+    }
+
+    /// Stops the workers once they processed everything queued and merges their ledgers.
+    fn merge_shards(&mut self) -> io::Result<Ledger> {
+        for index in 0..self.shards.len() {
+            self.flush(index);
+        }
+        let mut ledgers = Vec::with_capacity(self.shards.len());
+        let mut owned_elsewhere = Vec::new();
+        for shard in self.shards.drain(..) {
+            drop(shard.sender);
+            let (ledger, rejected, other) = shard.handle.join().map_err(|_| shard_panicked())?;
+            ledgers.push(ledger);
+            self.rejected.extend(rejected);
+            owned_elsewhere.extend(other);
+        }
+
+        let ledger = Ledger::merge(ledgers)?;
+        for mut rejection in owned_elsewhere {
+            let Transaction {
+                client_id, tx_id, ..
+            } = rejection.transaction;
+            let owner = ledger
+                .get_transaction(tx_id)?
+                .ok_or_else(|| {
+                    io::Error::other(format!(
+                        "transaction {} was accepted by a shard but is not stored",
+                        tx_id
+                    ))
+                })?
+                .client_id;
+            rejection.reason = RejectionReason::ClientMismatch {
+                tx_id,
+                owner,
+                requested_by: client_id,
+            };
+            self.rejected.push(rejection);
+        }
+        Ok(ledger)
+    }
+}
+
+fn shard_panicked() -> io::Error {
+    io::Error::other("a ledger shard panicked")
+}
+
+/// Deposits, withdrawals and transfers use up their tx id once accepted.
+fn claims_tx_id(tx_type: TransactionType) -> bool {
+    matches!(
+        tx_type,
+        TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Transfer
+    )
+}
+
+fn run_shard<T>(
+    mut ledger: Ledger,
+    receiver: Receiver<Vec<Routed<T>>>,
+    reports: Sender<Report>,
+) -> ShardResult<T> {
+    let mut rejected = Vec::new();
+    let mut owned_elsewhere = Vec::new();
+    for batch in receiver {
+        let mut report = Vec::new();
+        for Routed {
+            transaction,
+            tag,
+            taken,
+        } in batch
+        {
+            let claims = claims_tx_id(transaction.tx_type);
+            // Another shard accepted the id, so the ledger rejects it as a duplicate
+            if taken && claims {
+                ledger.reserve_tx_id(transaction.tx_id);
+            }
+            let result = ledger.process_transaction(&transaction);
+            if claims {
+                report.push((transaction.tx_id, result.is_ok()));
+            }
+            let Err(reason) = result else { continue };
+            let rejection = Rejected {
+                tag,
+                transaction,
+                reason,
+            };
+            match rejection.reason {
+                // The owner is looked up in the merged ledger
+                RejectionReason::TransactionNotFound(_) if taken => owned_elsewhere.push(rejection),
+                _ => rejected.push(rejection),
+            }
+        }
+        // The router only stops listening once every worker is done
+        let _ = reports.send(report);
+    }
+    (ledger, rejected, owned_elsewhere)
+}
+
+/// Set of tx ids as a bitmap allocated in pages on first use
+///
+/// Takes at most one bit per possible tx id (512 MiB), and far less for ids from a few
+/// dense ranges such as the sequential ids of a nightly file.
+struct TxIdSet {
+    pages: Vec<Option<Box<[u64]>>>,
+}
+
+impl TxIdSet {
+    fn new() -> Self {
+        let page_count = (u32::MAX / PAGE_BITS + 1) as usize;
+        TxIdSet {
+            pages: (0..page_count).map(|_| None).collect(),
+        }
+    }
+
+    /// Page index, word index within the page and bit mask of `tx_id`.
+    fn position(tx_id: TransactionId) -> (usize, usize, u64) {
+        let id = tx_id.0;
+        let offset = id % PAGE_BITS;
+        (
+            (id / PAGE_BITS) as usize,
+            (offset / 64) as usize,
+            1 << (offset % 64),
+        )
+    }
+
+    fn insert(&mut self, tx_id: TransactionId) {
+        let (page, word, bit) = Self::position(tx_id);
+        let page = self.pages[page]
+            .get_or_insert_with(|| vec![0; (PAGE_BITS / 64) as usize].into_boxed_slice());
+        page[word] |= bit;
+    }
+
+    fn contains(&self, tx_id: TransactionId) -> bool {
+        let (page, word, bit) = Self::position(tx_id);
+        self.pages[page]
+            .as_ref()
+            .is_some_and(|page| page[word] & bit != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::types::Amount;
+
+    fn amount(s: &str) -> Amount {
+        Amount::from_str_rounded(s).expect("failed to parse amount")
+    }
+
+    fn transaction(
+        tx_type: TransactionType,
+        client: u16,
+        tx: u32,
+        value: Option<&str>,
+    ) -> Transaction {
+        Transaction {
+            tx_type,
+            client_id: ClientId(client),
+            tx_id: TransactionId(tx),
+            amount: value.map(amount),
+            destination: None,
+            currency: None,
+            timestamp: None,
+            reason: None,
+        }
+    }
+
+    #[test]
+    fn test_matches_single_ledger() {
+        let transactions = vec![
+            transaction(TransactionType::Deposit, 1, 1, Some("100")),
+            transaction(TransactionType::Deposit, 2, 2, Some("50")),
+            transaction(TransactionType::Withdrawal, 1, 3, Some("30")),
+            transaction(TransactionType::Dispute, 2, 2, None),
+            transaction(TransactionType::Withdrawal, 3, 4, Some("1")),
+            transaction(TransactionType::Chargeback, 2, 2, None),
+            transaction(TransactionType::Deposit, 2, 5, Some("5")),
+        ];
+
+        let mut single = Ledger::new();
+        let mut sharded = ShardedLedger::new(3, Ledger::new);
+        for (line, transaction) in transactions.into_iter().enumerate() {
+            let _ = single.process_transaction(&transaction);
+            sharded
+                .process(transaction, line)
+                .expect("shards should process");
+        }
+        let (merged, mut rejected) = sharded.finish().expect("shards should merge");
+        rejected.sort_by_key(|r| r.tag);
+
+        assert_eq!(
+            merged.snapshot().expect("failed to take snapshot"),
+            single.snapshot().expect("failed to take snapshot")
+        );
+        let lines: Vec<_> = rejected.iter().map(|r| r.tag).collect();
+        assert_eq!(lines, vec![4, 6]);
+    }
+
+    #[test]
+    fn test_rejected_transactions_leave_tx_ids_free() {
+        let transactions = vec![
+            transaction(TransactionType::Withdrawal, 1, 1, Some("5")),
+            transaction(TransactionType::Deposit, 1, 1, Some("10")),
+            // Rejected in one shard, then used by a client of another shard
+            transaction(TransactionType::Withdrawal, 1, 2, Some("50")),
+            transaction(TransactionType::Deposit, 2, 2, Some("20")),
+            transaction(TransactionType::Deposit, 1, 2, Some("1")),
+            transaction(TransactionType::Dispute, 1, 2, None),
+            // Disputed before the id is used, then by the wrong client
+            transaction(TransactionType::Dispute, 2, 3, None),
+            transaction(TransactionType::Deposit, 3, 3, Some("7")),
+            transaction(TransactionType::Dispute, 2, 3, None),
+            transaction(TransactionType::Dispute, 3, 3, None),
+        ];
+
+        let mut single = Ledger::new();
+        let mut expected = Vec::new();
+        let mut sharded = ShardedLedger::new(2, Ledger::new);
+        for (line, transaction) in transactions.into_iter().enumerate() {
+            if let Err(reason) = single.process_transaction(&transaction) {
+                expected.push((line, reason));
+            }
+            sharded
+                .process(transaction, line)
+                .expect("shards should process");
+        }
+        let (merged, mut rejected) = sharded.finish().expect("shards should merge");
+        rejected.sort_by_key(|r| r.tag);
+
+        assert_eq!(
+            merged.snapshot().expect("failed to take snapshot"),
+            single.snapshot().expect("failed to take snapshot")
+        );
+        let reasons: Vec<_> = rejected.iter().map(|r| (r.tag, r.reason.clone())).collect();
+        assert_eq!(reasons, expected);
+        assert_eq!(
+            merged
+                .get_account(ClientId(1))
+                .expect("client 1 account should exist")
+                .available,
+            amount("10")
+        );
+    }
+
+    #[test]
+    fn test_tx_id_set() {
+        let mut set = TxIdSet::new();
+        for id in [0, 63, 64, PAGE_BITS, u32::MAX] {
+            assert!(!set.contains(TransactionId(id)));
+            set.insert(TransactionId(id));
+            assert!(set.contains(TransactionId(id)));
+        }
+        assert!(!set.contains(TransactionId(1)));
+        assert!(!set.contains(TransactionId(u32::MAX - 1)));
+        assert_eq!(set.pages.iter().filter(|page| page.is_some()).count(), 3);
+    }
+
+    #[test]
+    fn test_tx_ids_unique_across_shards() {
+        let mut sharded = ShardedLedger::new(2, Ledger::new);
+        sharded
+            .process(transaction(TransactionType::Deposit, 1, 7, Some("10")), 0)
+            .expect("shards should process");
+        sharded
+            .process(transaction(TransactionType::Deposit, 2, 7, Some("10")), 1)
+            .expect("shards should process");
+        sharded
+            .process(transaction(TransactionType::Dispute, 2, 7, None), 2)
+            .expect("shards should process");
+        let (merged, rejected) = sharded.finish().expect("shards should merge");
+
+        assert!(merged.get_account(ClientId(2)).is_none());
+        let reasons: Vec<_> = rejected.iter().map(|r| r.reason.clone()).collect();
+        assert_eq!(
+            reasons,
+            vec![
+                RejectionReason::DuplicateTransaction(TransactionId(7)),
+                RejectionReason::ClientMismatch {
+                    tx_id: TransactionId(7),
+                    owner: ClientId(1),
+                    requested_by: ClientId(2),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cross_shard_transfers_match_single_ledger() {
+        let mut transactions = vec![
+            transaction(TransactionType::Deposit, 1, 1, Some("10")),
+            transaction(TransactionType::Deposit, 2, 2, Some("5")),
+            transaction(TransactionType::Transfer, 1, 3, Some("4")),
+            transaction(TransactionType::Transfer, 1, 4, Some("4")),
+            transaction(TransactionType::Transfer, 2, 5, Some("50")),
+            transaction(TransactionType::Dispute, 1, 4, None),
+            transaction(TransactionType::Withdrawal, 2, 6, Some("8")),
+            transaction(TransactionType::Deposit, 3, 1, Some("1")),
+        ];
+        // Within a shard, then across shards of two
+        transactions[2].destination = Some(ClientId(3));
+        transactions[3].destination = Some(ClientId(2));
+        transactions[4].destination = Some(ClientId(1));
+
+        let mut single = Ledger::new();
+        let mut expected = Vec::new();
+        let mut sharded = ShardedLedger::new(2, Ledger::new);
+        for (line, transaction) in transactions.into_iter().enumerate() {
+            if let Err(reason) = single.process_transaction(&transaction) {
+                expected.push((line, reason));
+            }
+            sharded
+                .process(transaction, line)
+                .expect("shards should process");
+        }
+        let (merged, mut rejected) = sharded.finish().expect("shards should merge");
+        rejected.sort_by_key(|r| r.tag);
+
+        assert_eq!(
+            merged.snapshot().expect("failed to take snapshot"),
+            single.snapshot().expect("failed to take snapshot")
+        );
+        let reasons: Vec<_> = rejected.iter().map(|r| (r.tag, r.reason.clone())).collect();
+        assert_eq!(reasons, expected);
+        assert_eq!(
+            merged
+                .get_account(ClientId(2))
+                .expect("client 2 account should exist")
+                .held,
+            amount("4")
+        );
+    }
+
+    #[test]
+    fn test_preserves_order_within_client_across_batches() {
+        let mut single = Ledger::new();
+        let mut sharded = ShardedLedger::new(4, Ledger::new);
+        for i in 0..(BATCH_SIZE as u32 * 3) {
+            let client = (i % 7) as u16;
+            let tx_type = if i % 3 == 0 {
+                TransactionType::Withdrawal
+            } else {
+                TransactionType::Deposit
+            };
+            let transaction = transaction(tx_type, client, i, Some("1.5"));
+            let _ = single.process_transaction(&transaction);
+            sharded
+                .process(transaction, i)
+                .expect("shards should process");
+        }
+        let (merged, _) = sharded.finish().expect("shards should merge");
+
+        assert_eq!(
+            merged.snapshot().expect("failed to take snapshot"),
+            single.snapshot().expect("failed to take snapshot")
+        );
+    }
+}
@@ -0,0 +1,218 @@
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::domain::{Ledger, LedgerSnapshot, SnapshotError};
+
+/// Serializes the full ledger state as a versioned JSON document.
+///
+/// Transactions are streamed from storage, so they are never all in memory at once.
+pub fn write_snapshot<W: Write>(writer: W, ledger: &Ledger) -> Result<(), SnapshotError> {
+    let snapshot = ledger.snapshot_stream();
+    serde_json::to_writer(writer, &snapshot).map_err(|e| match snapshot.take_error() {
+        Some(error) => SnapshotError::from(error),
+        None => SnapshotError::Format(e.to_string()),
+    })
+}
+
+/// Restores a ledger written by [`write_snapshot`].
+pub fn read_snapshot<R: Read>(reader: R) -> Result<Ledger, SnapshotError> {
+    let snapshot: LedgerSnapshot =
+        serde_json::from_reader(reader).map_err(|e| SnapshotError::Format(e.to_string()))?;
+    Ledger::from_snapshot(snapshot)
+}
+
+/// Loads a snapshot file from disk.
+pub fn load_snapshot(path: &Path) -> Result<Ledger, String> {
+    let file =
+        File::open(path).map_err(|e| format!("Failed to open '{}': {}", path.display(), e))?;
+    read_snapshot(BufReader::new(file))
+        .map_err(|e| format!("Failed to load '{}': {}", path.display(), e))
+}
+
+/// Saves a snapshot file, replacing any previous one only once fully written.
+pub fn save_snapshot(path: &Path, ledger: &Ledger) -> Result<(), String> {
+    let tmp_path = tmp_path(path);
+    let file = File::create(&tmp_path)
+        .map_err(|e| format!("Failed to create '{}': {}", tmp_path.display(), e))?;
+    let mut writer = BufWriter::new(file);
+    write_snapshot(&mut writer, ledger)
+        .map_err(|e| format!("Failed to write '{}': {}", tmp_path.display(), e))?;
+    writer
+        .into_inner()
+        .map_err(|e| e.to_string())
+        .and_then(|file| file.sync_all().map_err(|e| e.to_string()))
+        .map_err(|e| format!("Failed to write '{}': {}", tmp_path.display(), e))?;
+    fs::rename(&tmp_path, path).map_err(|e| {
+        format!(
+            "Failed to move '{}' to '{}': {}",
+            tmp_path.display(),
+            path.display(),
+            e
+        )
+    })
+}
+
+/// Where a file is written before being moved to `path`: next to it, keeping its
+/// extension so `state.json` and `state.bin` do not share a temp file.
+pub fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".tmp");
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::types::{
+        Amount, ClientId, LockReason, Transaction, TransactionId, TransactionType,
+    };
+    use std::io::Cursor;
+
+    #[test]
+    fn test_snapshot_json_round_trip() {
+        let mut ledger = Ledger::new();
+        let _ = ledger.process(
+            TransactionType::Deposit,
+            ClientId(1),
+            TransactionId(1),
+            Some(Amount::from_str_rounded("12.5").expect("failed to parse amount")),
+        );
+        let _ = ledger.process(
+            TransactionType::Dispute,
+            ClientId(1),
+            TransactionId(1),
+            None,
+        );
+
+        let mut bytes = Vec::new();
+        write_snapshot(&mut bytes, &ledger).expect("failed to write snapshot");
+        let restored = read_snapshot(Cursor::new(bytes)).expect("failed to read snapshot");
+
+        assert_eq!(
+            restored.snapshot().expect("failed to take snapshot"),
+            ledger.snapshot().expect("failed to take snapshot")
+        );
+    }
+
+    #[test]
+    fn test_snapshot_round_trip_with_currencies() {
+        let mut ledger = Ledger::new();
+        let _ = ledger.process_transaction(&Transaction {
+            tx_type: TransactionType::Deposit,
+            client_id: ClientId(1),
+            tx_id: TransactionId(1),
+            amount: Some(Amount::from_str_rounded("3").expect("failed to parse amount")),
+            destination: None,
+            currency: "CHF".parse().ok(),
+            timestamp: None,
+            reason: None,
+        });
+
+        let mut bytes = Vec::new();
+        write_snapshot(&mut bytes, &ledger).expect("failed to write snapshot");
+        let text = String::from_utf8(bytes.clone()).expect("snapshot should be valid UTF-8");
+        assert!(text.contains("\"currencies\":{\"CHF\":{\"available\":\"3\",\"held\":\"0\"}}"));
+        let restored = read_snapshot(Cursor::new(bytes)).expect("failed to read snapshot");
+
+        assert_eq!(
+            restored.snapshot().expect("failed to take snapshot"),
+            ledger.snapshot().expect("failed to take snapshot")
+        );
+    }
+
+    #[test]
+    fn test_snapshot_document_layout() {
+        let mut ledger = Ledger::new();
+        let _ = ledger.process(
+            TransactionType::Deposit,
+            ClientId(3),
+            TransactionId(9),
+            Some(Amount::from_str_rounded("1").expect("failed to parse amount")),
+        );
+        let mut bytes = Vec::new();
+        write_snapshot(&mut bytes, &ledger).expect("failed to write snapshot");
+        let text = String::from_utf8(bytes).expect("snapshot should be valid UTF-8");
+        assert_eq!(
+            text,
+            "{\"version\":9,\
+             \"accounts\":[{\"client\":3,\"available\":\"1\",\"held\":\"0\",\"locked\":false,\"lock_reason\":null}],\
+             \"transactions\":[{\"tx\":9,\"client_id\":3,\"kind\":\"deposit\",\"amount\":\"1\",\"state\":\"none\",\
+             \"disputed\":\"0\",\"settled\":\"0\"}],\
+             \"processed_tx_ids\":[9],\
+             \"books\":[{\"account\":{\"available\":3},\"balance\":\"1\"},\
+             {\"account\":\"external_funding\",\"balance\":\"-1\"}]}"
+        );
+    }
+
+    #[test]
+    fn test_snapshot_without_kind_reads_as_deposit() {
+        let input = "{\"version\":1,\"accounts\":[],\
+                     \"transactions\":[{\"tx\":9,\"client_id\":3,\"amount\":\"1\",\"state\":\"none\"}],\
+                     \"processed_tx_ids\":[9]}";
+        let mut ledger = read_snapshot(Cursor::new(input)).expect("failed to read snapshot");
+        assert!(ledger
+            .process(
+                TransactionType::Dispute,
+                ClientId(3),
+                TransactionId(9),
+                None
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn test_v1_disputed_transaction_is_fully_disputed() {
+        let input = "{\"version\":1,\
+                     \"accounts\":[{\"client\":3,\"available\":\"0\",\"held\":\"5\",\"locked\":false}],\
+                     \"transactions\":[{\"tx\":9,\"client_id\":3,\"amount\":\"5\",\"state\":\"disputed\"}],\
+                     \"processed_tx_ids\":[9]}";
+        let mut ledger = read_snapshot(Cursor::new(input)).expect("failed to read snapshot");
+        assert!(ledger
+            .process(
+                TransactionType::Resolve,
+                ClientId(3),
+                TransactionId(9),
+                None
+            )
+            .is_ok());
+        let account = ledger
+            .get_account(ClientId(3))
+            .expect("account should exist");
+        assert_eq!(
+            account.available,
+            Amount::from_str_rounded("5").expect("failed to parse amount")
+        );
+        assert_eq!(account.held, Amount::ZERO);
+    }
+
+    #[test]
+    fn test_v2_locked_account_was_charged_back() {
+        let input = "{\"version\":2,\
+                     \"accounts\":[{\"client\":3,\"available\":\"0\",\"held\":\"0\",\"locked\":true}],\
+                     \"transactions\":[],\"processed_tx_ids\":[]}";
+        let ledger = read_snapshot(Cursor::new(input)).expect("failed to read snapshot");
+        let account = ledger
+            .get_account(ClientId(3))
+            .expect("account should exist");
+        assert_eq!(account.lock_reason, Some(LockReason::CHARGEBACK));
+    }
+
+    #[test]
+    fn test_tmp_path_keeps_extension() {
+        assert_eq!(
+            tmp_path(Path::new("out/state.json")),
+            Path::new("out/state.json.tmp")
+        );
+        assert_ne!(
+            tmp_path(Path::new("state.json")),
+            tmp_path(Path::new("state.bin"))
+        );
+    }
+
+    #[test]
+    fn test_malformed_snapshot_rejected() {
+        let result = read_snapshot(Cursor::new("{\"version\":1}"));
+        assert!(matches!(result, Err(SnapshotError::Format(_))));
+    }
+}
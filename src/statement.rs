@@ -0,0 +1,194 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::domain::account::Balance;
+use crate::domain::types::{ClientId, Currency};
+use crate::domain::{HistoryEntry, Ledger};
+use crate::writer::{aligned_lines, Align};
+
+const COLUMNS: [(&str, Align); 8] = [
+    ("tx", Align::Right),
+    ("activity", Align::Left),
+    ("currency", Align::Left),
+    ("available_change", Align::Right),
+    ("held_change", Align::Right),
+    ("available", Align::Right),
+    ("held", Align::Right),
+    ("total", Align::Right),
+];
+
+/// A client's activity with its balances before and after, see [`Statement::new`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    pub client: ClientId,
+    /// Balances per currency, `None` being the default currency.
+    pub opening: BTreeMap<Option<Currency>, Balance>,
+    pub entries: Vec<HistoryEntry>,
+    pub closing: BTreeMap<Option<Currency>, Balance>,
+}
+
+impl Statement {
+    /// Current balances of `client_id`; the default currency is always included.
+    pub fn balances(ledger: &Ledger, client_id: ClientId) -> BTreeMap<Option<Currency>, Balance> {
+        let mut balances = BTreeMap::from([(None, Balance::default())]);
+        if let Some(account) = ledger.get_account(client_id) {
+            balances.insert(None, account.balance(None));
+            for (currency, balance) in &account.currencies {
+                balances.insert(Some(*currency), *balance);
+            }
+        }
+        balances
+    }
+
+    /// Collects the history of `client_id` from a ledger built with
+    /// [`Ledger::with_history`]; `opening` are its [`Statement::balances`] from when
+    /// the history was enabled.
+    pub fn new(
+        ledger: &Ledger,
+        client_id: ClientId,
+        mut opening: BTreeMap<Option<Currency>, Balance>,
+    ) -> Self {
+        let closing = Self::balances(ledger, client_id);
+        // Currencies first used during the statement open at zero
+        for currency in closing.keys() {
+            opening.entry(*currency).or_default();
+        }
+        Statement {
+            client: client_id,
+            opening,
+            entries: ledger.history(client_id).to_vec(),
+            closing,
+        }
+    }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Statement for client {}", self.client)?;
+        write_balances(f, "Opening", &self.opening)?;
+        if self.entries.is_empty() {
+            writeln!(f, "No activity")?;
+        } else {
+            write_entries(f, &self.entries)?;
+        }
+        write_balances(f, "Closing", &self.closing)
+    }
+}
+
+fn write_balances(
+    f: &mut fmt::Formatter<'_>,
+    title: &str,
+    balances: &BTreeMap<Option<Currency>, Balance>,
+) -> fmt::Result {
+    for (currency, balance) in balances {
+        write!(f, "{}", title)?;
+        if let Some(currency) = currency {
+            write!(f, " {}", currency)?;
+        }
+        writeln!(
+            f,
+            ": available {}, held {}, total {}",
+            balance.available,
+            balance.held,
+            balance.total()
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes the entries as a table, numbers right-aligned like
+/// [`write_table`](crate::writer::write_table).
+fn write_entries(f: &mut fmt::Formatter<'_>, entries: &[HistoryEntry]) -> fmt::Result {
+    let rows: Vec<[String; 8]> = entries
+        .iter()
+        .map(|entry| {
+            [
+                entry.tx.to_string(),
+                entry.activity.to_string(),
+                entry.currency.map(|c| c.to_string()).unwrap_or_default(),
+                entry.change.available.to_string(),
+                entry.change.held.to_string(),
+                entry.balance.available.to_string(),
+                entry.balance.held.to_string(),
+                entry.balance.total().to_string(),
+            ]
+        })
+        .collect();
+    for line in aligned_lines(&COLUMNS, &rows) {
+        writeln!(f, "{}", line)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::types::{Amount, Transaction, TransactionId, TransactionType};
+
+    fn amount(s: &str) -> Amount {
+        Amount::from_str_rounded(s).expect("failed to parse amount")
+    }
+
+    fn deposit(client: u16, tx: u32, value: &str, currency: Option<&str>) -> Transaction {
+        Transaction {
+            tx_type: TransactionType::Deposit,
+            client_id: ClientId(client),
+            tx_id: TransactionId(tx),
+            amount: Some(amount(value)),
+            destination: None,
+            currency: currency.map(|c| c.parse().expect("failed to parse currency")),
+            timestamp: None,
+            reason: None,
+        }
+    }
+
+    #[test]
+    fn test_statement_opens_at_enabled_history() {
+        let mut ledger = Ledger::new();
+        ledger
+            .process_transaction(&deposit(1, 1, "100", None))
+            .expect("transaction should be accepted");
+
+        let opening = Statement::balances(&ledger, ClientId(1));
+        let mut ledger = ledger.with_history();
+        ledger
+            .process_transaction(&deposit(1, 2, "5", Some("EUR")))
+            .expect("transaction should be accepted");
+        ledger
+            .process(
+                TransactionType::Withdrawal,
+                ClientId(1),
+                TransactionId(3),
+                Some(amount("40")),
+            )
+            .expect("transaction should be accepted");
+
+        let statement = Statement::new(&ledger, ClientId(1), opening);
+        assert_eq!(
+            statement.to_string(),
+            "Statement for client 1
+Opening: available 100.0000, held 0.0000, total 100.0000
+Opening EUR: available 0.0000, held 0.0000, total 0.0000
+tx  activity    currency  available_change  held_change  available    held    total
+ 2  deposit     EUR                 5.0000       0.0000     5.0000  0.0000   5.0000
+ 3  withdrawal                    -40.0000       0.0000    60.0000  0.0000  60.0000
+Closing: available 60.0000, held 0.0000, total 60.0000
+Closing EUR: available 5.0000, held 0.0000, total 5.0000
+"
+        );
+    }
+
+    #[test]
+    fn test_statement_without_activity() {
+        let ledger = Ledger::new().with_history();
+        let statement = Statement::new(&ledger, ClientId(7), BTreeMap::new());
+        assert_eq!(
+            statement.to_string(),
+            "Statement for client 7
+Opening: available 0.0000, held 0.0000, total 0.0000
+No activity
+Closing: available 0.0000, held 0.0000, total 0.0000
+"
+        );
+    }
+}
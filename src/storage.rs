@@ -0,0 +1,464 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use rust_decimal::Decimal;
+
+use crate::domain::ledger::StoredTransaction;
+use crate::domain::storage::Storage;
+use crate::domain::types::{
+    Amount, ClientId, Timestamp, TransactionId, TransactionKind, TransactionState,
+};
+use crate::domain::Account;
+
+/// Bytes per tx id in the records file.
+const RECORD_SIZE: usize = 96;
+
+const PROCESSED: u8 = 1;
+const STORED: u8 = 1 << 1;
+const HAS_DESTINATION: u8 = 1 << 2;
+const HAS_CURRENCY: u8 = 1 << 3;
+const HAS_TIMESTAMP: u8 = 1 << 4;
+const HAS_DISPUTED_AT: u8 = 1 << 5;
+
+/// Keeps stored transactions and processed tx ids in files, so memory use does not
+/// grow with the number of transactions
+///
+/// Every tx id owns a fixed-size record at `tx_id * 96` in the records file, so a
+/// lookup is a single read without any index in memory. Only the pages holding used
+/// records take up disk space, which relies on sparse file support (ext4, XFS, APFS,
+/// ...). A second file lists the used tx ids in order of first use, for snapshots.
+///
+/// Both files are scratch space and truncated when opened; snapshots keep the state.
+#[derive(Debug)]
+pub struct DiskStorage {
+    accounts: BTreeMap<ClientId, Account>,
+    records: File,
+    /// Flushed before the list is read back, and by `BufWriter` when dropped.
+    ids: RefCell<BufWriter<File>>,
+    ids_path: PathBuf,
+}
+
+impl DiskStorage {
+    /// Creates the records file at `path` and the tx id list next to it, named like
+    /// `path` with `.ids` appended.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let open = |path: &Path| {
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)
+        };
+        let ids_path = ids_path(path);
+        Ok(DiskStorage {
+            accounts: BTreeMap::new(),
+            records: open(path)?,
+            ids: RefCell::new(BufWriter::new(open(&ids_path)?)),
+            ids_path,
+        })
+    }
+
+    fn read_record(&self, tx_id: TransactionId) -> io::Result<[u8; RECORD_SIZE]> {
+        let mut record = [0; RECORD_SIZE];
+        let mut file = &self.records;
+        file.seek(SeekFrom::Start(offset(tx_id)))
+            .and_then(|_| read_up_to(&mut file, &mut record))
+            .map_err(|e| context(e, format!("failed to read tx {} from disk storage", tx_id)))?;
+        Ok(record)
+    }
+
+    fn write_record(
+        &mut self,
+        tx_id: TransactionId,
+        record: &[u8; RECORD_SIZE],
+        new: bool,
+    ) -> io::Result<()> {
+        self.records
+            .seek(SeekFrom::Start(offset(tx_id)))
+            .and_then(|_| self.records.write_all(record))
+            .and_then(|_| match new {
+                true => self.ids.get_mut().write_all(&tx_id.0.to_le_bytes()),
+                false => Ok(()),
+            })
+            .map_err(|e| context(e, format!("failed to write tx {} to disk storage", tx_id)))
+    }
+
+    /// Used tx ids with their records, in order of first use; the ids end at the first
+    /// error reading them.
+    fn records(&self) -> impl Iterator<Item = io::Result<(TransactionId, [u8; RECORD_SIZE])>> + '_ {
+        let mut ids = Some(
+            self.ids
+                .borrow_mut()
+                .flush()
+                .and_then(|_| File::open(&self.ids_path))
+                .map(BufReader::new),
+        );
+        std::iter::from_fn(move || {
+            let mut reader = match ids.take()? {
+                Ok(reader) => reader,
+                Err(e) => {
+                    let message = format!(
+                        "failed to open '{}' of disk storage",
+                        self.ids_path.display()
+                    );
+                    return Some(Err(context(e, message)));
+                }
+            };
+            let mut bytes = [0; 4];
+            match reader.read_exact(&mut bytes) {
+                Ok(()) => {
+                    ids = Some(Ok(reader));
+                    Some(Ok(TransactionId(u32::from_le_bytes(bytes))))
+                }
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => None,
+                Err(e) => Some(Err(context(
+                    e,
+                    "failed to read tx ids of disk storage".to_string(),
+                ))),
+            }
+        })
+        .map(|tx_id| {
+            let tx_id = tx_id?;
+            Ok((tx_id, self.read_record(tx_id)?))
+        })
+    }
+}
+
+impl Storage for DiskStorage {
+    fn accounts(&self) -> &BTreeMap<ClientId, Account> {
+        &self.accounts
+    }
+
+    fn accounts_mut(&mut self) -> &mut BTreeMap<ClientId, Account> {
+        &mut self.accounts
+    }
+
+    fn transaction(&self, tx_id: TransactionId) -> io::Result<Option<StoredTransaction>> {
+        Ok(decode(&self.read_record(tx_id)?))
+    }
+
+    fn insert_transaction(
+        &mut self,
+        tx_id: TransactionId,
+        transaction: StoredTransaction,
+    ) -> io::Result<()> {
+        let flags = self.read_record(tx_id)?[0];
+        let record = encode(&transaction, flags & PROCESSED);
+        self.write_record(tx_id, &record, flags == 0)
+    }
+
+    fn transactions(
+        &self,
+    ) -> Box<dyn Iterator<Item = io::Result<(TransactionId, StoredTransaction)>> + '_> {
+        Box::new(self.records().filter_map(|result| match result {
+            Ok((tx_id, record)) => Some(Ok((tx_id, decode(&record)?))),
+            Err(e) => Some(Err(e)),
+        }))
+    }
+
+    fn is_processed(&self, tx_id: TransactionId) -> io::Result<bool> {
+        Ok(self.read_record(tx_id)?[0] & PROCESSED != 0)
+    }
+
+    fn mark_processed(&mut self, tx_id: TransactionId) -> io::Result<()> {
+        let mut record = self.read_record(tx_id)?;
+        let new = record[0] == 0;
+        record[0] |= PROCESSED;
+        self.write_record(tx_id, &record, new)
+    }
+
+    fn processed_tx_ids(&self) -> Box<dyn Iterator<Item = io::Result<TransactionId>> + '_> {
+        Box::new(self.records().filter_map(|result| match result {
+            Ok((tx_id, record)) => (record[0] & PROCESSED != 0).then_some(Ok(tx_id)),
+            Err(e) => Some(Err(e)),
+        }))
+    }
+}
+
+fn offset(tx_id: TransactionId) -> u64 {
+    u64::from(tx_id.0) * RECORD_SIZE as u64
+}
+
+/// Fills `buf` from `reader`, leaving zeros past the end of the file.
+fn read_up_to(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<()> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// `error` with `message` in front, keeping its kind.
+fn context(error: io::Error, message: String) -> io::Error {
+    io::Error::new(error.kind(), format!("{}: {}", message, error))
+}
+
+/// The tx id list of the records file at `path`, e.g. `ledger.db.ids` for `ledger.db`.
+fn ids_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".ids");
+    PathBuf::from(name)
+}
+
+/// Layout: flags, kind, state, padding, client (2), destination (2), currency (3),
+/// padding, timestamp (8), disputed_at (8), amount, disputed, settled and charged_back
+/// (16 each), padding.
+fn encode(stored: &StoredTransaction, flags: u8) -> [u8; RECORD_SIZE] {
+    let mut record = [0; RECORD_SIZE];
+    let mut flags = flags | STORED;
+    record[1] = match stored.kind {
+        TransactionKind::Deposit => 0,
+        TransactionKind::Withdrawal => 1,
+        TransactionKind::Transfer => 2,
+    };
+    record[2] = match stored.state {
+        TransactionState::None => 0,
+        TransactionState::Disputed => 1,
+        TransactionState::Resolved => 2,
+        TransactionState::ChargedBack => 3,
+    };
+    record[4..6].copy_from_slice(&stored.client_id.0.to_le_bytes());
+    if let Some(destination) = stored.destination {
+        flags |= HAS_DESTINATION;
+        record[6..8].copy_from_slice(&destination.0.to_le_bytes());
+    }
+    if let Some(currency) = stored.currency {
+        flags |= HAS_CURRENCY;
+        record[8..11].copy_from_slice(currency.as_str().as_bytes());
+    }
+    if let Some(timestamp) = stored.timestamp {
+        flags |= HAS_TIMESTAMP;
+        record[12..20].copy_from_slice(&timestamp.0.to_le_bytes());
+    }
+    if let Some(disputed_at) = stored.disputed_at {
+        flags |= HAS_DISPUTED_AT;
+        record[20..28].copy_from_slice(&disputed_at.0.to_le_bytes());
+    }
+    record[28..44].copy_from_slice(&stored.amount.0.serialize());
+    record[44..60].copy_from_slice(&stored.disputed.0.serialize());
+    record[60..76].copy_from_slice(&stored.settled.0.serialize());
+    record[76..92].copy_from_slice(&stored.charged_back.0.serialize());
+    record[0] = flags;
+    record
+}
+
+/// The stored transaction of a record written by [`encode`], if it holds one.
+fn decode(record: &[u8; RECORD_SIZE]) -> Option<StoredTransaction> {
+    let flags = record[0];
+    if flags & STORED == 0 {
+        return None;
+    }
+    let u16_at = |at: usize| u16::from_le_bytes([record[at], record[at + 1]]);
+    let u64_at = |at: usize| {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&record[at..at + 8]);
+        u64::from_le_bytes(bytes)
+    };
+    let amount_at = |at: usize| {
+        let mut bytes = [0; 16];
+        bytes.copy_from_slice(&record[at..at + 16]);
+        Amount(Decimal::deserialize(bytes))
+    };
+    let present = |flag: u8| flags & flag != 0;
+
+    let kind = match record[1] {
+        0 => TransactionKind::Deposit,
+        1 => TransactionKind::Withdrawal,
+        _ => TransactionKind::Transfer,
+    };
+    let state = match record[2] {
+        0 => TransactionState::None,
+        1 => TransactionState::Disputed,
+        2 => TransactionState::Resolved,
+        _ => TransactionState::ChargedBack,
+    };
+    let currency = present(HAS_CURRENCY)
+        .then(|| std::str::from_utf8(&record[8..11]).ok()?.parse().ok())
+        .flatten();
+
+    let mut stored = StoredTransaction::new(ClientId(u16_at(4)), kind, amount_at(28), currency);
+    stored.state = state;
+    stored.disputed = amount_at(44);
+    stored.settled = amount_at(60);
+    stored.charged_back = amount_at(76);
+    stored.destination = present(HAS_DESTINATION).then(|| ClientId(u16_at(6)));
+    stored.timestamp = present(HAS_TIMESTAMP).then(|| Timestamp(u64_at(12)));
+    stored.disputed_at = present(HAS_DISPUTED_AT).then(|| Timestamp(u64_at(20)));
+    Some(stored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::types::TransactionType;
+    use crate::domain::{Ledger, SnapshotError};
+    use crate::snapshot::{read_snapshot, write_snapshot};
+
+    fn amount(s: &str) -> Amount {
+        Amount::from_str_rounded(s).expect("failed to parse amount")
+    }
+
+    fn scratch_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ledger-{}-{}.records", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_record_round_trip() {
+        let mut stored = StoredTransaction::new(
+            ClientId(65535),
+            TransactionKind::Transfer,
+            amount("1234567.8901"),
+            "EUR".parse().ok(),
+        );
+        stored.state = TransactionState::Disputed;
+        stored.disputed = amount("0.0001");
+        stored.settled = amount("-3");
+        stored.charged_back = amount("2.5");
+        stored.destination = Some(ClientId(7));
+        stored.timestamp = Some(Timestamp(1_700_000_000));
+        stored.disputed_at = Some(Timestamp(u64::MAX));
+
+        let record = encode(&stored, PROCESSED);
+        assert_eq!(record[0] & PROCESSED, PROCESSED);
+        assert_eq!(decode(&record), Some(stored));
+
+        let plain =
+            StoredTransaction::new(ClientId(1), TransactionKind::Deposit, amount("5"), None);
+        assert_eq!(decode(&encode(&plain, 0)), Some(plain));
+        assert_eq!(decode(&[0; RECORD_SIZE]), None);
+    }
+
+    #[test]
+    fn test_disk_storage_matches_memory() {
+        let path = scratch_path("matches-memory");
+        let storage = DiskStorage::create(&path).expect("failed to create disk storage");
+        let mut on_disk = Ledger::new()
+            .with_storage(Box::new(storage))
+            .expect("failed to move to disk storage");
+        let mut in_memory = Ledger::new();
+
+        for ledger in [&mut on_disk, &mut in_memory] {
+            for (tx_type, client, tx, value) in [
+                (TransactionType::Deposit, 1, 4_000_000_000, Some("100")),
+                (TransactionType::Deposit, 2, 2, Some("50")),
+                (TransactionType::Withdrawal, 1, 3, Some("20")),
+                (TransactionType::Dispute, 1, 4_000_000_000, Some("30")),
+                (TransactionType::Deposit, 2, 2, Some("50")),
+                (TransactionType::Chargeback, 1, 4_000_000_000, None),
+            ] {
+                let _ = ledger.process(
+                    tx_type,
+                    ClientId(client),
+                    TransactionId(tx),
+                    value.map(amount),
+                );
+            }
+        }
+
+        assert_eq!(
+            on_disk.snapshot().expect("failed to take snapshot"),
+            in_memory.snapshot().expect("failed to take snapshot")
+        );
+        let mut written = Vec::new();
+        write_snapshot(&mut written, &on_disk).expect("failed to write snapshot");
+        assert_eq!(
+            read_snapshot(written.as_slice())
+                .expect("failed to read snapshot")
+                .snapshot()
+                .expect("failed to take snapshot"),
+            in_memory.snapshot().expect("failed to take snapshot")
+        );
+        assert_eq!(
+            on_disk
+                .get_transaction(TransactionId(4_000_000_000))
+                .expect("failed to read storage")
+                .map(|t| t.state),
+            Some(TransactionState::ChargedBack)
+        );
+        assert_eq!(
+            on_disk.process(
+                TransactionType::Deposit,
+                ClientId(2),
+                TransactionId(2),
+                Some(amount("1"))
+            ),
+            Err(crate::domain::RejectionReason::DuplicateTransaction(
+                TransactionId(2)
+            ))
+        );
+
+        drop(on_disk);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(ids_path(&path));
+    }
+
+    #[test]
+    fn test_with_storage_moves_existing_state() {
+        let path = scratch_path("moves-state");
+        let mut ledger = Ledger::new();
+        ledger
+            .process(
+                TransactionType::Deposit,
+                ClientId(1),
+                TransactionId(1),
+                Some(amount("10")),
+            )
+            .expect("transaction should be accepted");
+        let before = ledger.snapshot().expect("failed to take snapshot");
+
+        let storage = DiskStorage::create(&path).expect("failed to create disk storage");
+        let ledger = ledger
+            .with_storage(Box::new(storage))
+            .expect("failed to move to disk storage");
+        assert_eq!(ledger.snapshot().expect("failed to take snapshot"), before);
+        assert!(ids_path(&path).exists());
+        assert_eq!(
+            ids_path(Path::new("ledger.db")),
+            PathBuf::from("ledger.db.ids")
+        );
+
+        drop(ledger);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(ids_path(&path));
+    }
+
+    #[test]
+    fn test_missing_ids_file_is_an_error() {
+        let path = scratch_path("missing-ids");
+        let storage = DiskStorage::create(&path).expect("failed to create disk storage");
+        let mut ledger = Ledger::new()
+            .with_storage(Box::new(storage))
+            .expect("failed to move to disk storage");
+        ledger
+            .process(
+                TransactionType::Deposit,
+                ClientId(1),
+                TransactionId(1),
+                Some(amount("10")),
+            )
+            .expect("transaction should be accepted");
+
+        std::fs::remove_file(ids_path(&path)).expect("failed to remove the tx id list");
+        let error = ledger.snapshot().expect_err("snapshot should fail");
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(error.to_string().starts_with("failed to open '"));
+        match write_snapshot(io::sink(), &ledger) {
+            Err(SnapshotError::Storage(message)) => {
+                assert!(message.starts_with("failed to open '"))
+            }
+            other => panic!("expected a storage error, got {:?}", other),
+        }
+
+        drop(ledger);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+
+use crate::domain::types::{ClientId, TransactionType};
+use crate::domain::{Ledger, RejectionReason};
+use crate::parser::{InputRecord, ParseError};
+
+/// Lines listed per category in the summary; the rest are only counted.
+const MAX_LISTED_LINES: usize = 10;
+
+/// Outcome of a dry run over an input, see [`validate`]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ValidationSummary {
+    /// Well-formed rows, whether the ledger accepted them or not.
+    pub rows_by_type: BTreeMap<TransactionType, usize>,
+    /// Lines of malformed rows per parse error category.
+    pub parse_errors: BTreeMap<&'static str, Vec<usize>>,
+    /// Lines of rows refused by the ledger per rejection category.
+    pub rejections: BTreeMap<&'static str, Vec<usize>>,
+    pub locked_accounts: Vec<ClientId>,
+    /// Accounts with a negative available balance in any currency.
+    pub negative_accounts: Vec<ClientId>,
+}
+
+impl ValidationSummary {
+    /// Whether every line would be applied.
+    pub fn is_clean(&self) -> bool {
+        self.parse_errors.is_empty() && self.rejections.is_empty()
+    }
+
+    pub fn dropped_lines(&self) -> usize {
+        self.parse_errors
+            .values()
+            .chain(self.rejections.values())
+            .map(Vec::len)
+            .sum()
+    }
+}
+
+/// Applies `records` to `ledger`, which is then thrown away, and summarizes what happened.
+///
+/// Fails when the ledger's storage does, as nothing can be said about the rest.
+pub fn validate(
+    records: impl Iterator<Item = Result<InputRecord, ParseError>>,
+    mut ledger: Ledger,
+) -> io::Result<ValidationSummary> {
+    let mut summary = ValidationSummary::default();
+    for result in records {
+        match result {
+            Ok(record) => {
+                *summary.rows_by_type.entry(record.tx_type).or_default() += 1;
+                match ledger.process_transaction(&record.transaction()) {
+                    Ok(_) => {}
+                    Err(RejectionReason::Storage(message)) => {
+                        return Err(io::Error::other(format!(
+                            "line {}: storage failed: {}",
+                            record.line, message
+                        )))
+                    }
+                    Err(reason) => summary
+                        .rejections
+                        .entry(reason.category())
+                        .or_default()
+                        .push(record.line),
+                }
+            }
+            Err(e) => summary
+                .parse_errors
+                .entry(e.kind.category())
+                .or_default()
+                .push(e.line),
+        }
+    }
+
+    for (client_id, account) in ledger.accounts() {
+        if account.locked {
+            summary.locked_accounts.push(*client_id);
+        }
+        let negative = account.available.is_negative()
+            || account
+                .currencies
+                .values()
+                .any(|balance| balance.available.is_negative());
+        if negative {
+            summary.negative_accounts.push(*client_id);
+        }
+    }
+    Ok(summary)
+}
+
+impl fmt::Display for ValidationSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Rows: {}", self.rows_by_type.values().sum::<usize>())?;
+        for (tx_type, count) in &self.rows_by_type {
+            writeln!(f, "  {}: {}", tx_type, count)?;
+        }
+        write_lines(f, "Parse errors", &self.parse_errors)?;
+        write_lines(f, "Ledger rejections", &self.rejections)?;
+        write_clients(f, "Accounts that would be locked", &self.locked_accounts)?;
+        write_clients(
+            f,
+            "Accounts that would go negative",
+            &self.negative_accounts,
+        )
+    }
+}
+
+fn write_lines(
+    f: &mut fmt::Formatter<'_>,
+    title: &str,
+    lines_by_category: &BTreeMap<&'static str, Vec<usize>>,
+) -> fmt::Result {
+    let total: usize = lines_by_category.values().map(Vec::len).sum();
+    writeln!(f, "{}: {}", title, total)?;
+    for (category, lines) in lines_by_category {
+        let listed: Vec<_> = lines
+            .iter()
+            .take(MAX_LISTED_LINES)
+            .map(ToString::to_string)
+            .collect();
+        let more = if lines.len() > MAX_LISTED_LINES {
+            ", ..."
+        } else {
+            ""
+        };
+        let noun = if lines.len() == 1 { "line" } else { "lines" };
+        writeln!(
+            f,
+            "  {}: {} ({} {}{})",
+            category,
+            lines.len(),
+            noun,
+            listed.join(", "),
+            more
+        )?;
+    }
+    Ok(())
+}
+
+fn write_clients(f: &mut fmt::Formatter<'_>, title: &str, clients: &[ClientId]) -> fmt::Result {
+    write!(f, "{}: {}", title, clients.len())?;
+    if !clients.is_empty() {
+        let clients: Vec<_> = clients.iter().map(ToString::to_string).collect();
+        write!(f, " (client {})", clients.join(", "))?;
+    }
+    writeln!(f)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::CsvParser;
+    use std::io::Cursor;
+
+    fn validate_csv(input: &str) -> ValidationSummary {
+        let parser = CsvParser::new(Cursor::new(input)).expect("failed to create parser");
+        validate(parser, Ledger::new()).expect("memory storage should not fail")
+    }
+
+    #[test]
+    fn test_summary_counts() {
+        let summary = validate_csv(
+            "type,client,tx,amount
+deposit,1,1,100
+deposit,2,2,50
+withdrawal,1,3,20
+dispute,2,2,
+chargeback,2,2,
+withdrawal,2,4,10
+deposit,3,5,abc
+dispute,1,1,
+withdrawal,1,6,90
+",
+        );
+        assert_eq!(
+            summary.rows_by_type,
+            BTreeMap::from([
+                (TransactionType::Deposit, 2),
+                (TransactionType::Withdrawal, 3),
+                (TransactionType::Dispute, 2),
+                (TransactionType::Chargeback, 1),
+            ])
+        );
+        assert_eq!(
+            summary.parse_errors,
+            BTreeMap::from([("invalid_amount", vec![8])])
+        );
+        assert_eq!(
+            summary.rejections,
+            BTreeMap::from([
+                ("account_locked", vec![7]),
+                ("insufficient_funds", vec![10])
+            ])
+        );
+        assert_eq!(summary.locked_accounts, vec![ClientId(2)]);
+        assert_eq!(summary.negative_accounts, vec![ClientId(1)]);
+        assert_eq!(summary.dropped_lines(), 3);
+        assert!(!summary.is_clean());
+    }
+
+    #[test]
+    fn test_summary_display() {
+        let summary = validate_csv("type,client,tx,amount\ndeposit,1,1,100\ndeposit,1,1,100\n");
+        assert_eq!(
+            summary.to_string(),
+            "Rows: 2
+  deposit: 2
+Parse errors: 0
+Ledger rejections: 1
+  duplicate_tx: 1 (line 3)
+Accounts that would be locked: 0
+Accounts that would go negative: 0
+"
+        );
+    }
+
+    #[test]
+    fn test_clean_input() {
+        let summary = validate_csv("type,client,tx,amount\ndeposit,1,1,100\n");
+        assert!(summary.is_clean());
+        assert_eq!(summary.dropped_lines(), 0);
+    }
+}
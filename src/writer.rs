@@ -1,7 +1,72 @@
+use std::cmp::Ordering;
 use std::io::Write;
+use std::str::FromStr;
 
-use crate::domain::types::{Amount, ClientId};
-use crate::domain::Account;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+use crate::domain::types::{
+    Amount, ClientId, Currency, LockReason, TransactionId, TransactionType,
+};
+use crate::domain::{Account, LedgerEvent};
+
+/// How [`aligned_lines`] pads a column
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Align {
+    Left,
+    Right,
+}
+
+/// Output columns, each with the feature it is left out without, see [`Feature`].
+const COLUMNS: [(&str, Align, Option<Feature>); 9] = [
+    ("client", Align::Right, None),
+    ("available", Align::Right, None),
+    ("held", Align::Right, None),
+    ("total", Align::Right, None),
+    ("locked", Align::Left, None),
+    ("lock_reason", Align::Left, Some(Feature::LockReasons)),
+    ("currency", Align::Left, Some(Feature::Currencies)),
+    (
+        "overdraft_limit",
+        Align::Right,
+        Some(Feature::OverdraftLimits),
+    ),
+    ("headroom", Align::Right, Some(Feature::OverdraftLimits)),
+];
+
+/// What the optional columns show; CSV and table output only have them once a record
+/// uses the feature, so inputs without it keep the original
+/// `client,available,held,total,locked` columns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Feature {
+    LockReasons,
+    Currencies,
+    OverdraftLimits,
+}
+
+impl Feature {
+    fn used_by(self, record: &OutputRecord) -> bool {
+        match self {
+            // Chargebacks locked accounts before there were other reasons
+            Feature::LockReasons => record
+                .lock_reason
+                .is_some_and(|reason| reason != LockReason::CHARGEBACK),
+            Feature::Currencies => record.currency.is_some(),
+            Feature::OverdraftLimits => !record.overdraft_limit.is_zero(),
+        }
+    }
+}
+
+/// Indices into [`COLUMNS`] of the columns written for `records`.
+fn used_columns(records: &[OutputRecord]) -> Vec<usize> {
+    COLUMNS
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, _, feature))| {
+            feature.is_none_or(|feature| records.iter().any(|record| feature.used_by(record)))
+        })
+        .map(|(index, _)| index)
+        .collect()
+}
 
 pub struct OutputRecord {
     pub client: ClientId,
@@ -9,40 +74,428 @@ pub struct OutputRecord {
     pub held: Amount,
     pub total: Amount,
     pub locked: bool,
+    pub lock_reason: Option<LockReason>,
+    /// `None` for the default currency, written as an empty column.
+    pub currency: Option<Currency>,
+    pub overdraft_limit: Amount,
+    /// What can still be withdrawn, see [`Account::headroom`].
+    pub headroom: Amount,
 }
 
 impl OutputRecord {
+    /// Record of the account's default-currency balance.
     pub fn from_account(client: ClientId, account: &Account) -> Self {
+        Self::from_balance(client, account, None)
+    }
+
+    /// One record per currency held by the account, default currency first.
+    ///
+    /// The default balance is left out when it is empty and the account holds other currencies.
+    pub fn from_account_balances(client: ClientId, account: &Account) -> Vec<Self> {
+        let default_unused =
+            account.available.is_zero() && account.held.is_zero() && !account.currencies.is_empty();
+        let default = (!default_unused).then_some(None);
+        default
+            .into_iter()
+            .chain(account.currencies.keys().map(|currency| Some(*currency)))
+            .map(|currency| Self::from_balance(client, account, currency))
+            .collect()
+    }
+
+    /// Values of every column, in [`COLUMNS`] order.
+    fn fields(&self) -> [String; 9] {
+        [
+            self.client.to_string(),
+            self.available.to_string(),
+            self.held.to_string(),
+            self.total.to_string(),
+            self.locked.to_string(),
+            self.lock_reason
+                .map_or_else(String::new, |reason| reason.to_string()),
+            self.currency
+                .as_ref()
+                .map_or_else(String::new, ToString::to_string),
+            self.overdraft_limit.to_string(),
+            self.headroom.to_string(),
+        ]
+    }
+
+    fn from_balance(client: ClientId, account: &Account, currency: Option<Currency>) -> Self {
+        let balance = account.balance(currency);
         OutputRecord {
             client,
-            available: account.available,
-            held: account.held,
-            total: account.total(),
+            available: balance.available,
+            held: balance.held,
+            total: balance.total(),
             locked: account.locked,
+            lock_reason: account.lock_reason,
+            currency,
+            overdraft_limit: account.overdraft_limit(currency),
+            headroom: account.headroom(currency),
+        }
+    }
+}
+
+/// Fields named after the CSV columns, with amounts as 4-decimal strings.
+impl Serialize for OutputRecord {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut record = serializer.serialize_struct("OutputRecord", COLUMNS.len())?;
+        record.serialize_field("client", &self.client)?;
+        record.serialize_field("available", &self.available.to_string())?;
+        record.serialize_field("held", &self.held.to_string())?;
+        record.serialize_field("total", &self.total.to_string())?;
+        record.serialize_field("locked", &self.locked)?;
+        record.serialize_field("lock_reason", &self.lock_reason)?;
+        record.serialize_field("currency", &self.currency)?;
+        record.serialize_field("overdraft_limit", &self.overdraft_limit.to_string())?;
+        record.serialize_field("headroom", &self.headroom.to_string())?;
+        record.end()
+    }
+}
+
+/// Format of the accounts output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    /// A JSON array with one object per record.
+    Json,
+    /// One JSON object per line.
+    Ndjson,
+    /// Space-aligned columns for reading in a terminal.
+    Table,
+}
+
+impl OutputFormat {
+    pub fn write<W: Write>(
+        self,
+        writer: &mut W,
+        records: impl Iterator<Item = OutputRecord>,
+    ) -> std::io::Result<()> {
+        match self {
+            OutputFormat::Csv => write_csv(writer, records),
+            OutputFormat::Json => write_json(writer, records),
+            OutputFormat::Ndjson => write_ndjson(writer, records),
+            OutputFormat::Table => write_table(writer, records),
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "ndjson" | "jsonl" => Ok(OutputFormat::Ndjson),
+            "table" => Ok(OutputFormat::Table),
+            _ => Err(format!("Unknown output format: '{}'", s)),
+        }
+    }
+}
+
+/// Output column records can be sorted by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortColumn {
+    Client,
+    Available,
+    Held,
+    Total,
+    Locked,
+    LockReason,
+    Currency,
+    OverdraftLimit,
+    Headroom,
+}
+
+impl SortColumn {
+    fn compare(self, a: &OutputRecord, b: &OutputRecord) -> Ordering {
+        match self {
+            SortColumn::Client => a.client.cmp(&b.client),
+            SortColumn::Available => a.available.cmp(&b.available),
+            SortColumn::Held => a.held.cmp(&b.held),
+            SortColumn::Total => a.total.cmp(&b.total),
+            SortColumn::Locked => a.locked.cmp(&b.locked),
+            SortColumn::LockReason => a.lock_reason.cmp(&b.lock_reason),
+            SortColumn::Currency => a.currency.cmp(&b.currency),
+            SortColumn::OverdraftLimit => a.overdraft_limit.cmp(&b.overdraft_limit),
+            SortColumn::Headroom => a.headroom.cmp(&b.headroom),
+        }
+    }
+}
+
+impl FromStr for SortColumn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "client" => Ok(SortColumn::Client),
+            "available" => Ok(SortColumn::Available),
+            "held" => Ok(SortColumn::Held),
+            "total" => Ok(SortColumn::Total),
+            "locked" => Ok(SortColumn::Locked),
+            "lock_reason" => Ok(SortColumn::LockReason),
+            "currency" => Ok(SortColumn::Currency),
+            "overdraft_limit" => Ok(SortColumn::OverdraftLimit),
+            "headroom" => Ok(SortColumn::Headroom),
+            _ => Err(format!("Unknown output column: '{}'", s)),
         }
     }
 }
 
+/// Order of output records, parsed from `<column>` or `<column>:<asc|desc>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortOrder {
+    pub column: SortColumn,
+    pub descending: bool,
+}
+
+impl SortOrder {
+    /// Stable sort, so ties keep their client order from [`Ledger::accounts`].
+    ///
+    /// [`Ledger::accounts`]: crate::domain::Ledger::accounts
+    pub fn sort(&self, records: &mut [OutputRecord]) {
+        records.sort_by(|a, b| {
+            let ordering = self.column.compare(a, b);
+            if self.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+    }
+}
+
+impl FromStr for SortOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (column, direction) = s.split_once(':').unwrap_or((s, "asc"));
+        let descending = match direction.to_ascii_lowercase().as_str() {
+            "asc" => false,
+            "desc" => true,
+            _ => return Err(format!("Unknown sort direction: '{}'", direction)),
+        };
+        Ok(SortOrder {
+            column: column.parse()?,
+            descending,
+        })
+    }
+}
+
+/// Writes the columns in use, see [`Feature`]; needs every record before the first line.
 pub fn write_csv<W: Write>(
     writer: &mut W,
     records: impl Iterator<Item = OutputRecord>,
 ) -> std::io::Result<()> {
-    writeln!(writer, "client,available,held,total,locked")?;
+    let records: Vec<_> = records.collect();
+    let columns = used_columns(&records);
+    // Lock reasons are free text, quoted when they hold commas or quotes
+    let mut writer = csv::WriterBuilder::new()
+        .terminator(csv::Terminator::Any(b'\n'))
+        .from_writer(writer);
+    writer.write_record(columns.iter().map(|&column| COLUMNS[column].0))?;
+
+    for record in &records {
+        let fields = record.fields();
+        writer.write_record(columns.iter().map(|&column| fields[column].as_str()))?;
+    }
+
+    writer.flush()
+}
+
+/// Writes a JSON array, one record per line.
+pub fn write_json<W: Write>(
+    writer: &mut W,
+    records: impl Iterator<Item = OutputRecord>,
+) -> std::io::Result<()> {
+    write!(writer, "[")?;
+    let mut empty = true;
+    for record in records {
+        let separator = if empty { "\n" } else { ",\n" };
+        write!(writer, "{}  ", separator)?;
+        serde_json::to_writer(&mut *writer, &record)?;
+        empty = false;
+    }
+    writeln!(writer, "{}", if empty { "]" } else { "\n]" })
+}
 
+pub fn write_ndjson<W: Write>(
+    writer: &mut W,
+    records: impl Iterator<Item = OutputRecord>,
+) -> std::io::Result<()> {
     for record in records {
-        writeln!(
-            writer,
-            "{},{},{},{},{}",
-            record.client, record.available, record.held, record.total, record.locked
-        )?;
+        serde_json::to_writer(&mut *writer, &record)?;
+        writeln!(writer)?;
     }
+    Ok(())
+}
 
+/// Writes aligned columns, amounts right-aligned, with the columns in use like
+/// [`write_csv`]; needs every record before the first line.
+pub fn write_table<W: Write>(
+    writer: &mut W,
+    records: impl Iterator<Item = OutputRecord>,
+) -> std::io::Result<()> {
+    let records: Vec<_> = records.collect();
+    let columns = used_columns(&records);
+    let header: Vec<_> = columns
+        .iter()
+        .map(|&column| (COLUMNS[column].0, COLUMNS[column].1))
+        .collect();
+    let rows: Vec<Vec<_>> = records
+        .iter()
+        .map(|record| {
+            let mut fields = record.fields().map(Some);
+            columns
+                .iter()
+                .filter_map(|&column| fields[column].take())
+                .collect()
+        })
+        .collect();
+    for line in aligned_lines(&header, &rows) {
+        writeln!(writer, "{}", line)?;
+    }
     Ok(())
 }
 
+/// Lays out a header of the column names followed by `rows`, each column padded to
+/// its widest field and separated by two spaces.
+pub(crate) fn aligned_lines<R: AsRef<[String]>>(
+    columns: &[(&str, Align)],
+    rows: &[R],
+) -> Vec<String> {
+    let widths: Vec<_> = columns.iter().map(|(name, _)| name.len()).collect();
+    let widths = rows.iter().fold(widths, |mut widths, row| {
+        for (width, field) in widths.iter_mut().zip(row.as_ref()) {
+            *width = (*width).max(field.len());
+        }
+        widths
+    });
+
+    let header: Vec<_> = columns.iter().map(|(name, _)| name.to_string()).collect();
+    std::iter::once(header.as_slice())
+        .chain(rows.iter().map(AsRef::as_ref))
+        .map(|row| {
+            let cells: Vec<_> = row
+                .iter()
+                .zip(columns.iter().zip(&widths))
+                .map(|(field, ((_, align), &width))| match align {
+                    Align::Left => format!("{:<width$}", field),
+                    Align::Right => format!("{:>width$}", field),
+                })
+                .collect();
+            cells.join("  ").trim_end().to_string()
+        })
+        .collect()
+}
+
+/// A dropped input line, either malformed or refused by the ledger
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RejectionRecord {
+    pub line: usize,
+    pub row: String,
+    pub category: &'static str,
+    pub message: String,
+}
+
+/// Streams rejected lines to a CSV report as they are encountered.
+pub struct RejectionWriter<W: Write> {
+    writer: csv::Writer<W>,
+}
+
+impl<W: Write> RejectionWriter<W> {
+    pub fn new(writer: W) -> std::io::Result<Self> {
+        let mut writer = csv::WriterBuilder::new()
+            .terminator(csv::Terminator::Any(b'\n'))
+            .from_writer(writer);
+        writer.write_record(["line", "row", "category", "message"])?;
+        Ok(RejectionWriter { writer })
+    }
+
+    pub fn write(&mut self, record: &RejectionRecord) -> std::io::Result<()> {
+        self.writer.write_record([
+            record.line.to_string().as_str(),
+            record.row.as_str(),
+            record.category,
+            record.message.as_str(),
+        ])?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// A fee charged for one transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeeRecord {
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub fee_for: TransactionType,
+    pub currency: Option<Currency>,
+    pub fee: Amount,
+}
+
+impl FeeRecord {
+    /// The fee of a [`LedgerEvent::FeeCharged`], `None` for any other event.
+    pub fn from_event(event: &LedgerEvent) -> Option<Self> {
+        match *event {
+            LedgerEvent::FeeCharged {
+                client,
+                tx,
+                fee_for,
+                amount,
+                currency,
+                ..
+            } => Some(FeeRecord {
+                client,
+                tx,
+                fee_for,
+                currency,
+                fee: amount,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Streams charged fees to a CSV report as they are charged.
+pub struct FeeWriter<W: Write> {
+    writer: csv::Writer<W>,
+}
+
+impl<W: Write> FeeWriter<W> {
+    pub fn new(writer: W) -> std::io::Result<Self> {
+        let mut writer = csv::WriterBuilder::new()
+            .terminator(csv::Terminator::Any(b'\n'))
+            .from_writer(writer);
+        writer.write_record(["client", "tx", "type", "currency", "fee"])?;
+        Ok(FeeWriter { writer })
+    }
+
+    pub fn write(&mut self, record: &FeeRecord) -> std::io::Result<()> {
+        self.writer.write_record([
+            record.client.to_string().as_str(),
+            record.tx.to_string().as_str(),
+            record.fee_for.as_str(),
+            record.currency.as_ref().map_or("", Currency::as_str),
+            record.fee.to_string().as_str(),
+        ])?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serde_json::{json, Value};
 
     fn amount(s: &str) -> Amount {
         Amount::from_str_rounded(s).expect("failed to parse amount")
@@ -57,6 +510,10 @@ mod tests {
             held: amount("0"),
             total: amount("1.5"),
             locked: false,
+            lock_reason: None,
+            currency: None,
+            overdraft_limit: amount("0"),
+            headroom: amount("1.5"),
         }];
         write_csv(&mut output, records.into_iter()).expect("failed to write CSV");
         let csv = String::from_utf8(output).expect("output should be valid UTF-8");
@@ -73,6 +530,10 @@ mod tests {
             held: amount("50"),
             total: amount("150"),
             locked: true,
+            lock_reason: Some(LockReason::CHARGEBACK),
+            currency: None,
+            overdraft_limit: amount("0"),
+            headroom: amount("0"),
         }];
         write_csv(&mut output, records.into_iter()).expect("failed to write CSV");
         let csv = String::from_utf8(output).expect("output should be valid UTF-8");
@@ -81,6 +542,41 @@ mod tests {
         assert_eq!(lines[1], "1,100.0000,50.0000,150.0000,true");
     }
 
+    #[test]
+    fn test_optional_columns_written_when_used() {
+        let plain = OutputRecord {
+            client: ClientId(1),
+            available: amount("1"),
+            held: amount("0"),
+            total: amount("1"),
+            locked: false,
+            lock_reason: None,
+            currency: None,
+            overdraft_limit: amount("0"),
+            headroom: amount("1"),
+        };
+        let limited = OutputRecord {
+            client: ClientId(2),
+            available: amount("1"),
+            held: amount("0"),
+            total: amount("1"),
+            locked: false,
+            lock_reason: None,
+            currency: None,
+            overdraft_limit: amount("5"),
+            headroom: amount("6"),
+        };
+        let mut output = Vec::new();
+        write_csv(&mut output, vec![plain, limited].into_iter()).expect("failed to write CSV");
+        let csv = String::from_utf8(output).expect("output should be valid UTF-8");
+        assert_eq!(
+            csv,
+            "client,available,held,total,locked,overdraft_limit,headroom\n\
+             1,1.0000,0.0000,1.0000,false,0.0000,1.0000\n\
+             2,1.0000,0.0000,1.0000,false,5.0000,6.0000\n"
+        );
+    }
+
     #[test]
     fn test_output_boolean_lowercase() {
         let mut output = Vec::new();
@@ -91,6 +587,10 @@ mod tests {
                 held: amount("0"),
                 total: amount("0"),
                 locked: true,
+                lock_reason: Some(LockReason::CHARGEBACK),
+                currency: None,
+                overdraft_limit: amount("0"),
+                headroom: amount("0"),
             },
             OutputRecord {
                 client: ClientId(2),
@@ -98,6 +598,10 @@ mod tests {
                 held: amount("0"),
                 total: amount("0"),
                 locked: false,
+                lock_reason: None,
+                currency: None,
+                overdraft_limit: amount("0"),
+                headroom: amount("0"),
             },
         ];
         write_csv(&mut output, records.into_iter()).expect("failed to write CSV");
@@ -117,6 +621,10 @@ mod tests {
             held: amount("0"),
             total: amount("-80"),
             locked: true,
+            lock_reason: Some(LockReason::CHARGEBACK),
+            currency: None,
+            overdraft_limit: amount("0"),
+            headroom: amount("0"),
         }];
         write_csv(&mut output, records.into_iter()).expect("failed to write CSV");
         let csv = String::from_utf8(output).expect("output should be valid UTF-8");
@@ -135,8 +643,10 @@ mod tests {
     #[test]
     fn test_from_account() {
         let mut account = Account::new();
-        account.deposit(amount("100"));
-        account.hold(amount("30"));
+        account
+            .deposit(None, amount("100"))
+            .expect("deposit should succeed");
+        account.hold(None, amount("30"));
 
         let record = OutputRecord::from_account(ClientId(5), &account);
         assert_eq!(record.client, ClientId(5));
@@ -144,6 +654,35 @@ mod tests {
         assert_eq!(record.held, amount("30"));
         assert_eq!(record.total, amount("100"));
         assert!(!record.locked);
+        assert_eq!(record.lock_reason, None);
+        assert_eq!(record.currency, None);
+        assert_eq!(record.overdraft_limit, amount("0"));
+        assert_eq!(record.headroom, amount("70"));
+    }
+
+    #[test]
+    fn test_one_record_per_currency() {
+        let eur = "EUR".parse().ok();
+        let usd = "USD".parse().ok();
+        let mut account = Account::new();
+        account
+            .deposit(usd, amount("3"))
+            .expect("deposit should succeed");
+        account
+            .deposit(eur, amount("2"))
+            .expect("deposit should succeed");
+        let records = OutputRecord::from_account_balances(ClientId(5), &account);
+        let currencies: Vec<_> = records.iter().map(|r| r.currency).collect();
+        assert_eq!(currencies, vec![eur, usd]);
+        assert_eq!(records[0].available, amount("2"));
+
+        account
+            .deposit(None, amount("1"))
+            .expect("deposit should succeed");
+        let records = OutputRecord::from_account_balances(ClientId(5), &account);
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].currency, None);
+        assert_eq!(records[0].total, amount("1"));
     }
 
     #[test]
@@ -156,6 +695,10 @@ mod tests {
                 held: amount("0"),
                 total: amount("100"),
                 locked: false,
+                lock_reason: None,
+                currency: None,
+                overdraft_limit: amount("0"),
+                headroom: amount("100"),
             },
             OutputRecord {
                 client: ClientId(2),
@@ -163,6 +706,10 @@ mod tests {
                 held: amount("25"),
                 total: amount("75"),
                 locked: true,
+                lock_reason: Some(LockReason::CHARGEBACK),
+                currency: None,
+                overdraft_limit: amount("0"),
+                headroom: amount("0"),
             },
         ];
         write_csv(&mut output, records.into_iter()).expect("failed to write CSV");
@@ -186,6 +733,10 @@ mod tests {
             held: amount("0"),
             total: amount("100"),
             locked: false,
+            lock_reason: None,
+            currency: None,
+            overdraft_limit: amount("0"),
+            headroom: amount("100"),
         }];
         write_csv(&mut output, records.into_iter()).expect("failed to write CSV");
         let csv = String::from_utf8(output).expect("output should be valid UTF-8");
@@ -194,4 +745,170 @@ mod tests {
         // Should contain at least one newline
         assert!(csv.contains('\n'), "Output has no newlines");
     }
+
+    fn sample_records() -> Vec<OutputRecord> {
+        vec![
+            OutputRecord {
+                client: ClientId(1),
+                available: amount("1.5"),
+                held: amount("0"),
+                total: amount("1.5"),
+                locked: false,
+                lock_reason: None,
+                currency: None,
+                overdraft_limit: amount("10"),
+                headroom: amount("11.5"),
+            },
+            OutputRecord {
+                client: ClientId(12),
+                available: amount("-80"),
+                held: amount("100"),
+                total: amount("20"),
+                locked: true,
+                lock_reason: Some(LockReason::ADMINISTRATIVE),
+                currency: "EUR".parse().ok(),
+                overdraft_limit: amount("0"),
+                headroom: amount("0"),
+            },
+        ]
+    }
+
+    fn write_format(format: OutputFormat, records: Vec<OutputRecord>) -> String {
+        let mut output = Vec::new();
+        format
+            .write(&mut output, records.into_iter())
+            .expect("failed to write output");
+        String::from_utf8(output).expect("output should be valid UTF-8")
+    }
+
+    #[test]
+    fn test_json_output_keeps_4_decimals() {
+        let json = write_format(OutputFormat::Json, sample_records());
+        let values: Value = serde_json::from_str(&json).expect("output should be valid JSON");
+        assert_eq!(
+            values[0],
+            json!({
+                "client": 1,
+                "available": "1.5000",
+                "held": "0.0000",
+                "total": "1.5000",
+                "locked": false,
+                "lock_reason": null,
+                "currency": null,
+                "overdraft_limit": "10.0000",
+                "headroom": "11.5000",
+            })
+        );
+        assert_eq!(values[1]["lock_reason"], "administrative");
+        assert_eq!(values[1]["currency"], "EUR");
+        assert_eq!(write_format(OutputFormat::Json, vec![]), "[]\n");
+    }
+
+    #[test]
+    fn test_ndjson_output_one_object_per_line() {
+        let ndjson = write_format(OutputFormat::Ndjson, sample_records());
+        let lines: Vec<_> = ndjson.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let value: Value = serde_json::from_str(lines[1]).expect("line should be valid JSON");
+        assert_eq!(value["available"], "-80.0000");
+    }
+
+    #[test]
+    fn test_table_output_aligns_columns() {
+        let table = write_format(OutputFormat::Table, sample_records());
+        assert_eq!(
+            table,
+            "\
+client  available      held    total  locked  lock_reason     currency  overdraft_limit  headroom
+     1     1.5000    0.0000   1.5000  false                                     10.0000   11.5000
+    12   -80.0000  100.0000  20.0000  true    administrative  EUR                0.0000    0.0000
+"
+        );
+    }
+
+    #[test]
+    fn test_sort_by_column() {
+        let mut records = sample_records();
+        records.push(OutputRecord {
+            client: ClientId(3),
+            ..sample_records().remove(0)
+        });
+
+        let order: SortOrder = "total:desc".parse().expect("failed to parse sort order");
+        order.sort(&mut records);
+        let clients: Vec<_> = records.iter().map(|r| r.client.0).collect();
+        assert_eq!(clients, vec![12, 1, 3]);
+
+        let order: SortOrder = "Client".parse().expect("failed to parse sort order");
+        assert!(!order.descending);
+        order.sort(&mut records);
+        let clients: Vec<_> = records.iter().map(|r| r.client.0).collect();
+        assert_eq!(clients, vec![1, 3, 12]);
+
+        assert!("balance".parse::<SortOrder>().is_err());
+        assert!("total:up".parse::<SortOrder>().is_err());
+    }
+
+    #[test]
+    fn test_output_format_from_str() {
+        assert_eq!("TABLE".parse(), Ok(OutputFormat::Table));
+        assert_eq!("jsonl".parse(), Ok(OutputFormat::Ndjson));
+        assert!("xml".parse::<OutputFormat>().is_err());
+    }
+
+    #[test]
+    fn test_rejection_report_quotes_original_row() {
+        let mut output = Vec::new();
+        {
+            let mut report = RejectionWriter::new(&mut output).expect("failed to write header");
+            report
+                .write(&RejectionRecord {
+                    line: 3,
+                    row: "deposit,1,1,\"1,000\"".to_string(),
+                    category: "invalid_amount",
+                    message: "Invalid amount: '1,000'".to_string(),
+                })
+                .expect("failed to write rejection");
+            report.flush().expect("failed to flush report");
+        }
+        let csv = String::from_utf8(output).expect("output should be valid UTF-8");
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines[0], "line,row,category,message");
+        assert_eq!(
+            lines[1],
+            "3,\"deposit,1,1,\"\"1,000\"\"\",invalid_amount,\"Invalid amount: '1,000'\""
+        );
+    }
+
+    #[test]
+    fn test_rejection_report_header_only_when_empty() {
+        let mut output = Vec::new();
+        RejectionWriter::new(&mut output)
+            .expect("failed to write header")
+            .flush()
+            .expect("failed to flush report");
+        let csv = String::from_utf8(output).expect("output should be valid UTF-8");
+        assert_eq!(csv, "line,row,category,message\n");
+    }
+
+    #[test]
+    fn test_fee_report() {
+        let event = LedgerEvent::FeeCharged {
+            client: ClientId(1),
+            tx: TransactionId(4),
+            fee_for: TransactionType::Withdrawal,
+            house: ClientId(0),
+            amount: amount("0.75"),
+            currency: None,
+        };
+        let mut output = Vec::new();
+        {
+            let mut report = FeeWriter::new(&mut output).expect("failed to write header");
+            let record = FeeRecord::from_event(&event).expect("event should be a fee");
+            report.write(&record).expect("failed to write fee");
+            report.flush().expect("failed to flush report");
+        }
+        let csv = String::from_utf8(output).expect("output should be valid UTF-8");
+        assert_eq!(csv, "client,tx,type,currency,fee\n1,4,withdrawal,,0.7500\n");
+    }
 }
@@ -2,9 +2,15 @@ use std::collections::HashMap;
 use std::io::Cursor;
 
 use simple_rust_ledger::domain::types::{Amount, ClientId};
-use simple_rust_ledger::domain::Ledger;
-use simple_rust_ledger::parser::CsvParser;
-use simple_rust_ledger::writer::{write_csv, OutputRecord};
+use simple_rust_ledger::domain::{BookAccount, Ledger};
+use simple_rust_ledger::fees::read_fee_schedule;
+use simple_rust_ledger::journal::{read_journal, JournalWriter};
+use simple_rust_ledger::parser::{CsvParser, InputFormat};
+use simple_rust_ledger::policy::read_policy;
+use simple_rust_ledger::sharded::ShardedLedger;
+use simple_rust_ledger::statement::Statement;
+use simple_rust_ledger::storage::DiskStorage;
+use simple_rust_ledger::writer::{write_csv, FeeRecord, FeeWriter, OutputRecord};
 
 fn amount(s: &str) -> Amount {
     Amount::from_str_rounded(s).expect("failed to parse amount")
@@ -17,12 +23,8 @@ fn process_csv(input: &str) -> HashMap<ClientId, (Amount, Amount, Amount, bool)>
 
     let mut ledger = Ledger::new();
     for record in parser.flatten() {
-        ledger.process(
-            record.tx_type,
-            record.client_id,
-            record.tx_id,
-            record.amount,
-        );
+        // Rejected transactions are part of several scenarios, so outcomes are ignored
+        let _ = ledger.process_transaction(&record.transaction());
     }
 
     ledger
@@ -48,19 +50,14 @@ fn get_csv_output(input: &str) -> String {
 
     let mut ledger = Ledger::new();
     for record in parser.flatten() {
-        ledger.process(
-            record.tx_type,
-            record.client_id,
-            record.tx_id,
-            record.amount,
-        );
+        let _ = ledger.process_transaction(&record.transaction());
     }
 
     let mut output = Vec::new();
     let records = ledger
         .accounts()
         .iter()
-        .map(|(client_id, account)| OutputRecord::from_account(*client_id, account));
+        .flat_map(|(client_id, account)| OutputRecord::from_account_balances(*client_id, account));
     write_csv(&mut output, records).expect("failed to write CSV output");
 
     String::from_utf8(output).expect("output should be valid UTF-8")
@@ -125,6 +122,108 @@ chargeback,1,1,
     assert!(locked);
 }
 
+#[test]
+fn test_partial_dispute_and_chargeback() {
+    let input = r#"type,client,tx,amount
+deposit,1,1,100.0
+dispute,1,1,30.0
+dispute,1,1,80.0
+dispute,1,1,20.0
+resolve,1,1,10.0
+chargeback,1,1,
+"#;
+    let accounts = process_csv(input);
+
+    let (available, held, total, locked) = &accounts[&ClientId(1)];
+    assert_eq!(*available, amount("60"));
+    assert_eq!(*held, amount("0"));
+    assert_eq!(*total, amount("60"));
+    assert!(locked);
+}
+
+#[test]
+fn test_lock_reason_from_row() {
+    let input = r#"type,client,tx,amount,reason
+deposit,1,1,100.0,
+deposit,2,2,50.0,
+lock,1,3,,"sanctions review, case 12"
+lock,2,4,,
+"#;
+    let output = get_csv_output(input);
+
+    assert!(output.contains("1,100.0000,0.0000,100.0000,true,\"sanctions review, case 12\"\n"));
+    assert!(output.contains("2,50.0000,0.0000,50.0000,true,administrative\n"));
+}
+
+#[test]
+fn test_admin_lock_and_unlock() {
+    let input = r#"type,client,tx,amount
+deposit,1,1,100.0
+deposit,2,2,50.0
+dispute,2,2,
+chargeback,2,2,
+lock,1,3,
+deposit,1,4,10.0
+unlock,2,5,
+deposit,2,6,5.0
+"#;
+    let output = get_csv_output(input);
+
+    assert!(output.contains("1,100.0000,0.0000,100.0000,true,administrative\n"));
+    assert!(output.contains("2,5.0000,0.0000,5.0000,false,\n"));
+}
+
+#[test]
+fn test_transfer_between_clients() {
+    let input = r#"type,client,tx,amount,destination
+deposit,1,1,100.0,
+transfer,1,2,40.0,2
+transfer,1,3,500.0,2
+transfer,2,4,10.0,1
+"#;
+    let accounts = process_csv(input);
+
+    assert_eq!(accounts[&ClientId(1)].0, amount("70"));
+    assert_eq!(accounts[&ClientId(2)].0, amount("30"));
+}
+
+#[test]
+fn test_transfer_chargeback_returns_funds_to_sender() {
+    let input = r#"type,client,tx,amount,destination
+deposit,1,1,100.0,
+transfer,1,2,40.0,2
+dispute,1,2,,
+chargeback,1,2,,
+"#;
+    let accounts = process_csv(input);
+
+    let (available, held, total, locked) = &accounts[&ClientId(1)];
+    assert_eq!(*available, amount("100"));
+    assert_eq!(*held, amount("0"));
+    assert_eq!(*total, amount("100"));
+    assert!(!locked);
+    let (available, held, total, _) = &accounts[&ClientId(2)];
+    assert_eq!(*available, amount("0"));
+    assert_eq!(*held, amount("0"));
+    assert_eq!(*total, amount("0"));
+}
+
+#[test]
+fn test_one_output_row_per_currency() {
+    let input = r#"type,client,tx,amount,currency
+deposit,1,1,100.0,
+deposit,1,2,50.0,eur
+deposit,2,3,7.0,USD
+withdrawal,1,4,20.0,EUR
+"#;
+    let output = get_csv_output(input);
+
+    assert_eq!(output.lines().count(), 4);
+    assert!(output.contains("1,100.0000,0.0000,100.0000,false,\n"));
+    assert!(output.contains("1,30.0000,0.0000,30.0000,false,EUR\n"));
+    assert!(output.contains("2,7.0000,0.0000,7.0000,false,USD\n"));
+}
+
 #[test]
 fn test_negative_balance_from_chargeback() {
     let input = r#"type,client,tx,amount
@@ -465,3 +564,416 @@ deposit,1,2,0.00001
 
     assert_eq!(accounts[&ClientId(1)].0, amount("1.0001"));
 }
+
+#[test]
+fn test_journal_replay_matches_run() {
+    let input = r#"type,client,tx,amount
+deposit,1,1,100.0
+deposit,2,2,50.0
+withdrawal,1,3,25.0
+withdrawal,2,4,500.0
+dispute,2,2,
+chargeback,2,2,
+dispute,1,1,
+"#;
+    let parser = CsvParser::new(Cursor::new(input)).expect("failed to create CSV parser");
+    let mut ledger = Ledger::new().with_event_log();
+    let mut journal_bytes = Vec::new();
+    let mut journal = JournalWriter::new(&mut journal_bytes);
+    for record in parser.flatten() {
+        let _ = ledger.process_transaction(&record.transaction());
+        for event in ledger.drain_events() {
+            journal.append(&event).expect("failed to append event");
+        }
+    }
+
+    let events = read_journal(Cursor::new(journal_bytes))
+        .collect::<Result<Vec<_>, _>>()
+        .expect("journal should decode");
+    let replayed = Ledger::replay(events).expect("journal should replay cleanly");
+
+    assert_eq!(replayed.accounts(), ledger.accounts());
+}
+
+#[test]
+fn test_withdrawal_chargeback_when_enabled() {
+    let input = r#"type,client,tx,amount
+deposit,1,1,100.0
+withdrawal,1,2,30.0
+dispute,1,2,
+chargeback,1,2,
+"#;
+    let parser = CsvParser::new(Cursor::new(input)).expect("failed to create CSV parser");
+    let mut ledger = Ledger::new().with_withdrawal_disputes();
+    for record in parser.flatten() {
+        ledger
+            .process_transaction(&record.transaction())
+            .expect("transaction should be accepted");
+    }
+
+    let account = ledger
+        .get_account(ClientId(1))
+        .expect("client 1 account should exist");
+    assert_eq!(account.available, amount("100"));
+    assert_eq!(account.held, amount("0"));
+    assert!(!account.locked);
+}
+
+#[test]
+fn test_sharded_run_matches_single_ledger() {
+    let input = r#"type,client,tx,amount
+deposit,1,1,100.0
+deposit,2,2,50.0
+deposit,3,3,10.0
+withdrawal,1,4,25.0
+deposit,4,4,5.0
+dispute,2,2,
+dispute,1,2,
+chargeback,2,2,
+deposit,2,5,1.0
+withdrawal,3,6,20.0
+dispute,1,1,
+resolve,1,1,
+"#;
+    let single = process_csv(input);
+
+    let parser = CsvParser::new(Cursor::new(input)).expect("failed to create CSV parser");
+    let mut sharded = ShardedLedger::new(3, Ledger::new);
+    for record in parser.flatten() {
+        sharded
+            .process(record.transaction(), record.line)
+            .expect("shards should process");
+    }
+    let (ledger, rejected) = sharded.finish().expect("shards should merge");
+
+    let accounts: HashMap<_, _> = ledger
+        .accounts()
+        .iter()
+        .map(|(client_id, account)| {
+            (
+                *client_id,
+                (
+                    account.available,
+                    account.held,
+                    account.total(),
+                    account.locked,
+                ),
+            )
+        })
+        .collect();
+    assert_eq!(accounts, single);
+    let mut lines: Vec<_> = rejected.iter().map(|r| r.tag).collect();
+    lines.sort();
+    assert_eq!(lines, vec![6, 8, 10, 11]);
+
+    // Each shard keeps its own books, which still balance once merged
+    let books = ledger.trial_balance().expect("merged books should balance");
+    assert_eq!(
+        books.balance(BookAccount::ExternalFunding, None),
+        amount("-135")
+    );
+    assert_eq!(
+        books.balance(BookAccount::ChargebackLoss, None),
+        amount("50")
+    );
+}
+
+#[test]
+fn test_ndjson_input_matches_csv() {
+    let csv = r#"type,client,tx,amount
+deposit,1,1,100.0
+deposit,2,2,50.0
+withdrawal,1,3,25.0
+dispute,2,2,
+chargeback,2,2,
+"#;
+    let ndjson = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "100.0"}
+{"type": "deposit", "client": 2, "tx": 2, "amount": "50"}
+{"type": "withdrawal", "client": 1, "tx": 3, "amount": "25.0"}
+{"type": "dispute", "client": 2, "tx": 2}
+{"type": "chargeback", "client": 2, "tx": 2, "amount": null}
+"#;
+    let parser = InputFormat::Ndjson
+        .parser(Cursor::new(ndjson))
+        .expect("failed to create NDJSON parser");
+    let mut ledger = Ledger::new();
+    for record in parser {
+        ledger
+            .process_transaction(&record.expect("record should be ok").transaction())
+            .expect("transaction should be accepted");
+    }
+
+    let accounts: HashMap<_, _> = ledger
+        .accounts()
+        .iter()
+        .map(|(client_id, account)| {
+            (
+                *client_id,
+                (
+                    account.available,
+                    account.held,
+                    account.total(),
+                    account.locked,
+                ),
+            )
+        })
+        .collect();
+    assert_eq!(accounts, process_csv(csv));
+}
+
+#[test]
+fn test_output_sorted_by_client() {
+    let input = r#"type,client,tx,amount
+deposit,30,1,1.0
+deposit,2,2,2.0
+deposit,100,3,3.0
+deposit,7,4,4.0
+"#;
+    let output = get_csv_output(input);
+    let clients: Vec<_> = output
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').next().expect("row should have a client"))
+        .collect();
+    assert_eq!(clients, vec!["2", "7", "30", "100"]);
+}
+
+#[test]
+fn test_history_changes_add_up_to_closing_balances() {
+    let input = r#"type,client,tx,amount,destination
+deposit,1,1,100.0,
+deposit,2,2,50.0,
+transfer,1,3,30.0,2
+withdrawal,2,4,60.0,
+dispute,1,3,,
+chargeback,1,3,,
+dispute,2,2,20.0,
+resolve,2,2,,
+withdrawal,1,5,1000.0,
+"#;
+    let parser = CsvParser::new(Cursor::new(input)).expect("failed to create CSV parser");
+    let mut ledger = Ledger::new().with_history();
+    for record in parser.flatten() {
+        let _ = ledger.process_transaction(&record.transaction());
+    }
+
+    for (client_id, account) in ledger.accounts() {
+        let history = ledger.history(*client_id);
+        let (available, held) =
+            history
+                .iter()
+                .fold((Amount::ZERO, Amount::ZERO), |(available, held), entry| {
+                    (available + entry.change.available, held + entry.change.held)
+                });
+        assert_eq!((available, held), (account.available, account.held));
+        let last = history.last().expect("client should have history");
+        assert_eq!(last.balance.available, account.available);
+        assert_eq!(last.balance.held, account.held);
+    }
+
+    let statement = Statement::new(&ledger, ClientId(2), Default::default());
+    assert_eq!(statement.entries.len(), 7);
+    assert_eq!(statement.closing[&None].available, amount("-10"));
+}
+
+#[test]
+fn test_dispute_window_with_timestamp_column() {
+    let input = r#"type,client,tx,amount,timestamp
+deposit,1,1,100.0,1700000000
+deposit,1,2,50.0,1700000000
+dispute,1,1,,1700086400
+dispute,1,2,,1700086401
+"#;
+    let parser = CsvParser::new(Cursor::new(input)).expect("failed to create CSV parser");
+    let mut ledger = Ledger::new().with_dispute_window(24 * 60 * 60);
+    let rejected: Vec<_> = parser
+        .flatten()
+        .filter_map(|record| {
+            ledger
+                .process_transaction(&record.transaction())
+                .err()
+                .map(|reason| (record.line, reason.category()))
+        })
+        .collect();
+
+    assert_eq!(rejected, vec![(5, "dispute_window_closed")]);
+    let account = ledger
+        .get_account(ClientId(1))
+        .expect("client 1 account should exist");
+    assert_eq!(account.held, amount("100"));
+}
+
+#[test]
+fn test_disk_storage_matches_memory_run() {
+    let input = r#"type,client,tx,amount,destination
+deposit,1,1,100.0,
+deposit,2,2,50.0,
+withdrawal,1,3,25.0,
+deposit,1,1,100.0,
+dispute,2,2,,
+chargeback,2,2,,
+transfer,1,4,10.0,2
+dispute,1,4,,
+resolve,1,4,,
+dispute,1,1,,
+"#;
+    let path = std::env::temp_dir().join(format!(
+        "integration-disk-storage-{}.records",
+        std::process::id()
+    ));
+    let storage = DiskStorage::create(&path).expect("failed to create disk storage");
+    let mut disk = Ledger::new()
+        .with_storage(Box::new(storage))
+        .expect("failed to move to disk storage");
+    let mut memory = Ledger::new();
+    let parser = CsvParser::new(Cursor::new(input)).expect("failed to create CSV parser");
+    for record in parser.flatten() {
+        let transaction = record.transaction();
+        assert_eq!(
+            disk.process_transaction(&transaction),
+            memory.process_transaction(&transaction)
+        );
+    }
+
+    assert_eq!(disk.accounts(), memory.accounts());
+    assert_eq!(
+        disk.snapshot().expect("failed to read disk storage"),
+        memory.snapshot().expect("failed to read memory storage")
+    );
+
+    drop(disk);
+    let _ = std::fs::remove_file(&path);
+    let mut ids = path.clone().into_os_string();
+    ids.push(".ids");
+    let _ = std::fs::remove_file(ids);
+}
+
+#[test]
+fn test_overdraft_limit_rows() {
+    let input = r#"type,client,tx,amount,destination
+deposit,1,1,10.0,
+withdrawal,1,2,20.0,
+limit,1,3,50.0,
+withdrawal,1,4,20.0,
+transfer,1,5,40.0,2
+transfer,1,6,30.0,2
+limit,2,7,5.0,
+"#;
+    let output = get_csv_output(input);
+
+    assert!(output.contains("1,-50.0000,0.0000,-50.0000,false,50.0000,0.0000\n"));
+    assert!(output.contains("2,40.0000,0.0000,40.0000,false,5.0000,45.0000\n"));
+}
+
+#[test]
+fn test_fee_schedule_charges_house_account() {
+    let schedule = read_fee_schedule(Cursor::new(
+        r#"{"house_account": 99, "fees": {
+            "deposit": {"percent": "1", "max": "2"},
+            "withdrawal": {"flat": "0.5"},
+            "chargeback": {"flat": "5"}
+        }}"#,
+    ))
+    .expect("schedule should parse");
+    let input = r#"type,client,tx,amount,currency
+deposit,1,1,100.0,
+deposit,1,2,500.0,EUR
+withdrawal,1,3,99.5,
+withdrawal,1,4,10.0,EUR
+dispute,1,1,,
+chargeback,1,1,,
+"#;
+    let mut ledger = Ledger::new().with_fees(schedule).with_event_log();
+    let mut output = Vec::new();
+    let mut report = FeeWriter::new(&mut output).expect("failed to write header");
+    let parser = CsvParser::new(Cursor::new(input)).expect("failed to create CSV parser");
+    for record in parser.flatten() {
+        let _ = ledger.process_transaction(&record.transaction());
+        for event in ledger.drain_events() {
+            if let Some(record) = FeeRecord::from_event(&event) {
+                report.write(&record).expect("failed to write fee");
+            }
+        }
+    }
+    report.flush().expect("failed to flush report");
+    drop(report);
+
+    // The first withdrawal cannot cover its fee; the chargeback fee is always charged
+    assert_eq!(
+        String::from_utf8(output).expect("report should be valid UTF-8"),
+        "client,tx,type,currency,fee\n\
+         1,1,deposit,,1.0000\n\
+         1,2,deposit,EUR,2.0000\n\
+         1,4,withdrawal,EUR,0.5000\n\
+         1,1,chargeback,,5.0000\n"
+    );
+    let account = &ledger.accounts()[&ClientId(1)];
+    assert_eq!(account.available, amount("-6"));
+    let eur = Some("EUR".parse().expect("currency should parse"));
+    assert_eq!(account.balance(eur).available, amount("487.5"));
+    assert_eq!(
+        ledger.accounts()[&ClientId(99)].balance(eur).available,
+        amount("2.5")
+    );
+    assert_eq!(ledger.accounts()[&ClientId(99)].available, amount("6"));
+}
+
+#[test]
+fn test_audit_finds_no_violations_after_mixed_run() {
+    let input = r#"type,client,tx,amount,destination,currency
+deposit,1,1,100.0,,
+deposit,2,2,50.0,,EUR
+transfer,1,3,30.0,2,
+dispute,1,3,,,
+dispute,2,2,20.0,,
+resolve,2,2,5.0,,
+deposit,1,4,10.0,,
+dispute,1,4,,,
+chargeback,1,4,,,
+dispute,1,4,,,
+dispute,1,1,,,
+"#;
+    let mut ledger = Ledger::new();
+    let parser = CsvParser::new(Cursor::new(input)).expect("failed to create CSV parser");
+    for record in parser.flatten() {
+        let _ = ledger.process_transaction(&record.transaction());
+    }
+
+    assert_eq!(ledger.audit().expect("failed to read storage"), Vec::new());
+    let account = ledger
+        .get_account(ClientId(1))
+        .expect("client 1 should exist");
+    assert_eq!(account.held, amount("100"));
+}
+
+#[test]
+fn test_policy_file_changes_dispute_rules() {
+    let policy = read_policy(Cursor::new(
+        r#"{"dispute_withdrawals": true, "redispute_resolved": true}"#,
+    ))
+    .expect("policy should parse");
+    let input = r#"type,client,tx,amount
+deposit,1,1,100.0
+withdrawal,1,2,30.0
+dispute,1,2,
+resolve,1,2,
+dispute,1,2,
+chargeback,1,2,
+dispute,1,2,
+"#;
+    let mut ledger = Ledger::new().with_policy(policy);
+    let parser = CsvParser::new(Cursor::new(input)).expect("failed to create CSV parser");
+    let rejected: Vec<_> = parser
+        .flatten()
+        .filter(|record| ledger.process_transaction(&record.transaction()).is_err())
+        .map(|record| record.line)
+        .collect();
+
+    // Only the dispute after the chargeback is refused
+    assert_eq!(rejected, vec![8]);
+    let account = ledger
+        .get_account(ClientId(1))
+        .expect("client 1 should exist");
+    assert_eq!(account.available, amount("100"));
+    assert!(!account.locked);
+}